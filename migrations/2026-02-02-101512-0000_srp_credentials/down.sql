@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+-- Irreversible: accounts registered or migrated since then only have an SRP verifier,
+-- the password-equivalent key `password_hash` held can't be derived from it, so the
+-- column can't be made NOT NULL again. Restore a backup taken before this migration.
+SIGNAL SQLSTATE '45000'
+    SET MESSAGE_TEXT = 'srp_credentials cannot be reverted, password_hash is gone';
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+ALTER TABLE users
+    MODIFY password_hash BLOB NULL,
+    ADD COLUMN srp_salt     BLOB NULL,
+    ADD COLUMN srp_verifier BLOB NULL;
+
+ALTER TABLE challenges
+    CHANGE nonce client_public BLOB NOT NULL;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- NULL marks accounts still on the unsalted HKDF derivation
+ALTER TABLE users
+    ADD COLUMN kdf_params VARCHAR(255) NULL;
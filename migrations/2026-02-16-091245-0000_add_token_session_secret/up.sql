@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- Tokens issued before this carry no secret and can't be resumed
+DELETE FROM tokens;
+
+ALTER TABLE tokens
+    ADD COLUMN session_secret BLOB NOT NULL;
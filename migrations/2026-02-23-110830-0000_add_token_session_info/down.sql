@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE tokens
+    DROP COLUMN peer_addr,
+    DROP COLUMN client_label,
+    DROP COLUMN last_used_at,
+    DROP COLUMN created_at;
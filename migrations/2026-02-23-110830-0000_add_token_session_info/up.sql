@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE tokens
+    ADD COLUMN created_at   DATETIME     NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ADD COLUMN last_used_at DATETIME     NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ADD COLUMN client_label VARCHAR(255) NOT NULL DEFAULT '',
+    ADD COLUMN peer_addr    VARCHAR(64)  NOT NULL DEFAULT '';
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX tokens_expires_at_idx ON tokens;
+DROP INDEX challenges_expires_at_idx ON challenges;
+
+ALTER TABLE challenges
+    DROP COLUMN expires_at,
+    DROP COLUMN created_at;
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+-- Rows that predate this get expires_at = now and are swept on the next run
+ALTER TABLE challenges
+    ADD COLUMN created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ADD COLUMN expires_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP;
+
+CREATE INDEX challenges_expires_at_idx ON challenges (expires_at);
+CREATE INDEX tokens_expires_at_idx ON tokens (expires_at);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users
+    DROP COLUMN is_admin;
+
+DROP TABLE IF EXISTS login_attempts;
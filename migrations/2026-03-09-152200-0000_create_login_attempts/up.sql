@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE login_attempts
+(
+    throttle_key    VARCHAR(255) PRIMARY KEY,
+    failures        INT UNSIGNED NOT NULL,
+    last_failure_at DATETIME     NOT NULL,
+    locked_until    DATETIME     NULL
+);
+
+ALTER TABLE users
+    ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+-- Credentials cleared by up.sql can't be restored, flagged accounts stay unusable
+ALTER TABLE users
+    DROP INDEX users_login_unique,
+    DROP COLUMN login_key,
+    DROP COLUMN credential_reset;
@@ -0,0 +1,23 @@
+-- Your SQL goes here
+-- Accounts that must get a new credential before they can log in again
+ALTER TABLE users
+    ADD COLUMN credential_reset BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Later duplicates of a login keep it but lose their credential and are flagged for a
+-- reset, the oldest account stays the one that logs in under it.
+UPDATE users u
+    JOIN (SELECT login, MIN(id) AS keep_id
+          FROM users
+          GROUP BY login
+          HAVING COUNT(*) > 1) d ON u.login = d.login AND u.id <> d.keep_id
+SET u.password_hash    = NULL,
+    u.srp_salt         = NULL,
+    u.srp_verifier     = NULL,
+    u.kdf_params       = NULL,
+    u.credential_reset = TRUE;
+
+-- Flagged accounts are left out of the index so the duplicates above can stay, clearing
+-- the flag takes a login no one else holds. Generated, `User` never writes it.
+ALTER TABLE users
+    ADD COLUMN login_key VARCHAR(255) AS (IF(credential_reset, NULL, login)) STORED,
+    ADD UNIQUE INDEX users_login_unique (login_key);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users
+    DROP COLUMN require_2fa;
+
+DROP TABLE IF EXISTS totp_backup_codes;
+DROP TABLE IF EXISTS totp_secrets;
@@ -0,0 +1,24 @@
+-- Your SQL goes here
+CREATE TABLE totp_secrets
+(
+    user_id           BIGINT UNSIGNED PRIMARY KEY,
+    secret_ciphertext BLOB            NOT NULL,
+    secret_nonce      BLOB            NOT NULL,
+    confirmed         BOOLEAN         NOT NULL DEFAULT FALSE,
+    last_used_step    BIGINT UNSIGNED NULL,
+    created_at        DATETIME        NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE TABLE totp_backup_codes
+(
+    id        BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    user_id   BIGINT UNSIGNED NOT NULL,
+    code_hash BLOB            NOT NULL,
+    used_at   DATETIME        NULL,
+    INDEX totp_backup_codes_user_id (user_id),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+ALTER TABLE users
+    ADD COLUMN require_2fa BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users
+    DROP INDEX users_delete_after,
+    DROP COLUMN deleted_at,
+    DROP COLUMN delete_after;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE users
+    ADD COLUMN delete_after DATETIME NULL,
+    ADD COLUMN deleted_at   DATETIME NULL,
+    ADD INDEX users_delete_after (delete_after);
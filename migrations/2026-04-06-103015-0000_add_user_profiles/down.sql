@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users
+    DROP COLUMN created_at,
+    DROP COLUMN avatar_ref,
+    DROP COLUMN bio,
+    DROP COLUMN display_name;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE users
+    ADD COLUMN display_name VARCHAR(255) NULL,
+    ADD COLUMN bio          TEXT         NULL,
+    ADD COLUMN avatar_ref   VARCHAR(255) NULL,
+    ADD COLUMN created_at   DATETIME     NOT NULL DEFAULT CURRENT_TIMESTAMP;
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX users_display_name ON users;
+DROP INDEX users_discoverable_login ON users;
+
+ALTER TABLE users
+    DROP COLUMN discoverable;
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+ALTER TABLE users
+    ADD COLUMN discoverable BOOLEAN NOT NULL DEFAULT TRUE;
+
+-- Prefix searches walk these in login order; substring searches still scan, but only
+-- over discoverable rows.
+CREATE INDEX users_discoverable_login ON users (discoverable, login);
+CREATE INDEX users_display_name ON users (display_name);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE tokens
+    DROP FOREIGN KEY tokens_device_id,
+    DROP COLUMN device_id;
+
+DROP TABLE IF EXISTS devices;
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+CREATE TABLE devices
+(
+    id           BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    user_id      BIGINT UNSIGNED NOT NULL,
+    label        VARCHAR(255)    NOT NULL,
+    platform     VARCHAR(64)     NOT NULL,
+    public_key   BLOB            NOT NULL,
+    created_at   DATETIME        NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_seen_at DATETIME        NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX devices_user_id (user_id),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+ALTER TABLE tokens
+    ADD COLUMN device_id BIGINT UNSIGNED NULL,
+    ADD CONSTRAINT tokens_device_id FOREIGN KEY (device_id) REFERENCES devices(id) ON DELETE CASCADE;
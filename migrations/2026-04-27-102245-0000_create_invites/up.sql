@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE invites
+(
+    id         BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    code_hash  VARBINARY(32)   NOT NULL,
+    created_by BIGINT UNSIGNED NOT NULL,
+    max_uses   INT UNSIGNED    NOT NULL,
+    uses       INT UNSIGNED    NOT NULL DEFAULT 0,
+    expires_at DATETIME        NOT NULL,
+    created_at DATETIME        NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    revoked_at DATETIME        NULL,
+    UNIQUE INDEX invites_code_hash (code_hash),
+    INDEX invites_created_by (created_by),
+    INDEX invites_expires_at (expires_at),
+    FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM tokens;
+
+ALTER TABLE tokens
+    DROP INDEX tokens_token_hash,
+    DROP COLUMN token_hash,
+    ADD COLUMN token BIGINT UNSIGNED NOT NULL UNIQUE;
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+-- Plaintext 64-bit tokens can't be turned into hashes of the new format, everyone logs in again
+DELETE FROM tokens;
+
+ALTER TABLE tokens
+    DROP COLUMN token,
+    ADD COLUMN token_hash VARBINARY(32) NOT NULL,
+    ADD UNIQUE INDEX tokens_token_hash (token_hash);
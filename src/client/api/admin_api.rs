@@ -0,0 +1,65 @@
+use crate::client::api::api_consumer::process_response_oneshot;
+use crate::structures::protolink_stype::{
+    ListLockoutsRequestStruct, ListLockoutsResponse, ProtoLinkSType,
+};
+use std::sync::Arc;
+use tfserver::client::{ClientConnect, ClientRequest, DataRequest, HandlerInfo};
+use tfserver::structures::s_type;
+use tfserver::structures::s_type::StructureType;
+use tfserver::tokio::sync::oneshot;
+use tfserver::tokio::sync::oneshot::Sender;
+use tfserver::tokio_util::bytes::BytesMut;
+
+/// Admin requests, expects a connection from `init_encrypted_client_api`
+pub struct AdminApi {
+    handler_info: HandlerInfo,
+    conn: Arc<ClientConnect>,
+}
+
+impl AdminApi {
+    pub fn new(conn: Arc<ClientConnect>) -> Self {
+        Self {
+            handler_info: HandlerInfo::new_named("ADMIN_HANDLER".to_string()),
+            conn,
+        }
+    }
+
+    async fn build_request(
+        &self,
+        data: Vec<u8>,
+        on_received: Sender<BytesMut>,
+        s_type: Box<dyn StructureType>,
+        id: u64,
+    ) -> ClientRequest {
+        ClientRequest {
+            req: DataRequest {
+                handler_info: self.handler_info.clone(),
+                data,
+                s_type,
+            },
+            consumer: on_received,
+            payload_id: id,
+        }
+    }
+
+    async fn dispatch(&self, data: Vec<u8>, s_type: ProtoLinkSType) -> oneshot::Receiver<BytesMut> {
+        let (tx, rx) = oneshot::channel();
+
+        let req = self.build_request(data, tx, Box::new(s_type), 0).await;
+        self.conn
+            .dispatch_request(req)
+            .await
+            .expect("Failed to dispatch request");
+        rx
+    }
+
+    pub async fn list_lockouts(
+        &self,
+        request: ListLockoutsRequestStruct,
+    ) -> impl std::future::Future<Output = ListLockoutsResponse> {
+        let rx = self
+            .dispatch(s_type::to_vec(&request).unwrap(), ProtoLinkSType::ListLockouts)
+            .await;
+        process_response_oneshot(rx)
+    }
+}
@@ -1,5 +1,11 @@
 use crate::client::api::api_consumer::{process_response_oneshot};
-use crate::structures::protolink_stype::{AuthResponse, ProtoLinkSType, RegisterRequestStruct};
+use crate::structures::protolink_stype::{
+    AuthChallenge, AuthProof, AuthRequestStruct, AuthResponse, ChangePasswordRequestStruct,
+    DeleteAccountRequestStruct,
+    KdfParamsRequestStruct, KdfParamsResponse, ProtoLinkSType, RegisterRequestStruct,
+    TotpConfirmRequestStruct, TotpEnrollRequestStruct, TotpEnrollResponse,
+    TotpVerifyRequestStruct, TwoFactorResponseStruct,
+};
 use std::sync::Arc;
 use tfserver::client::{ClientConnect, ClientRequest, DataRequest, HandlerInfo};
 use tfserver::structures::s_type;
@@ -10,6 +16,8 @@ use tfserver::tokio_util::bytes::BytesMut;
 
 pub struct AuthApi {
     handler_info: HandlerInfo,
+    auth_handler_info: HandlerInfo,
+    two_factor_handler_info: HandlerInfo,
     conn: Arc<ClientConnect>,
 }
 
@@ -17,6 +25,8 @@ impl AuthApi {
     pub fn new(conn: Arc<ClientConnect>) -> Self {
         Self {
             handler_info: HandlerInfo::new_named("REGISTER_HANDLER".to_string()),
+            auth_handler_info: HandlerInfo::new_named("AUTH_HANDLER".to_string()),
+            two_factor_handler_info: HandlerInfo::new_named("TWO_FACTOR_HANDLER".to_string()),
             conn
            ,
         }
@@ -24,6 +34,7 @@ impl AuthApi {
 
     async fn build_request(
         &self,
+        handler_info: &HandlerInfo,
         data: Vec<u8>,
         on_received: Sender<BytesMut>,
         s_type: Box<dyn StructureType>,
@@ -31,7 +42,7 @@ impl AuthApi {
     ) -> ClientRequest {
         let mut res = ClientRequest {
             req: DataRequest {
-                handler_info: self.handler_info.clone(),
+                handler_info: handler_info.clone(),
                 data,
                 s_type,
             },
@@ -50,6 +61,7 @@ impl AuthApi {
 
         let req = self
             .build_request(
+                &self.handler_info,
                 s_type::to_vec(&request).unwrap(),
                 tx,
                 Box::new(ProtoLinkSType::RegisterRequest),
@@ -63,5 +75,187 @@ impl AuthApi {
         process_response_oneshot(rx)
     }
 
+    pub async fn get_kdf_params(
+        &self,
+        request: KdfParamsRequestStruct,
+    ) -> impl std::future::Future<Output = KdfParamsResponse> {
+        let (tx, rx) = oneshot::channel();
+
+        let req = self
+            .build_request(
+                &self.handler_info,
+                s_type::to_vec(&request).unwrap(),
+                tx,
+                Box::new(ProtoLinkSType::KdfParamsRequest),
+                0
+            )
+            .await;
+        self.conn
+            .dispatch_request(req)
+            .await
+            .expect("Failed to dispatch request");
+        process_response_oneshot(rx)
+    }
+
+    /// First step of the login, sends the SRP public ephemeral and gets the challenge back
+    pub async fn login(
+        &self,
+        request: AuthRequestStruct,
+    ) -> impl std::future::Future<Output = AuthChallenge> {
+        let (tx, rx) = oneshot::channel();
+
+        let req = self
+            .build_request(
+                &self.auth_handler_info,
+                s_type::to_vec(&request).unwrap(),
+                tx,
+                Box::new(ProtoLinkSType::AuthRequest),
+                0
+            )
+            .await;
+        self.conn
+            .dispatch_request(req)
+            .await
+            .expect("Failed to dispatch request");
+        process_response_oneshot(rx)
+    }
+
+    /// Second step of the login, the response carries the session token
+    pub async fn login_proof(
+        &self,
+        request: AuthProof,
+    ) -> impl std::future::Future<Output = AuthResponse> {
+        let (tx, rx) = oneshot::channel();
+
+        let req = self
+            .build_request(
+                &self.auth_handler_info,
+                s_type::to_vec(&request).unwrap(),
+                tx,
+                Box::new(ProtoLinkSType::AuthProof),
+                0
+            )
+            .await;
+        self.conn
+            .dispatch_request(req)
+            .await
+            .expect("Failed to dispatch request");
+        process_response_oneshot(rx)
+    }
+
+    /// Replaces the password, the request proves the old one like `login_proof`
+    pub async fn change_password(
+        &self,
+        request: ChangePasswordRequestStruct,
+    ) -> impl std::future::Future<Output = AuthResponse> {
+        let (tx, rx) = oneshot::channel();
+
+        let req = self
+            .build_request(
+                &self.auth_handler_info,
+                s_type::to_vec(&request).unwrap(),
+                tx,
+                Box::new(ProtoLinkSType::ChangePassword),
+                0
+            )
+            .await;
+        self.conn
+            .dispatch_request(req)
+            .await
+            .expect("Failed to dispatch request");
+        process_response_oneshot(rx)
+    }
 
+    /// Schedules the account for deletion, the request proves the password like
+    /// `login_proof`
+    pub async fn delete_account(
+        &self,
+        request: DeleteAccountRequestStruct,
+    ) -> impl std::future::Future<Output = AuthResponse> {
+        let (tx, rx) = oneshot::channel();
+
+        let req = self
+            .build_request(
+                &self.auth_handler_info,
+                s_type::to_vec(&request).unwrap(),
+                tx,
+                Box::new(ProtoLinkSType::DeleteAccount),
+                0
+            )
+            .await;
+        self.conn
+            .dispatch_request(req)
+            .await
+            .expect("Failed to dispatch request");
+        process_response_oneshot(rx)
+    }
+
+    /// Starts authenticator enrollment, from a session or a pending login
+    pub async fn totp_enroll(
+        &self,
+        request: TotpEnrollRequestStruct,
+    ) -> impl std::future::Future<Output = TotpEnrollResponse> {
+        let (tx, rx) = oneshot::channel();
+
+        let req = self
+            .build_request(
+                &self.two_factor_handler_info,
+                s_type::to_vec(&request).unwrap(),
+                tx,
+                Box::new(ProtoLinkSType::TotpEnroll),
+                0
+            )
+            .await;
+        self.conn
+            .dispatch_request(req)
+            .await
+            .expect("Failed to dispatch request");
+        process_response_oneshot(rx)
+    }
+
+    /// Turns 2FA on with a first code from the enrolled authenticator
+    pub async fn totp_confirm(
+        &self,
+        request: TotpConfirmRequestStruct,
+    ) -> impl std::future::Future<Output = TwoFactorResponseStruct> {
+        let (tx, rx) = oneshot::channel();
+
+        let req = self
+            .build_request(
+                &self.two_factor_handler_info,
+                s_type::to_vec(&request).unwrap(),
+                tx,
+                Box::new(ProtoLinkSType::TotpConfirm),
+                0
+            )
+            .await;
+        self.conn
+            .dispatch_request(req)
+            .await
+            .expect("Failed to dispatch request");
+        process_response_oneshot(rx)
+    }
+
+    /// Last step of a login that needs a second factor, the response carries the token
+    pub async fn totp_verify(
+        &self,
+        request: TotpVerifyRequestStruct,
+    ) -> impl std::future::Future<Output = AuthResponse> {
+        let (tx, rx) = oneshot::channel();
+
+        let req = self
+            .build_request(
+                &self.two_factor_handler_info,
+                s_type::to_vec(&request).unwrap(),
+                tx,
+                Box::new(ProtoLinkSType::TotpVerify),
+                0
+            )
+            .await;
+        self.conn
+            .dispatch_request(req)
+            .await
+            .expect("Failed to dispatch request");
+        process_response_oneshot(rx)
+    }
 }
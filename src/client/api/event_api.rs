@@ -0,0 +1,65 @@
+use crate::client::api::api_consumer::process_response_oneshot;
+use crate::structures::protolink_stype::{
+    PollEventsRequestStruct, PollEventsResponse, ProtoLinkSType,
+};
+use std::sync::Arc;
+use tfserver::client::{ClientConnect, ClientRequest, DataRequest, HandlerInfo};
+use tfserver::structures::s_type;
+use tfserver::structures::s_type::StructureType;
+use tfserver::tokio::sync::oneshot;
+use tfserver::tokio::sync::oneshot::Sender;
+use tfserver::tokio_util::bytes::BytesMut;
+
+/// Server events, expects a connection from `init_encrypted_client_api`
+pub struct EventApi {
+    handler_info: HandlerInfo,
+    conn: Arc<ClientConnect>,
+}
+
+impl EventApi {
+    pub fn new(conn: Arc<ClientConnect>) -> Self {
+        Self {
+            handler_info: HandlerInfo::new_named("EVENT_HANDLER".to_string()),
+            conn,
+        }
+    }
+
+    async fn build_request(
+        &self,
+        data: Vec<u8>,
+        on_received: Sender<BytesMut>,
+        s_type: Box<dyn StructureType>,
+        id: u64,
+    ) -> ClientRequest {
+        ClientRequest {
+            req: DataRequest {
+                handler_info: self.handler_info.clone(),
+                data,
+                s_type,
+            },
+            consumer: on_received,
+            payload_id: id,
+        }
+    }
+
+    pub async fn poll_events(
+        &self,
+        request: PollEventsRequestStruct,
+    ) -> impl std::future::Future<Output = PollEventsResponse> {
+        let (tx, rx) = oneshot::channel();
+
+        let req = self
+            .build_request(
+                s_type::to_vec(&request).unwrap(),
+                tx,
+                Box::new(ProtoLinkSType::PollEvents),
+                0,
+            )
+            .await;
+        self.conn
+            .dispatch_request(req)
+            .await
+            .expect("Failed to dispatch request");
+        process_response_oneshot(rx)
+    }
+}
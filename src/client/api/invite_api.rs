@@ -0,0 +1,87 @@
+use crate::client::api::api_consumer::process_response_oneshot;
+use crate::structures::protolink_stype::{
+    CreateInviteRequestStruct, CreateInviteResponse, InviteHandlerResponseStruct,
+    ListInvitesRequestStruct, ListInvitesResponse, ProtoLinkSType, RevokeInviteRequestStruct,
+};
+use std::sync::Arc;
+use tfserver::client::{ClientConnect, ClientRequest, DataRequest, HandlerInfo};
+use tfserver::structures::s_type;
+use tfserver::structures::s_type::StructureType;
+use tfserver::tokio::sync::oneshot;
+use tfserver::tokio::sync::oneshot::Sender;
+use tfserver::tokio_util::bytes::BytesMut;
+
+/// Invites for servers in invite-only mode, expects a connection from
+/// `init_encrypted_client_api`
+pub struct InviteApi {
+    handler_info: HandlerInfo,
+    conn: Arc<ClientConnect>,
+}
+
+impl InviteApi {
+    pub fn new(conn: Arc<ClientConnect>) -> Self {
+        Self {
+            handler_info: HandlerInfo::new_named("INVITE_HANDLER".to_string()),
+            conn,
+        }
+    }
+
+    async fn build_request(
+        &self,
+        data: Vec<u8>,
+        on_received: Sender<BytesMut>,
+        s_type: Box<dyn StructureType>,
+        id: u64,
+    ) -> ClientRequest {
+        ClientRequest {
+            req: DataRequest {
+                handler_info: self.handler_info.clone(),
+                data,
+                s_type,
+            },
+            consumer: on_received,
+            payload_id: id,
+        }
+    }
+
+    async fn dispatch(&self, data: Vec<u8>, s_type: ProtoLinkSType) -> oneshot::Receiver<BytesMut> {
+        let (tx, rx) = oneshot::channel();
+
+        let req = self.build_request(data, tx, Box::new(s_type), 0).await;
+        self.conn
+            .dispatch_request(req)
+            .await
+            .expect("Failed to dispatch request");
+        rx
+    }
+
+    pub async fn create_invite(
+        &self,
+        request: CreateInviteRequestStruct,
+    ) -> impl std::future::Future<Output = CreateInviteResponse> {
+        let rx = self
+            .dispatch(s_type::to_vec(&request).unwrap(), ProtoLinkSType::CreateInvite)
+            .await;
+        process_response_oneshot(rx)
+    }
+
+    pub async fn list_invites(
+        &self,
+        request: ListInvitesRequestStruct,
+    ) -> impl std::future::Future<Output = ListInvitesResponse> {
+        let rx = self
+            .dispatch(s_type::to_vec(&request).unwrap(), ProtoLinkSType::ListInvites)
+            .await;
+        process_response_oneshot(rx)
+    }
+
+    pub async fn revoke_invite(
+        &self,
+        request: RevokeInviteRequestStruct,
+    ) -> impl std::future::Future<Output = InviteHandlerResponseStruct> {
+        let rx = self
+            .dispatch(s_type::to_vec(&request).unwrap(), ProtoLinkSType::RevokeInvite)
+            .await;
+        process_response_oneshot(rx)
+    }
+}
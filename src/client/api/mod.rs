@@ -2,16 +2,27 @@ use std::sync::Arc;
 use hkdf::Hkdf;
 use sha2::Sha256;
 use tfserver::client::ClientConnect;
-use tfserver::codec::length_delimited::LengthDelimitedCodec;
+use crate::client::client_auth_codec::ClientAuthCodec;
 use crate::client::client_encrypted_codec::ClientEncryptedCodec;
+use crate::client::error::ClientError;
+use crate::client::server_pin::ServerPin;
 
 pub mod auth_api;
 pub mod api_consumer;
+pub mod session_api;
+pub mod admin_api;
+pub mod profile_api;
+pub mod event_api;
+pub mod invite_api;
 
+/// Connection to the auth listener, fails with `ClientError::ServerIdentityMismatch` if
+/// the server's identity key isn't the one `pin` expects and with
+/// `ClientError::UnsupportedProtocolVersion` if it speaks none of our versions
 pub async fn init_client_api(
     server_dest: String,
     server_name: String,
-) -> Arc<ClientConnect> {
+    pin: Arc<ServerPin>,
+) -> Result<Arc<ClientConnect>, ClientError> {
     let hk = Hkdf::<Sha256>::new(None, "hello_larry!".as_bytes());
 
     let mut key = [0u8; 32];
@@ -19,6 +30,23 @@ pub async fn init_client_api(
 
   //  let codec = ClientEncryptedCodec::new("la11y".parse().unwrap(), key.to_vec());
 
-    let client = ClientConnect::new(server_name, server_dest, None, LengthDelimitedCodec::new(), None, 16).await.unwrap();
-    Arc::new(client)
+    let codec = ClientAuthCodec::new(pin.clone());
+    ClientConnect::new(server_name, server_dest, None, codec, None, 16)
+        .await
+        .map(Arc::new)
+        .map_err(|_| pin.take_failure().unwrap_or(ClientError::Connection))
+}
+
+/// Connection to the encrypted listener, `codec` decides between a password handshake
+/// and resuming a session and carries the `ServerPin` checked like in `init_client_api`
+pub async fn init_encrypted_client_api(
+    server_dest: String,
+    server_name: String,
+    codec: ClientEncryptedCodec,
+) -> Result<Arc<ClientConnect>, ClientError> {
+    let pin = codec.pin();
+    ClientConnect::new(server_name, server_dest, None, codec, None, 16)
+        .await
+        .map(Arc::new)
+        .map_err(|_| pin.take_failure().unwrap_or(ClientError::Connection))
 }
@@ -0,0 +1,97 @@
+use crate::client::api::api_consumer::process_response_oneshot;
+use crate::structures::protolink_stype::{
+    GetUserProfileRequestStruct, GetUsersBatchRequestStruct, ProtoLinkSType,
+    SearchUsersRequestStruct, SearchUsersResponse, UpdateProfileRequestStruct,
+    UserProfileResponse, UsersBatchResponse,
+};
+use std::sync::Arc;
+use tfserver::client::{ClientConnect, ClientRequest, DataRequest, HandlerInfo};
+use tfserver::structures::s_type;
+use tfserver::structures::s_type::StructureType;
+use tfserver::tokio::sync::oneshot;
+use tfserver::tokio::sync::oneshot::Sender;
+use tfserver::tokio_util::bytes::BytesMut;
+
+/// Profile lookups and updates, expects a connection from `init_encrypted_client_api`
+pub struct ProfileApi {
+    handler_info: HandlerInfo,
+    conn: Arc<ClientConnect>,
+}
+
+impl ProfileApi {
+    pub fn new(conn: Arc<ClientConnect>) -> Self {
+        Self {
+            handler_info: HandlerInfo::new_named("PROFILE_HANDLER".to_string()),
+            conn,
+        }
+    }
+
+    async fn build_request(
+        &self,
+        data: Vec<u8>,
+        on_received: Sender<BytesMut>,
+        s_type: Box<dyn StructureType>,
+        id: u64,
+    ) -> ClientRequest {
+        ClientRequest {
+            req: DataRequest {
+                handler_info: self.handler_info.clone(),
+                data,
+                s_type,
+            },
+            consumer: on_received,
+            payload_id: id,
+        }
+    }
+
+    async fn dispatch(&self, data: Vec<u8>, s_type: ProtoLinkSType) -> oneshot::Receiver<BytesMut> {
+        let (tx, rx) = oneshot::channel();
+
+        let req = self.build_request(data, tx, Box::new(s_type), 0).await;
+        self.conn
+            .dispatch_request(req)
+            .await
+            .expect("Failed to dispatch request");
+        rx
+    }
+
+    pub async fn get_user_profile(
+        &self,
+        request: GetUserProfileRequestStruct,
+    ) -> impl std::future::Future<Output = UserProfileResponse> {
+        let rx = self
+            .dispatch(s_type::to_vec(&request).unwrap(), ProtoLinkSType::GetUserProfile)
+            .await;
+        process_response_oneshot(rx)
+    }
+
+    pub async fn get_users_batch(
+        &self,
+        request: GetUsersBatchRequestStruct,
+    ) -> impl std::future::Future<Output = UsersBatchResponse> {
+        let rx = self
+            .dispatch(s_type::to_vec(&request).unwrap(), ProtoLinkSType::GetUsersBatch)
+            .await;
+        process_response_oneshot(rx)
+    }
+
+    pub async fn update_profile(
+        &self,
+        request: UpdateProfileRequestStruct,
+    ) -> impl std::future::Future<Output = UserProfileResponse> {
+        let rx = self
+            .dispatch(s_type::to_vec(&request).unwrap(), ProtoLinkSType::UpdateProfile)
+            .await;
+        process_response_oneshot(rx)
+    }
+
+    pub async fn search_users(
+        &self,
+        request: SearchUsersRequestStruct,
+    ) -> impl std::future::Future<Output = SearchUsersResponse> {
+        let rx = self
+            .dispatch(s_type::to_vec(&request).unwrap(), ProtoLinkSType::SearchUsers)
+            .await;
+        process_response_oneshot(rx)
+    }
+}
@@ -0,0 +1,129 @@
+use crate::client::api::api_consumer::process_response_oneshot;
+use crate::structures::protolink_stype::{
+    ListDevicesRequestStruct, ListDevicesResponse, ListSessionsRequestStruct, ListSessionsResponse,
+    LogoutAllDevicesRequestStruct, LogoutRequestStruct, ProtoLinkSType, RefreshAccessRequestStruct,
+    RefreshAccessResponse, RevokeDeviceRequestStruct, RevokeSessionRequestStruct,
+    SessionHandlerResponseStruct,
+};
+use std::sync::Arc;
+use tfserver::client::{ClientConnect, ClientRequest, DataRequest, HandlerInfo};
+use tfserver::structures::s_type;
+use tfserver::structures::s_type::StructureType;
+use tfserver::tokio::sync::oneshot;
+use tfserver::tokio::sync::oneshot::Sender;
+use tfserver::tokio_util::bytes::BytesMut;
+
+/// Session management, expects a connection from `init_encrypted_client_api`
+pub struct SessionApi {
+    handler_info: HandlerInfo,
+    conn: Arc<ClientConnect>,
+}
+
+impl SessionApi {
+    pub fn new(conn: Arc<ClientConnect>) -> Self {
+        Self {
+            handler_info: HandlerInfo::new_named("SESSION_HANDLER".to_string()),
+            conn,
+        }
+    }
+
+    async fn build_request(
+        &self,
+        data: Vec<u8>,
+        on_received: Sender<BytesMut>,
+        s_type: Box<dyn StructureType>,
+        id: u64,
+    ) -> ClientRequest {
+        ClientRequest {
+            req: DataRequest {
+                handler_info: self.handler_info.clone(),
+                data,
+                s_type,
+            },
+            consumer: on_received,
+            payload_id: id,
+        }
+    }
+
+    async fn dispatch(&self, data: Vec<u8>, s_type: ProtoLinkSType) -> oneshot::Receiver<BytesMut> {
+        let (tx, rx) = oneshot::channel();
+
+        let req = self.build_request(data, tx, Box::new(s_type), 0).await;
+        self.conn
+            .dispatch_request(req)
+            .await
+            .expect("Failed to dispatch request");
+        rx
+    }
+
+    pub async fn list_sessions(
+        &self,
+        request: ListSessionsRequestStruct,
+    ) -> impl std::future::Future<Output = ListSessionsResponse> {
+        let rx = self
+            .dispatch(s_type::to_vec(&request).unwrap(), ProtoLinkSType::ListSessions)
+            .await;
+        process_response_oneshot(rx)
+    }
+
+    pub async fn logout(
+        &self,
+        request: LogoutRequestStruct,
+    ) -> impl std::future::Future<Output = SessionHandlerResponseStruct> {
+        let rx = self
+            .dispatch(s_type::to_vec(&request).unwrap(), ProtoLinkSType::Logout)
+            .await;
+        process_response_oneshot(rx)
+    }
+
+    pub async fn revoke_session(
+        &self,
+        request: RevokeSessionRequestStruct,
+    ) -> impl std::future::Future<Output = SessionHandlerResponseStruct> {
+        let rx = self
+            .dispatch(s_type::to_vec(&request).unwrap(), ProtoLinkSType::RevokeSession)
+            .await;
+        process_response_oneshot(rx)
+    }
+
+    pub async fn logout_all_devices(
+        &self,
+        request: LogoutAllDevicesRequestStruct,
+    ) -> impl std::future::Future<Output = SessionHandlerResponseStruct> {
+        let rx = self
+            .dispatch(s_type::to_vec(&request).unwrap(), ProtoLinkSType::LogoutAllDevices)
+            .await;
+        process_response_oneshot(rx)
+    }
+
+    pub async fn list_devices(
+        &self,
+        request: ListDevicesRequestStruct,
+    ) -> impl std::future::Future<Output = ListDevicesResponse> {
+        let rx = self
+            .dispatch(s_type::to_vec(&request).unwrap(), ProtoLinkSType::ListDevices)
+            .await;
+        process_response_oneshot(rx)
+    }
+
+    pub async fn revoke_device(
+        &self,
+        request: RevokeDeviceRequestStruct,
+    ) -> impl std::future::Future<Output = SessionHandlerResponseStruct> {
+        let rx = self
+            .dispatch(s_type::to_vec(&request).unwrap(), ProtoLinkSType::RevokeDevice)
+            .await;
+        process_response_oneshot(rx)
+    }
+
+    /// `request` has to carry the session token, not an access token
+    pub async fn refresh_access(
+        &self,
+        request: RefreshAccessRequestStruct,
+    ) -> impl std::future::Future<Output = RefreshAccessResponse> {
+        let rx = self
+            .dispatch(s_type::to_vec(&request).unwrap(), ProtoLinkSType::RefreshAccess)
+            .await;
+        process_response_oneshot(rx)
+    }
+}
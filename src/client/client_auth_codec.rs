@@ -0,0 +1,152 @@
+use crate::client::server_pin::ServerPin;
+use crate::structures::protocol_hello::{Capabilities, ClientHello};
+use crate::util::crypto::codec_util::{
+    derive_auth_traffic_keys, ephemeral_shared_secret, generate_ephemeral_key, CipherSuite,
+    CryptoState, RekeyLimits, Transcript, AUTH_NONCE_LEN, EPHEMERAL_KEY_LEN,
+    NONCE_CLIENT_TO_SERVER, NONCE_SERVER_TO_CLIENT,
+};
+use crate::util::crypto::identity_util::{
+    verify, IDENTITY_KEY_LEN, IDENTITY_SIGNATURE_LEN, SIGN_AUTH_HANDSHAKE,
+};
+use rand::RngCore;
+use std::io;
+use std::sync::Arc;
+use tfserver::async_trait::async_trait;
+use tfserver::codec::codec_trait::TfCodec;
+use tfserver::futures_util::{SinkExt, StreamExt};
+use tfserver::structures::temp_transport::TempTransport;
+use tfserver::structures::transport::Transport;
+use tfserver::tokio_util::bytes::{Bytes, BytesMut};
+use tfserver::tokio_util::codec::{Decoder, Encoder, Framed, LengthDelimitedCodec};
+
+/// Client side of the auth listener codec, see `ServerAuthCodec`. Checks the server's
+/// identity key against `pin` and its signature over the handshake before anything is
+/// sent through the encrypted channel.
+#[derive(Clone)]
+pub struct ClientAuthCodec {
+    pin: Arc<ServerPin>,
+    crypto: CryptoState,
+    base_codec: LengthDelimitedCodec,
+}
+
+impl ClientAuthCodec {
+    pub fn new(pin: Arc<ServerPin>) -> Self {
+        ClientAuthCodec {
+            pin,
+            crypto: CryptoState::Uninitialized,
+            base_codec: LengthDelimitedCodec::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl TfCodec for ClientAuthCodec {
+    async fn initial_setup(&mut self, transport: &mut Transport) -> bool {
+        let tmp_transport = TempTransport::new(transport);
+        let mut framed = Framed::new(tmp_transport, LengthDelimitedCodec::new());
+
+        let hello = ClientHello::ours();
+        let mut client_nonce = [0u8; AUTH_NONCE_LEN];
+        rand::rng().fill_bytes(&mut client_nonce);
+        let (ephemeral_secret, client_ephemeral) = generate_ephemeral_key();
+        let mut client_hello = hello.encode();
+        client_hello.extend_from_slice(&client_nonce);
+        client_hello.extend_from_slice(&client_ephemeral);
+        if framed
+            .send(Bytes::copy_from_slice(&client_hello))
+            .await
+            .is_err()
+        {
+            return false;
+        }
+
+        let hello_reply = match framed.next().await {
+            Some(Ok(res)) => res,
+            _ => return false,
+        };
+        let Some(negotiated) = self.pin.check_hello_reply(&hello, &hello_reply) else {
+            return false;
+        };
+
+        let reply = match framed.next().await {
+            Some(Ok(res))
+                if res.len()
+                    == AUTH_NONCE_LEN
+                        + EPHEMERAL_KEY_LEN
+                        + IDENTITY_KEY_LEN
+                        + IDENTITY_SIGNATURE_LEN =>
+            {
+                res
+            }
+            _ => return false,
+        };
+        let (server_nonce, rest) = reply.split_at(AUTH_NONCE_LEN);
+        let (server_ephemeral, rest) = rest.split_at(EPHEMERAL_KEY_LEN);
+        let (public_key, signature) = rest.split_at(IDENTITY_KEY_LEN);
+
+        let mut transcript = Transcript::new();
+        transcript.add(&client_hello);
+        transcript.add(&hello_reply);
+        transcript.add(server_nonce);
+        transcript.add(server_ephemeral);
+        transcript.add(public_key);
+        let binding = transcript.hash();
+
+        // Signature first, `check` may record the key under `TrustOnFirstUse`
+        if !verify(public_key, SIGN_AUTH_HANDSHAKE, &binding, signature)
+            || !self.pin.check(public_key)
+        {
+            return false;
+        }
+        let Some(shared) = ephemeral_shared_secret(&ephemeral_secret, server_ephemeral) else {
+            return false;
+        };
+        drop(ephemeral_secret);
+
+        let (client_traffic, server_traffic) = derive_auth_traffic_keys(&shared, &binding);
+        self.crypto = CryptoState::established(
+            CipherSuite::Aes256Gcm,
+            client_traffic,
+            NONCE_CLIENT_TO_SERVER,
+            server_traffic,
+            NONCE_SERVER_TO_CLIENT,
+            RekeyLimits::default(),
+            negotiated.capabilities.contains(Capabilities::KEY_UPDATE),
+        );
+        true
+    }
+}
+
+impl Decoder for ClientAuthCodec {
+    type Item = BytesMut;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let CryptoState::Established(traffic) = &mut self.crypto else {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"));
+        };
+
+        // Key updates carry nothing for the caller, keep reading past them
+        while let Some(data) = self.base_codec.decode(src)? {
+            if let Some(decrypted) = traffic.open(&data)? {
+                return Ok(Some(BytesMut::from(Bytes::from(decrypted))));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Encoder<Bytes> for ClientAuthCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: Bytes, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let CryptoState::Established(traffic) = &mut self.crypto else {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"));
+        };
+
+        for encrypted in traffic.seal(&item)? {
+            self.base_codec.encode(Bytes::from(encrypted), dst)?;
+        }
+        Ok(())
+    }
+}
@@ -1,35 +1,149 @@
+use crate::client::server_pin::ServerPin;
+use crate::structures::protocol_hello::{Capabilities, ClientHello};
 use crate::util::crypto::codec_util::*;
-use aes_gcm::aead::Aead;
-use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
-use hkdf::Hkdf;
+use crate::util::crypto::identity_util::{verify, IDENTITY_KEY_LEN, SIGN_ENCRYPTED_HANDSHAKE};
+use crate::util::crypto::srp_util::{
+    compute_client_public, generate_ephemeral_secret, process_server_reply,
+};
+use crate::util::crypto::token_util::hash_session_token;
 use rand::RngCore;
-use sha2::Sha256;
 use std::io;
+use std::sync::Arc;
 use tfserver::async_trait::async_trait;
 use tfserver::codec::codec_trait::TfCodec;
 use tfserver::structures::temp_transport::TempTransport;
 use tfserver::structures::transport::Transport;
-use tfserver::tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tfserver::tokio_util::bytes::{Bytes, BytesMut};
 use tfserver::tokio_util::codec::{Decoder, Encoder, Framed, LengthDelimitedCodec};
-use tfserver::futures_util::{SinkExt, StreamExt};
+use tfserver::futures_util::{Sink, SinkExt, Stream, StreamExt};
+/// What the client proves itself with during the handshake
+#[derive(Clone)]
+enum HandshakeCredential {
+    /// Full SRP exchange with the KDF-derived password key
+    Password { login: String, password_key: Vec<u8> },
+    /// Resumption with a token and the session secret issued at login
+    Session { token: String, session_secret: Vec<u8> },
+}
+
 #[derive(Clone)]
 pub struct ClientEncryptedCodec {
-    login: String,
-    password_hash: Vec<u8>,
+    credential: HandshakeCredential,
+    pin: Arc<ServerPin>,
+    rekey_limits: RekeyLimits,
+    /// Offered in this order, the server picks by its own preference
+    cipher_suites: Vec<CipherSuite>,
     state: CryptoState,
     base_codec: LengthDelimitedCodec,
 }
 
 impl ClientEncryptedCodec {
-    pub fn new(login: String, password_hash: Vec<u8>) -> Self {
+    /// `pin` decides which server identity keys are accepted
+    pub fn new(login: String, password_key: Vec<u8>, pin: Arc<ServerPin>) -> Self {
+        ClientEncryptedCodec {
+            credential: HandshakeCredential::Password {
+                login,
+                password_key,
+            },
+            pin,
+            rekey_limits: RekeyLimits::default(),
+            cipher_suites: CipherSuite::ALL.to_vec(),
+            state: CryptoState::Uninitialized,
+            base_codec: LengthDelimitedCodec::new(),
+        }
+    }
+
+    /// Reconnects with a session from `AuthModel::login` instead of the password key
+    pub fn resume(token: String, session_secret: Vec<u8>, pin: Arc<ServerPin>) -> Self {
         ClientEncryptedCodec {
-            login,
-            password_hash,
+            credential: HandshakeCredential::Session {
+                token,
+                session_secret,
+            },
+            pin,
+            rekey_limits: RekeyLimits::default(),
+            cipher_suites: CipherSuite::ALL.to_vec(),
             state: CryptoState::Uninitialized,
             base_codec: LengthDelimitedCodec::new(),
         }
     }
+
+    pub fn pin(&self) -> Arc<ServerPin> {
+        self.pin.clone()
+    }
+
+    /// When this side moves to its next send key, `RekeyLimits::default()` unless set
+    pub fn with_rekey_limits(mut self, rekey_limits: RekeyLimits) -> Self {
+        self.rekey_limits = rekey_limits;
+        self
+    }
+
+    /// Cipher suites to offer, all of them unless set. A client without AES instructions
+    /// can offer only `CipherSuite::ChaCha20Poly1305`.
+    pub fn with_cipher_suites(mut self, cipher_suites: Vec<CipherSuite>) -> Self {
+        self.cipher_suites = cipher_suites;
+        self
+    }
+
+    /// Client side of the SRP exchange, returns the shared session key once the server
+    /// has proven it knows the verifier
+    async fn srp_handshake<F>(
+        framed: &mut F,
+        transcript: &mut Transcript,
+        login: &str,
+        password_key: &[u8],
+    ) -> Option<Vec<u8>>
+    where
+        F: Stream<Item = io::Result<BytesMut>> + Sink<Bytes, Error = io::Error> + Unpin + Send,
+    {
+        let client_secret = generate_ephemeral_secret();
+        let client_public = compute_client_public(&client_secret);
+        transcript.add(&client_public);
+        framed.send(Bytes::from(client_public)).await.ok()?;
+
+        let salt = match framed.next().await {
+            Some(Ok(res)) => res,
+            _ => return None,
+        };
+        let server_public = match framed.next().await {
+            Some(Ok(res)) => res,
+            _ => return None,
+        };
+        transcript.add(&salt);
+        transcript.add(&server_public);
+
+        let srp_session =
+            process_server_reply(login, password_key, &salt, &client_secret, &server_public)?;
+
+        transcript.add(srp_session.proof());
+        framed
+            .send(Bytes::copy_from_slice(srp_session.proof()))
+            .await
+            .ok()?;
+
+        let server_proof = match framed.next().await {
+            Some(Ok(res)) => res,
+            _ => return None,
+        };
+        transcript.add(&server_proof);
+        if let Err(err) = srp_session.verify_server(&server_proof) {
+            eprintln!("{}", err);
+            return None;
+        }
+
+        Some(srp_session.key().to_vec())
+    }
+
+    /// Checks the server's identity key against the pin and its signature over everything
+    /// the handshake exchanged
+    fn verify_server_identity(&self, transcript: &Transcript, frame: &[u8]) -> bool {
+        if frame.len() <= IDENTITY_KEY_LEN {
+            return false;
+        }
+        let (public_key, signature) = frame.split_at(IDENTITY_KEY_LEN);
+        // Signature first, `check` may record the key under `TrustOnFirstUse`
+        verify(public_key, SIGN_ENCRYPTED_HANDSHAKE, &transcript.hash(), signature)
+            && self.pin.check(public_key)
+    }
 }
 
 #[async_trait]
@@ -38,47 +152,57 @@ impl TfCodec for ClientEncryptedCodec {
         let tmp_transport = TempTransport::new(transport);
         let mut framed = Framed::new(tmp_transport, LengthDelimitedCodec::new());
 
-        let l_bytes_org = self.login.as_bytes();
+        // A resumed session is named by its token's hash, the token is a bearer credential
+        let (mode, identity) = match &self.credential {
+            HandshakeCredential::Password { login, .. } => {
+                (HANDSHAKE_SRP, login.as_bytes().to_vec())
+            }
+            HandshakeCredential::Session { token, .. } => match hash_session_token(token) {
+                Some(token_hash) => (HANDSHAKE_RESUME, token_hash),
+                None => return false,
+            },
+        };
+        let client_hello = ClientHello::ours();
+        let mut hello = client_hello.encode();
+        hello.push(mode);
+        hello.extend_from_slice(&identity);
 
-        if framed.send(Bytes::copy_from_slice(l_bytes_org)).await.is_err() {
+        let mut transcript = Transcript::new();
+        transcript.add(&hello);
+        if framed.send(Bytes::from(hello)).await.is_err() {
             return false;
         }
 
-        let msg = match framed.next().await {
+        let hello_reply = match framed.next().await {
             Some(Ok(res)) => res,
             _ => return false,
         };
-        if msg.len() < 144 {
+        let Some(negotiated) = self.pin.check_hello_reply(&client_hello, &hello_reply) else {
             return false;
-        }
-        let mut nonce = [0u8; 12];
-        nonce.copy_from_slice(&msg[msg.len() - 12..msg.len()]);
-        let ciphertext = &msg[..msg.len() - 12];
-
-        let handshake_key = derive_handshake_key(self.password_hash.as_slice());
-        let cipher = match Aes256Gcm::new_from_slice(&handshake_key) {
-            Ok(res) => res,
-            Err(err) => {
-                eprintln!("{}", err.to_string());
-                return false;
-            }
         };
+        transcript.add(&hello_reply);
 
-        let plaintext = match cipher.decrypt(Nonce::from_slice(&nonce), ciphertext.as_ref()) {
-            Ok(res) => res,
-            Err(err) => {
-                eprintln!("{}", err.to_string());
-                return false;
-            }
+        let base_key = match &self.credential {
+            HandshakeCredential::Password {
+                login,
+                password_key,
+            } => Self::srp_handshake(&mut framed, &mut transcript, login, password_key).await,
+            HandshakeCredential::Session { session_secret, .. } => Some(session_secret.clone()),
         };
-
-        if framed.send(Bytes::from(plaintext)).await.is_err() {
+        let Some(base_key) = base_key else {
             return false;
-        }
+        };
 
+        // The ephemeral keys ride along with the nonces. Both are in the transcript the
+        // server signs, and the shared secret is only used together with `base_key`.
+        let (ephemeral_secret, client_ephemeral) = generate_ephemeral_key();
         let mut client_nonce = [0u8; 12];
-        rand::thread_rng().fill_bytes(&mut client_nonce);
-        if framed.send(Bytes::copy_from_slice(&client_nonce)).await.is_err() {
+        rand::rng().fill_bytes(&mut client_nonce);
+        let mut client_nonce_msg = client_nonce.to_vec();
+        client_nonce_msg.extend_from_slice(&client_ephemeral);
+        client_nonce_msg.extend(self.cipher_suites.iter().map(|suite| suite.id()));
+        transcript.add(&client_nonce_msg);
+        if framed.send(Bytes::from(client_nonce_msg)).await.is_err() {
             return false;
         }
 
@@ -86,20 +210,58 @@ impl TfCodec for ClientEncryptedCodec {
             Some(Ok(res)) => res,
             _ => return false,
         };
-        if server_nonce_msg.len() != 12 {
+        if server_nonce_msg.len() != NONCE_MESSAGE_LEN + 1 {
             return false;
         }
-        let mut server_nonce = [0u8; 12];
-        server_nonce.copy_from_slice(&server_nonce_msg);
+        let server_ephemeral = &server_nonce_msg[12..NONCE_MESSAGE_LEN];
+        let Some(suite) = CipherSuite::from_id(server_nonce_msg[NONCE_MESSAGE_LEN])
+            .filter(|suite| self.cipher_suites.contains(suite))
+        else {
+            return false;
+        };
+        transcript.add(&server_nonce_msg);
+        let Some(ephemeral_shared) = ephemeral_shared_secret(&ephemeral_secret, server_ephemeral)
+        else {
+            return false;
+        };
+        drop(ephemeral_secret);
 
-        let traffic_key =
-            derive_traffic_key(self.password_hash.as_slice(), &client_nonce, &server_nonce);
+        let keys = HandshakeKeys::derive(&base_key, &ephemeral_shared, &transcript.hash());
 
-        self.state = CryptoState::Established {
-            cipher: Aes256Gcm::new_from_slice(&traffic_key).unwrap(),
-            send_ctr: 0,
-            recv_ctr: 0,
+        let server_identity = match framed.next().await {
+            Some(Ok(res)) => res,
+            _ => return false,
+        };
+        if !self.verify_server_identity(&transcript, &server_identity) {
+            return false;
+        }
+        transcript.add(&server_identity);
+
+        // Both sides confirm they derived the same keys from the same transcript before
+        // any traffic is sent with them
+        let server_finished = match framed.next().await {
+            Some(Ok(res)) => res,
+            _ => return false,
         };
+        if !verify_finished(&keys.server_finished, &transcript.hash(), &server_finished) {
+            return false;
+        }
+        transcript.add(&server_finished);
+
+        let client_finished = finished_mac(&keys.client_finished, &transcript.hash());
+        if framed.send(Bytes::copy_from_slice(&client_finished)).await.is_err() {
+            return false;
+        }
+
+        self.state = CryptoState::established(
+            suite,
+            keys.client_traffic,
+            NONCE_CLIENT_TO_SERVER,
+            keys.server_traffic,
+            NONCE_SERVER_TO_CLIENT,
+            self.rekey_limits,
+            negotiated.capabilities.contains(Capabilities::KEY_UPDATE),
+        );
         true
     }
 }
@@ -109,25 +271,17 @@ impl Decoder for ClientEncryptedCodec {
     type Error = io::Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let CryptoState::Established {
-            cipher, recv_ctr, ..
-        } = &mut self.state
-        else {
+        let CryptoState::Established(traffic) = &mut self.state else {
             return Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"));
         };
 
-        let Some(data) = self.base_codec.decode(src)? else {
-            return Ok(None);
-        };
-
-        let nonce = make_nonce(*recv_ctr, NONCE_SERVER_TO_CLIENT);
-        *recv_ctr += 1;
-
-        let decrypted = cipher
-            .decrypt(&nonce, data.as_ref())
-            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"))?;
-
-        Ok(Some(BytesMut::from(Bytes::from(decrypted))))
+        // Key updates carry nothing for the caller, keep reading past them
+        while let Some(data) = self.base_codec.decode(src)? {
+            if let Some(decrypted) = traffic.open(&data)? {
+                return Ok(Some(BytesMut::from(Bytes::from(decrypted))));
+            }
+        }
+        Ok(None)
     }
 }
 
@@ -135,20 +289,13 @@ impl Encoder<Bytes> for ClientEncryptedCodec {
     type Error = io::Error;
 
     fn encode(&mut self, item: Bytes, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let CryptoState::Established {
-            cipher, send_ctr, ..
-        } = &mut self.state
-        else {
+        let CryptoState::Established(traffic) = &mut self.state else {
             return Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"));
         };
 
-        let nonce = make_nonce(*send_ctr, NONCE_CLIENT_TO_SERVER);
-        *send_ctr += 1;
-
-        let encrypted = cipher
-            .encrypt(&nonce, item.as_ref())
-            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"))?;
-
-        self.base_codec.encode(Bytes::from(encrypted), dst)
+        for encrypted in traffic.seal(&item)? {
+            self.base_codec.encode(Bytes::from(encrypted), dst)?;
+        }
+        Ok(())
     }
 }
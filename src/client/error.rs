@@ -0,0 +1,153 @@
+use crate::structures::error_code::ErrorCode;
+use crate::structures::protolink_stype::{
+    AuthChallenge, AuthResponse, ChatHandlerResponseStruct, CreateInviteResponse,
+    InviteHandlerResponseStruct, ListDevicesResponse, ListInvitesResponse, ListLockoutsResponse,
+    ListSessionsResponse, PollEventsResponse, RefreshAccessResponse, SearchUsersResponse,
+    SessionHandlerResponseStruct, TotpEnrollResponse, TwoFactorResponseStruct,
+    UserProfileResponse, UsersBatchResponse,
+};
+use chrono::{DateTime, Utc};
+use std::fmt;
+
+/// Failure of a client operation, either reported by the server or detected locally
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientError {
+    /// The server refused the request, `message` is its English fallback text
+    Server { code: ErrorCode, message: String },
+    /// Too many failed logins, retry after `until`
+    LockedOut { until: DateTime<Utc> },
+    /// The password could not be turned into a key with the account's KDF parameters
+    KeyDerivation,
+    /// The server sent something that doesn't fit the protocol
+    Protocol,
+    /// The server failed to prove it knows the verifier
+    ServerProof,
+    /// The server signed its handshake with a key other than the pinned one, see
+    /// `ServerPin`. Fingerprints as produced by `identity_util::fingerprint`.
+    ServerIdentityMismatch { expected: String, found: String },
+    /// The server speaks none of this client's protocol versions, both ranges inclusive
+    UnsupportedProtocolVersion { ours: (u16, u16), server: (u16, u16) },
+    /// Connecting or the handshake failed for any other reason
+    Connection,
+}
+
+impl ClientError {
+    /// Code to branch on, None for errors that never reached the server
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            ClientError::Server { code, .. } => Some(*code),
+            ClientError::LockedOut { .. } => Some(ErrorCode::LockedOut),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Server { message, .. } => f.write_str(message),
+            ClientError::LockedOut { until } => write!(f, "locked until {}", until),
+            ClientError::KeyDerivation => f.write_str("password key derivation failed"),
+            ClientError::Protocol => f.write_str("unexpected response from server"),
+            ClientError::ServerProof => f.write_str("server proof mismatch"),
+            ClientError::ServerIdentityMismatch { expected, found } => write!(
+                f,
+                "server identity mismatch, expected {} but found {}",
+                expected, found
+            ),
+            ClientError::UnsupportedProtocolVersion { ours, server } => write!(
+                f,
+                "no common protocol version, this client speaks {} to {} and the server {} to {}",
+                ours.0, ours.1, server.0, server.1
+            ),
+            ClientError::Connection => f.write_str("connection failed"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+/// Turns a response into `Err` when it carries an error code
+pub trait IntoResult: Sized {
+    fn into_result(self) -> Result<Self, ClientError>;
+}
+
+fn server_error(code: ErrorCode, message: String) -> ClientError {
+    let message = if message.is_empty() {
+        code.description().to_string()
+    } else {
+        message
+    };
+    ClientError::Server { code, message }
+}
+
+fn locked_out(locked_until: i64) -> ClientError {
+    match DateTime::from_timestamp(locked_until, 0) {
+        Some(until) => ClientError::LockedOut { until },
+        None => ClientError::Protocol,
+    }
+}
+
+impl IntoResult for AuthResponse {
+    fn into_result(self) -> Result<Self, ClientError> {
+        match self.error {
+            None if self.success => Ok(self),
+            Some(ErrorCode::LockedOut) => Err(locked_out(self.locked_until)),
+            Some(code) => Err(server_error(code, self.message)),
+            None => Err(ClientError::Protocol),
+        }
+    }
+}
+
+impl IntoResult for TotpEnrollResponse {
+    fn into_result(self) -> Result<Self, ClientError> {
+        match self.error {
+            None if self.success => Ok(self),
+            Some(ErrorCode::LockedOut) => Err(locked_out(self.locked_until)),
+            Some(code) => Err(server_error(code, self.message)),
+            None => Err(ClientError::Protocol),
+        }
+    }
+}
+
+impl IntoResult for AuthChallenge {
+    fn into_result(self) -> Result<Self, ClientError> {
+        match self.error {
+            Some(ErrorCode::LockedOut) => Err(locked_out(self.locked_until)),
+            Some(code) => Err(server_error(code, String::new())),
+            None if self.server_public.is_empty() => Err(ClientError::Protocol),
+            None => Ok(self),
+        }
+    }
+}
+
+macro_rules! impl_into_result {
+    ($($response:ty),*) => {
+        $(impl IntoResult for $response {
+            fn into_result(self) -> Result<Self, ClientError> {
+                match self.error {
+                    None if self.success => Ok(self),
+                    Some(code) => Err(server_error(code, self.message)),
+                    None => Err(ClientError::Protocol),
+                }
+            }
+        })*
+    };
+}
+
+impl_into_result!(
+    ChatHandlerResponseStruct,
+    ListSessionsResponse,
+    SessionHandlerResponseStruct,
+    ListLockoutsResponse,
+    TwoFactorResponseStruct,
+    UserProfileResponse,
+    UsersBatchResponse,
+    PollEventsResponse,
+    SearchUsersResponse,
+    ListDevicesResponse,
+    CreateInviteResponse,
+    ListInvitesResponse,
+    InviteHandlerResponseStruct,
+    RefreshAccessResponse
+);
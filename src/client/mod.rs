@@ -1,3 +1,6 @@
 pub mod api;
+pub mod client_auth_codec;
 pub mod client_encrypted_codec;
+pub mod error;
 pub mod model;
+pub mod server_pin;
@@ -1,10 +1,91 @@
 use crate::client::api::auth_api::AuthApi;
-use hkdf::Hkdf;
-use sha2::{Digest, Sha256};
+use crate::client::error::{ClientError, IntoResult};
+use chrono::{DateTime, Utc};
 use std::sync::Arc;
 use tfserver::client::ClientConnect;
 
-use crate::structures::protolink_stype::RegisterRequestStruct;
+use crate::structures::protolink_stype::{
+    AuthChallenge, AuthProof, AuthRequestStruct, AuthResponse, ChangePasswordRequestStruct,
+    CredentialUpdateStruct, DeleteAccountRequestStruct, DeviceRegistrationStruct,
+    KdfParamsRequestStruct, RefreshAccessResponse,
+    RegisterRequestStruct, TotpConfirmRequestStruct, TotpEnrollRequestStruct,
+    TotpVerifyRequestStruct,
+};
+use crate::util::crypto::codec_util::derive_session_secret;
+use crate::util::crypto::kdf_util::{derive_password_key, KdfParams};
+use crate::util::crypto::srp_util::{
+    compute_client_public, compute_verifier, credential_update_mac, generate_ephemeral_secret,
+    generate_salt, process_server_reply,
+};
+use sha2::Sha256;
+use srp::client::SrpClientVerifier;
+
+/// Session token issued by a successful login
+#[derive(Clone)]
+pub struct SessionToken {
+    pub value: String,
+    pub expires_at: DateTime<Utc>,
+    /// Keys resumed connections, see `ClientEncryptedCodec::resume`
+    pub secret: Vec<u8>,
+    /// Device the token is bound to, None if the login didn't register one
+    pub device_id: Option<u64>,
+    /// Short-lived token the server checks without a database lookup, renewed with
+    /// `SessionApi::refresh_access` and `SessionToken::set_access`
+    pub access_token: String,
+    pub access_expires_at: DateTime<Utc>,
+}
+
+impl SessionToken {
+    /// What authenticated requests carry: the access token while it is valid, the session
+    /// token once it expired
+    pub fn request_token(&self) -> String {
+        if self.access_expires_at > Utc::now() {
+            self.access_token.clone()
+        } else {
+            self.value.clone()
+        }
+    }
+
+    /// Keeps the access token of a successful `RefreshAccess`
+    pub fn set_access(&mut self, res: &RefreshAccessResponse) -> Result<(), ClientError> {
+        self.access_expires_at =
+            DateTime::from_timestamp(res.expires_at, 0).ok_or(ClientError::Protocol)?;
+        self.access_token = res.access_token.clone();
+        Ok(())
+    }
+}
+
+/// Login that proved the password but still needs a TOTP or backup code,
+/// finished with `AuthModel::complete_login`
+#[derive(Clone)]
+pub struct PendingLogin {
+    pub id: u64,
+    /// The account requires 2FA and has no authenticator yet, see `AuthModel::enroll_totp_pending`
+    pub enrollment_required: bool,
+    pub expires_at: DateTime<Utc>,
+    secret: Vec<u8>,
+}
+
+pub enum LoginOutcome {
+    Complete(SessionToken),
+    SecondFactor(PendingLogin),
+}
+
+/// What the user needs to set up an authenticator, shown once
+pub struct TotpEnrollment {
+    /// Base32, for typing into the app by hand
+    pub secret: String,
+    pub otpauth_uri: String,
+    pub backup_codes: Vec<String>,
+}
+
+/// Freshly derived credential: the password key and the SRP salt and verifier made from it
+struct NewCredential {
+    kdf_params: KdfParams,
+    key: [u8; 32],
+    salt: Vec<u8>,
+    verifier: Vec<u8>,
+}
 
 pub struct AuthModel {
     auth_api: AuthApi,
@@ -17,18 +98,289 @@ impl AuthModel {
         }
     }
 
-    pub async fn create_user(&self, username: &str, login: &str, password: &str) -> bool {
-        let hk = Hkdf::<Sha256>::new(None, password.as_bytes());
+    /// Fetches the account's KDF parameters and derives the password key,
+    /// which is what `ClientEncryptedCodec` expects.
+    ///
+    /// Parameters outside the bounds the server enforces at registration are refused with
+    /// `ClientError::Protocol`, a server could otherwise exhaust the client's memory or
+    /// make the key cheap to brute force.
+    pub async fn derive_key(
+        &self,
+        login: &str,
+        password: &str,
+    ) -> Result<(KdfParams, [u8; 32]), ClientError> {
+        let request = KdfParamsRequestStruct::new(login.to_string());
+        let res = self.auth_api.get_kdf_params(request).await.await;
+        if !res.params.is_acceptable() && !res.params.is_legacy() {
+            return Err(ClientError::Protocol);
+        }
+        let key = derive_password_key(password, &res.params).ok_or(ClientError::KeyDerivation)?;
+        Ok((res.params, key))
+    }
+
+    /// Fresh Argon2id parameters, SRP salt and verifier for `password`
+    fn new_credential(login: &str, password: &str) -> Option<NewCredential> {
+        let kdf_params = KdfParams::generate();
+        let key = derive_password_key(password, &kdf_params)?;
+        let salt = generate_salt();
+        let verifier = compute_verifier(login, &key, &salt);
+        Some(NewCredential {
+            kdf_params,
+            key,
+            salt,
+            verifier,
+        })
+    }
+
+    /// `credential` as a replacement authorised by the SRP session `session_key`
+    fn credential_update(
+        credential: &NewCredential,
+        session_key: &[u8],
+    ) -> Option<CredentialUpdateStruct> {
+        let mac = credential_update_mac(
+            session_key,
+            &credential.kdf_params.to_db_string()?,
+            &credential.salt,
+            &credential.verifier,
+        );
+        Some(CredentialUpdateStruct {
+            kdf_params: credential.kdf_params.clone(),
+            srp_salt: credential.salt.clone(),
+            srp_verifier: credential.verifier.clone(),
+            mac,
+        })
+    }
+
+    fn session_token(res: &AuthResponse, secret: Vec<u8>) -> Result<SessionToken, ClientError> {
+        let session = res.session.as_ref().ok_or(ClientError::Protocol)?;
+        Ok(SessionToken {
+            value: session.value.clone(),
+            expires_at: DateTime::from_timestamp(session.expires_at, 0)
+                .ok_or(ClientError::Protocol)?,
+            secret,
+            device_id: session.device_id,
+            access_token: session.access_token.clone(),
+            access_expires_at: DateTime::from_timestamp(session.access_expires_at, 0)
+                .ok_or(ClientError::Protocol)?,
+        })
+    }
 
-        let mut key = [0u8; 32];
-        hk.expand(b"aes-256-key", &mut key).unwrap();
+    /// Gets a challenge for `login` and answers it with the password key `key`
+    async fn srp_exchange(
+        &self,
+        login: &str,
+        key: &[u8],
+    ) -> Result<(AuthChallenge, SrpClientVerifier<Sha256>), ClientError> {
+        let client_secret = generate_ephemeral_secret();
+        let request = AuthRequestStruct::new(login.to_string(), compute_client_public(&client_secret));
+        let challenge = self.auth_api.login(request).await.await.into_result()?;
+
+        let srp_session = process_server_reply(
+            login,
+            key,
+            &challenge.salt,
+            &client_secret,
+            &challenge.server_public,
+        )
+        .ok_or(ClientError::Protocol)?;
+        Ok((challenge, srp_session))
+    }
+
+    /// Registers a new account. Refusals carry the code of the registration rule the
+    /// request broke, or `ErrorCode::LoginTaken`. Servers that only accept invited users
+    /// need `invite_code` and answer `ErrorCode::InviteRequired` without one.
+    pub async fn create_user(
+        &self,
+        username: &str,
+        login: &str,
+        password: &str,
+        invite_code: Option<&str>,
+    ) -> Result<(), ClientError> {
+        let credential =
+            Self::new_credential(login, password).ok_or(ClientError::KeyDerivation)?;
 
         let mut request = RegisterRequestStruct::new();
 
         request.name = username.to_string();
         request.login = login.to_string();
-        request.password_hash_sha256_hkdf = key.to_vec();
-        let res = self.auth_api.create_user(request).await.await;
-        res.success
+        request.srp_verifier = credential.verifier;
+        request.srp_salt = credential.salt;
+        request.kdf_params = credential.kdf_params;
+        request.invite_code = invite_code.map(str::to_string);
+        self.auth_api.create_user(request).await.await.into_result()?;
+        Ok(())
+    }
+
+    /// Runs the whole login: KDF parameters, SRP challenge and proof. Accounts still on the
+    /// legacy derivation are moved to Argon2id along the way.
+    ///
+    /// `client_label` names this client in the session list. Passing `device` registers
+    /// this install on its first login and binds the token to it, so it can be revoked
+    /// on its own with `RevokeDevice`.
+    ///
+    /// Accounts with 2FA get `LoginOutcome::SecondFactor` instead of a token.
+    ///
+    /// Fails with `ClientError::LockedOut` after too many wrong passwords and with
+    /// `ClientError::ServerProof` if the server could not prove it knows the verifier.
+    pub async fn login(
+        &self,
+        login: &str,
+        password: &str,
+        client_label: &str,
+        device: Option<DeviceRegistrationStruct>,
+    ) -> Result<LoginOutcome, ClientError> {
+        let (kdf_params, key) = self.derive_key(login, password).await?;
+        let (challenge, srp_session) = self.srp_exchange(login, &key).await?;
+
+        let mut proof = AuthProof::new(
+            challenge.challenge_id,
+            login.to_string(),
+            srp_session.proof().to_vec(),
+        );
+        proof.client_label = client_label.to_string();
+        proof.device = device;
+        if kdf_params.is_legacy() {
+            proof.credential_update = Self::new_credential(login, password)
+                .and_then(|credential| Self::credential_update(&credential, srp_session.key()));
+        }
+
+        let res = self.auth_api.login_proof(proof).await.await.into_result()?;
+        if srp_session.verify_server(&res.server_proof).is_err() {
+            return Err(ClientError::ServerProof);
+        }
+
+        let secret = derive_session_secret(srp_session.key()).to_vec();
+        if let Some(pending) = &res.pending_login {
+            return Ok(LoginOutcome::SecondFactor(PendingLogin {
+                id: pending.id,
+                enrollment_required: pending.enrollment_required,
+                expires_at: DateTime::from_timestamp(pending.expires_at, 0)
+                    .ok_or(ClientError::Protocol)?,
+                secret,
+            }));
+        }
+        Ok(LoginOutcome::Complete(Self::session_token(&res, secret)?))
+    }
+
+    /// Deletes the account `session` is logged in to after the server's grace period,
+    /// returns when it will be purged. Every session ends now; logging in again before
+    /// the purge cancels the deletion.
+    pub async fn delete_account(
+        &self,
+        session: &SessionToken,
+        login: &str,
+        password: &str,
+    ) -> Result<DateTime<Utc>, ClientError> {
+        let (_, key) = self.derive_key(login, password).await?;
+        let (challenge, srp_session) = self.srp_exchange(login, &key).await?;
+
+        let request = DeleteAccountRequestStruct::new(
+            session.value.clone(),
+            challenge.challenge_id,
+            srp_session.proof().to_vec(),
+        );
+        let res = self.auth_api.delete_account(request).await.await.into_result()?;
+        if srp_session.verify_server(&res.server_proof).is_err() {
+            return Err(ClientError::ServerProof);
+        }
+        DateTime::from_timestamp(res.expires_at, 0).ok_or(ClientError::Protocol)
+    }
+
+    /// Finishes a login with a code from the authenticator or a backup code. Wrong codes
+    /// count towards the lockout like wrong passwords.
+    pub async fn complete_login(
+        &self,
+        pending: &PendingLogin,
+        code: &str,
+    ) -> Result<SessionToken, ClientError> {
+        let request = TotpVerifyRequestStruct::new(pending.id, code.to_string());
+        let res = self.auth_api.totp_verify(request).await.await.into_result()?;
+        Self::session_token(&res, pending.secret.clone())
+    }
+
+    /// Enrolls an authenticator for an account whose login is waiting on one. The login
+    /// is then finished with `complete_login` and a code from the new authenticator.
+    pub async fn enroll_totp_pending(
+        &self,
+        pending: &PendingLogin,
+    ) -> Result<TotpEnrollment, ClientError> {
+        self.enroll_totp_with(TotpEnrollRequestStruct::pending(pending.id))
+            .await
+    }
+
+    /// Enrolls an authenticator for the account of `session`, proving `password` again so
+    /// a stolen token alone can't. 2FA is only on once `confirm_totp` got a code from it.
+    pub async fn enroll_totp(
+        &self,
+        session: &SessionToken,
+        login: &str,
+        password: &str,
+    ) -> Result<TotpEnrollment, ClientError> {
+        let (_, key) = self.derive_key(login, password).await?;
+        let (challenge, srp_session) = self.srp_exchange(login, &key).await?;
+
+        let request = TotpEnrollRequestStruct::new(
+            session.value.clone(),
+            challenge.challenge_id,
+            srp_session.proof().to_vec(),
+        );
+        self.enroll_totp_with(request).await
+    }
+
+    async fn enroll_totp_with(
+        &self,
+        request: TotpEnrollRequestStruct,
+    ) -> Result<TotpEnrollment, ClientError> {
+        let res = self.auth_api.totp_enroll(request).await.await.into_result()?;
+        Ok(TotpEnrollment {
+            secret: res.secret,
+            otpauth_uri: res.otpauth_uri,
+            backup_codes: res.backup_codes,
+        })
+    }
+
+    pub async fn confirm_totp(
+        &self,
+        session: &SessionToken,
+        code: &str,
+    ) -> Result<(), ClientError> {
+        let request = TotpConfirmRequestStruct::new(session.value.clone(), code.to_string());
+        self.auth_api.totp_confirm(request).await.await.into_result()?;
+        Ok(())
+    }
+
+    /// Changes the password of the account `session` is logged in to. Every other session
+    /// of the account ends and encrypted connections have to handshake again, `session`
+    /// itself stays valid.
+    ///
+    /// Returns the new KDF parameters and password key, callers keep them in place of
+    /// what `derive_key` gave for the old password.
+    pub async fn change_password(
+        &self,
+        session: &SessionToken,
+        login: &str,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<(KdfParams, [u8; 32]), ClientError> {
+        let (_, old_key) = self.derive_key(login, old_password).await?;
+        let (challenge, srp_session) = self.srp_exchange(login, &old_key).await?;
+
+        let credential =
+            Self::new_credential(login, new_password).ok_or(ClientError::KeyDerivation)?;
+        let update = Self::credential_update(&credential, srp_session.key())
+            .ok_or(ClientError::KeyDerivation)?;
+
+        let request = ChangePasswordRequestStruct::new(
+            session.value.clone(),
+            challenge.challenge_id,
+            srp_session.proof().to_vec(),
+            update,
+        );
+        let res = self.auth_api.change_password(request).await.await.into_result()?;
+        if srp_session.verify_server(&res.server_proof).is_err() {
+            return Err(ClientError::ServerProof);
+        }
+
+        Ok((credential.kdf_params, credential.key))
     }
 }
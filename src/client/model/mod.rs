@@ -1 +1,2 @@
-pub mod auth_model;
\ No newline at end of file
+pub mod auth_model;
+pub mod profile_model;
\ No newline at end of file
@@ -0,0 +1,186 @@
+use crate::client::api::event_api::EventApi;
+use crate::client::api::profile_api::ProfileApi;
+use crate::client::error::{ClientError, IntoResult};
+use crate::client::model::auth_model::SessionToken;
+use crate::structures::protolink_stype::{
+    GetUserProfileRequestStruct, GetUsersBatchRequestStruct, PollEventsRequestStruct,
+    SearchMode, SearchUsersRequestStruct, ServerEventStruct, UpdateProfileRequestStruct,
+    UserProfileStruct,
+};
+use chrono::{DateTime, Utc};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tfserver::client::ClientConnect;
+
+/// Ids the server takes per `GetUsersBatch`
+const MAX_BATCH_SIZE: usize = 100;
+
+/// A user as shown to other users
+#[derive(Clone, Debug, PartialEq)]
+pub struct UserProfile {
+    pub user_id: u64,
+    pub login: String,
+    pub display_name: String,
+    pub bio: String,
+    pub avatar_ref: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// The account was deleted, every other field is empty
+    pub deleted: bool,
+}
+
+impl TryFrom<UserProfileStruct> for UserProfile {
+    type Error = ClientError;
+
+    fn try_from(profile: UserProfileStruct) -> Result<Self, Self::Error> {
+        Ok(Self {
+            user_id: profile.user_id,
+            login: profile.login,
+            display_name: profile.display_name,
+            bio: profile.bio,
+            avatar_ref: profile.avatar_ref,
+            created_at: DateTime::from_timestamp(profile.created_at, 0)
+                .ok_or(ClientError::Protocol)?,
+            deleted: profile.deleted,
+        })
+    }
+}
+
+/// Changes for `ProfileModel::update_profile`. None leaves a field as it is, an empty
+/// string clears it.
+#[derive(Clone, Debug, Default)]
+pub struct ProfileUpdate {
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub avatar_ref: Option<String>,
+    /// Whether others can find the user with `ProfileModel::search`
+    pub discoverable: Option<bool>,
+}
+
+/// One page of `ProfileModel::search`
+#[derive(Clone, Debug)]
+pub struct SearchPage {
+    pub profiles: Vec<UserProfile>,
+    /// Pass to the next `search` call, None on the last page
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub enum ProfileEvent {
+    /// The user or someone sharing a chat with them changed their profile
+    ProfileUpdated(UserProfile),
+    /// Events were lost, to a server restart or too many of them queued. Profiles shown
+    /// may be stale and should be fetched again.
+    Missed,
+}
+
+pub struct ProfileModel {
+    profile_api: ProfileApi,
+    event_api: EventApi,
+    /// Cursor the last `poll_events` got back
+    last_event: AtomicU64,
+}
+
+impl ProfileModel {
+    /// `conn` has to come from `init_encrypted_client_api`
+    pub fn new(conn: Arc<ClientConnect>) -> Self {
+        Self {
+            profile_api: ProfileApi::new(conn.clone()),
+            event_api: EventApi::new(conn),
+            last_event: AtomicU64::new(0),
+        }
+    }
+
+    pub async fn profile(
+        &self,
+        session: &SessionToken,
+        user_id: u64,
+    ) -> Result<UserProfile, ClientError> {
+        let request = GetUserProfileRequestStruct::new(session.request_token(), user_id);
+        let res = self.profile_api.get_user_profile(request).await.await.into_result()?;
+        res.profile.ok_or(ClientError::Protocol)?.try_into()
+    }
+
+    /// Profiles of `user_ids` in as few requests as the server allows. Ids of users that
+    /// don't exist are left out.
+    pub async fn profiles(
+        &self,
+        session: &SessionToken,
+        user_ids: &[u64],
+    ) -> Result<Vec<UserProfile>, ClientError> {
+        let mut profiles = Vec::with_capacity(user_ids.len());
+        for chunk in user_ids.chunks(MAX_BATCH_SIZE) {
+            let request = GetUsersBatchRequestStruct::new(session.request_token(), chunk.to_vec());
+            let res = self.profile_api.get_users_batch(request).await.await.into_result()?;
+            for profile in res.profiles {
+                profiles.push(profile.try_into()?);
+            }
+        }
+        Ok(profiles)
+    }
+
+    /// Applies `update` to the own profile and returns the result. Everyone sharing a
+    /// chat with the user gets a `ProfileEvent::ProfileUpdated`.
+    pub async fn update_profile(
+        &self,
+        session: &SessionToken,
+        update: ProfileUpdate,
+    ) -> Result<UserProfile, ClientError> {
+        let mut request = UpdateProfileRequestStruct::new(session.request_token());
+        request.display_name = update.display_name;
+        request.bio = update.bio;
+        request.avatar_ref = update.avatar_ref;
+        request.discoverable = update.discoverable;
+        let res = self.profile_api.update_profile(request).await.await.into_result()?;
+        res.profile.ok_or(ClientError::Protocol)?.try_into()
+    }
+
+    /// Searches the user directory. `limit` 0 leaves the page size to the server, which
+    /// caps it either way.
+    pub async fn search(
+        &self,
+        session: &SessionToken,
+        query: &str,
+        mode: SearchMode,
+        cursor: Option<String>,
+        limit: u32,
+    ) -> Result<SearchPage, ClientError> {
+        let mut request =
+            SearchUsersRequestStruct::new(session.request_token(), query.to_string(), mode);
+        request.cursor = cursor;
+        request.limit = limit;
+        let res = self.profile_api.search_users(request).await.await.into_result()?;
+        Ok(SearchPage {
+            profiles: res
+                .profiles
+                .into_iter()
+                .map(UserProfile::try_from)
+                .collect::<Result<_, _>>()?,
+            next_cursor: res.next_cursor,
+        })
+    }
+
+    /// Events since the previous call, oldest first. The server doesn't push them, call
+    /// this periodically.
+    pub async fn poll_events(
+        &self,
+        session: &SessionToken,
+    ) -> Result<Vec<ProfileEvent>, ClientError> {
+        let after = self.last_event.load(Ordering::SeqCst);
+        let request = PollEventsRequestStruct::new(session.request_token(), after);
+        let res = self.event_api.poll_events(request).await.await.into_result()?;
+
+        let mut events = Vec::with_capacity(res.events.len() + 1);
+        if res.missed {
+            events.push(ProfileEvent::Missed);
+        }
+        for event in res.events {
+            match event.event {
+                ServerEventStruct::ProfileUpdated(profile) => {
+                    events.push(ProfileEvent::ProfileUpdated(profile.try_into()?))
+                }
+            }
+        }
+        self.last_event.fetch_max(res.cursor, Ordering::SeqCst);
+        Ok(events)
+    }
+}
@@ -0,0 +1,102 @@
+use crate::client::error::ClientError;
+use crate::structures::protocol_hello::{ClientHello, HelloReply, Negotiated};
+use crate::util::crypto::identity_util::fingerprint;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Which server identity keys a client accepts
+#[derive(Clone, Debug)]
+pub enum PinPolicy {
+    /// Only the key with this fingerprint, as printed by `server keygen`
+    Fingerprint(String),
+    /// The first key a server presents is recorded in `known_servers`, one
+    /// `<server name> <fingerprint>` per line, and only that key is accepted from then on
+    TrustOnFirstUse { known_servers: PathBuf },
+}
+
+/// Checks the identity key a server signs its handshake with. Shared by every codec
+/// connecting to the same server, it keeps why the last handshake failed so connecting can
+/// fail with `ClientError::ServerIdentityMismatch` or a rejected hello instead of a bare
+/// handshake failure.
+pub struct ServerPin {
+    server_name: String,
+    policy: PinPolicy,
+    failure: Mutex<Option<ClientError>>,
+}
+
+impl ServerPin {
+    pub fn new(server_name: String, policy: PinPolicy) -> Self {
+        Self {
+            server_name,
+            policy,
+            failure: Mutex::new(None),
+        }
+    }
+
+    fn known_fingerprint(&self, known_servers: &PathBuf) -> Option<String> {
+        let known = fs::read_to_string(known_servers).ok()?;
+        known.lines().find_map(|line| {
+            let (name, fingerprint) = line.trim().split_once(' ')?;
+            (name == self.server_name).then(|| fingerprint.trim().to_string())
+        })
+    }
+
+    fn remember(&self, known_servers: &PathBuf, fingerprint: &str) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(known_servers)?;
+        writeln!(file, "{} {}", self.server_name, fingerprint)
+    }
+
+    /// Whether `public_key` is the server's pinned identity key. A server seen for the
+    /// first time under `TrustOnFirstUse` is accepted and recorded, so callers check the
+    /// handshake signature made with the key before asking.
+    pub fn check(&self, public_key: &[u8]) -> bool {
+        let found = fingerprint(public_key);
+        let expected = match &self.policy {
+            PinPolicy::Fingerprint(expected) => expected.clone(),
+            PinPolicy::TrustOnFirstUse { known_servers } => {
+                match self.known_fingerprint(known_servers) {
+                    Some(expected) => expected,
+                    None => return self.remember(known_servers, &found).is_ok(),
+                }
+            }
+        };
+        if expected == found {
+            return true;
+        }
+        self.record_failure(ClientError::ServerIdentityMismatch { expected, found });
+        false
+    }
+
+    /// The server's answer to `hello`, None if it refused or answered with something
+    /// `hello` didn't offer. A refusal is kept for `take_failure`.
+    pub fn check_hello_reply(&self, hello: &ClientHello, reply: &[u8]) -> Option<Negotiated> {
+        match HelloReply::parse(reply, hello)? {
+            HelloReply::Accepted(negotiated) => Some(negotiated),
+            HelloReply::UnsupportedVersion {
+                min_version,
+                max_version,
+            } => {
+                self.record_failure(ClientError::UnsupportedProtocolVersion {
+                    ours: (hello.min_version, hello.max_version),
+                    server: (min_version, max_version),
+                });
+                None
+            }
+        }
+    }
+
+    /// Keeps why a handshake with this server failed, for `take_failure`
+    pub fn record_failure(&self, error: ClientError) {
+        *self.failure.lock().unwrap() = Some(error);
+    }
+
+    /// What failed the last handshake, None if it failed for no reason the codecs know
+    pub fn take_failure(&self) -> Option<ClientError> {
+        self.failure.lock().unwrap().take()
+    }
+}
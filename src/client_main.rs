@@ -1,7 +1,9 @@
 use tfserver::tokio;
 use crate::client::api::auth_api::AuthApi;
 use crate::client::api::init_client_api;
-use crate::client::model::auth_model::AuthModel;
+use crate::client::model::auth_model::{AuthModel, LoginOutcome};
+use crate::client::server_pin::{PinPolicy, ServerPin};
+use std::sync::Arc;
 use crate::structures::protolink_stype::{RegisterRequestStruct};
 
 pub mod client;
@@ -14,8 +16,36 @@ pub struct TestReceiver;
 
 #[tokio::main]
 async fn main() {
-    let conn = init_client_api( "127.0.0.1:8080".to_string(), "127.0.0.1".to_string()).await;
+    let pin = Arc::new(ServerPin::new(
+        "127.0.0.1".to_string(),
+        PinPolicy::TrustOnFirstUse {
+            known_servers: "known_servers".into(),
+        },
+    ));
+    let conn = match init_client_api("127.0.0.1:8080".to_string(), "127.0.0.1".to_string(), pin)
+        .await
+    {
+        Ok(conn) => conn,
+        Err(err) => {
+            println!("Connecting failed: {}", err);
+            return;
+        }
+    };
     let auth_model = AuthModel::new(conn);
-    auth_model.create_user("hello", "hell_nah3asdfdasdfsfsdgf2", "hello3sd2_dfgslarry!").await;
-    
+    if let Err(reason) = auth_model.create_user("hello", "hell_nah3asdfdasdfsfsdgf2", "hello3sd2_dfgslarry!", None).await {
+        println!("Registration failed: {}", reason);
+    }
+    let outcome = auth_model
+        .login("hell_nah3asdfdasdfsfsdgf2", "hello3sd2_dfgslarry!", "cli", None)
+        .await;
+    match outcome {
+        Ok(LoginOutcome::Complete(token)) => {
+            println!("{} expires at {}", token.value, token.expires_at)
+        }
+        Ok(LoginOutcome::SecondFactor(pending)) => {
+            println!("Login needs a TOTP code before {}", pending.expires_at)
+        }
+        Err(err) => println!("Login failed: {}", err),
+    }
+
 }
@@ -0,0 +1,108 @@
+use crate::util::crypto::token_util::{
+    access_token_key_id, sign_access_token, verify_access_token, AccessClaims,
+};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use std::env;
+
+const DEFAULT_ACCESS_TOKEN_TTL_SECS: i64 = 300;
+const MAX_KEY_ID_LEN: usize = 16;
+
+struct AccessKey {
+    id: String,
+    secret: [u8; 32],
+}
+
+/// Keys access tokens are signed and checked with. Access tokens are verified without a
+/// database round trip, ending a session stops them through `SessionRegistry` instead.
+/// The registry doesn't survive a restart, so `ttl` bounds how long a revoked token may
+/// work after one, and `session_guard::authorize_session` keeps the requests that end
+/// sessions or change credentials on session tokens.
+///
+/// To rotate, put a new key first in `ACCESS_TOKEN_KEYS` and keep the old one after it
+/// for at least `ttl`. Tokens signed with the old key stay valid meanwhile and sessions
+/// are unaffected, they only need their next access token from the new key.
+pub struct AccessKeyring {
+    /// The first key signs, all of them verify
+    keys: Vec<AccessKey>,
+    pub ttl: Duration,
+}
+
+fn parse_key(entry: &str) -> Option<AccessKey> {
+    let (id, secret) = entry.trim().split_once(':')?;
+    if id.is_empty()
+        || id.len() > MAX_KEY_ID_LEN
+        || !id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    {
+        return None;
+    }
+    let secret = STANDARD.decode(secret.trim()).ok()?;
+    Some(AccessKey {
+        id: id.to_string(),
+        secret: <[u8; 32]>::try_from(secret).ok()?,
+    })
+}
+
+impl AccessKeyring {
+    /// Reads `ACCESS_TOKEN_KEYS`, a comma separated list of `<id>:<32 bytes of base64>`
+    /// with the signing key first, and `ACCESS_TOKEN_TTL_SECS`. Without valid keys a
+    /// random one is used and access tokens don't survive a restart.
+    pub fn from_env() -> Self {
+        let keys = env::var("ACCESS_TOKEN_KEYS")
+            .ok()
+            .and_then(|v| v.split(',').map(parse_key).collect::<Option<Vec<_>>>())
+            .filter(|keys| !keys.is_empty());
+        let keys = keys.unwrap_or_else(|| {
+            eprintln!("ACCESS_TOKEN_KEYS is missing or invalid, using a random signing key");
+            let mut secret = [0u8; 32];
+            rand::rng().fill_bytes(&mut secret);
+            vec![AccessKey {
+                id: "local".to_string(),
+                secret,
+            }]
+        });
+        let ttl = env::var("ACCESS_TOKEN_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_ACCESS_TOKEN_TTL_SECS);
+        Self {
+            keys,
+            ttl: Duration::seconds(ttl),
+        }
+    }
+
+    /// Signs an access token for `session_id` that expires after `ttl`, or with the
+    /// session if that comes first
+    pub fn issue(
+        &self,
+        user_id: u64,
+        session_id: u64,
+        device_id: Option<u64>,
+        session_expires_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> (String, DateTime<Utc>) {
+        let expires_at = (now + self.ttl).min(session_expires_at);
+        let claims = AccessClaims {
+            user_id,
+            session_id,
+            device_id,
+            issued_at: now.timestamp_millis(),
+            expires_at: expires_at.timestamp(),
+        };
+        let key = &self.keys[0];
+        (sign_access_token(&key.id, &key.secret, claims), expires_at)
+    }
+
+    /// Claims of an access token signed with any key of the ring, None if it is forged,
+    /// signed with a retired key or expired
+    pub fn verify(&self, token: &str, now: DateTime<Utc>) -> Option<AccessClaims> {
+        let key_id = access_token_key_id(token)?;
+        let key = self.keys.iter().find(|key| key.id == key_id)?;
+        verify_access_token(token, &key.secret).filter(|claims| claims.expires_at > now.timestamp())
+    }
+}
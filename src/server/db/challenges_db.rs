@@ -1,9 +1,13 @@
+use chrono::{NaiveDateTime, Utc};
 use diesel::{AsChangeset, Connection, ExpressionMethods, Insertable, MysqlConnection, QueryDsl, Queryable, RunQueryDsl, Selectable};
 use diesel::r2d2::{ConnectionManager, PooledConnection};
 use diesel::result::Error as DieselError;
+use crate::server::db::last_insert_id;
 
 pub struct ChallengesDb;
 
+/// Pending SRP exchange: `challenge` is the server public `B` sent to the client,
+/// `solution` the matching server secret `b` and `client_public` the client's `A`.
 #[derive(Queryable, Selectable, Insertable, PartialEq, AsChangeset, Debug)]
 #[diesel(table_name = crate::server::db::schema::challenges)]
 #[diesel(check_for_backend(diesel::mysql::Mysql))]
@@ -12,34 +16,92 @@ pub struct Challenge {
     pub challenge: Vec<u8>,
     pub solution: Vec<u8>,
     pub user_id: u64,
-    pub nonce: Vec<u8>,
+    pub client_public: Vec<u8>,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
 }
 
 impl ChallengesDb {
+    /// Stores a pending exchange and returns its id. Returns None without storing anything
+    /// when the user already has `max_outstanding` unexpired challenges, older ones are
+    /// never dropped so a flood of requests can't cancel a login in progress.
     pub fn create_challenge(
         conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
         user_id: u64,
         challenge_blob: Vec<u8>,
         solution_blob: Vec<u8>,
-        nonce: Vec<u8>
-    ) -> Result<usize, DieselError> {
+        client_public: Vec<u8>,
+        expires_at: NaiveDateTime,
+        max_outstanding: usize,
+    ) -> Result<Option<u64>, DieselError> {
         use crate::server::db::schema::challenges;
 
+        let now = Utc::now().naive_utc();
         let row = Challenge {
             id: 0,
             challenge: challenge_blob,
             solution: solution_blob,
             user_id,
-            nonce
+            client_public,
+            created_at: now,
+            expires_at,
         };
 
         conn.transaction(|conn| {
+            let outstanding = challenges::table
+                .filter(challenges::user_id.eq(user_id))
+                .filter(challenges::expires_at.gt(now))
+                .select(challenges::id)
+                .for_update()
+                .load::<u64>(conn)?;
+            if outstanding.len() >= max_outstanding {
+                return Ok(None);
+            }
+
             diesel::insert_into(challenges::table)
                 .values(&row)
-                .execute(conn)
+                .execute(conn)?;
+            diesel::select(last_insert_id()).get_result::<u64>(conn).map(Some)
+        })
+    }
+
+    /// Takes an unexpired challenge out of the table so it can be answered only once.
+    /// The row is gone afterwards whether or not the answer turns out to be right.
+    pub fn consume_challenge(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        challenge_id: u64,
+        uid: u64,
+        now: NaiveDateTime,
+    ) -> Result<Challenge, DieselError> {
+        use crate::server::db::schema::challenges::dsl::*;
+
+        conn.transaction(|conn| {
+            let row = challenges
+                .filter(id.eq(challenge_id))
+                .filter(user_id.eq(uid))
+                .filter(expires_at.gt(now))
+                .for_update()
+                .first::<Challenge>(conn)?;
+
+            if diesel::delete(challenges.filter(id.eq(row.id))).execute(conn)? != 1 {
+                return Err(DieselError::NotFound);
+            }
+            Ok(row)
         })
     }
 
+    /// Id of the most recently created challenge still stored, 0 if there is none
+    pub fn latest_id(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+    ) -> Result<u64, DieselError> {
+        use crate::server::db::schema::challenges::dsl::*;
+
+        challenges
+            .select(diesel::dsl::max(id))
+            .first::<Option<u64>>(conn)
+            .map(Option::unwrap_or_default)
+    }
+
     pub fn find_challenge_by_id(
         conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
         challenge_id: u64,
@@ -58,6 +120,20 @@ impl ChallengesDb {
         challenges.filter(user_id.eq(uid)).load::<Challenge>(conn)
     }
 
+    /// Like `find_challenges_by_user_id` but without the ones past `expires_at`
+    pub fn find_active_challenges_by_user_id(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        uid: u64,
+        now: NaiveDateTime,
+    ) -> Result<Vec<Challenge>, DieselError> {
+        use crate::server::db::schema::challenges::dsl::*;
+
+        challenges
+            .filter(user_id.eq(uid))
+            .filter(expires_at.gt(now))
+            .load::<Challenge>(conn)
+    }
+
     pub fn update_challenge(
         conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
         row: &Challenge,
@@ -86,4 +162,13 @@ impl ChallengesDb {
 
         diesel::delete(challenges.filter(user_id.eq(uid))).execute(conn)
     }
-}
\ No newline at end of file
+
+    pub fn delete_expired(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        now: NaiveDateTime,
+    ) -> Result<usize, DieselError> {
+        use crate::server::db::schema::challenges::dsl::*;
+
+        diesel::delete(challenges.filter(expires_at.lt(now))).execute(conn)
+    }
+}
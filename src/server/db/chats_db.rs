@@ -0,0 +1,41 @@
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::result::Error as DieselError;
+use diesel::{CombineDsl, ExpressionMethods, MysqlConnection, QueryDsl, RunQueryDsl};
+
+pub struct ChatsDb;
+
+impl ChatsDb {
+    /// Everyone who shares at least one chat with `uid`, as member or owner, `uid` itself
+    /// included if it is in any chat
+    pub fn find_chat_peers(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        uid: u64,
+    ) -> Result<Vec<u64>, DieselError> {
+        use crate::server::db::schema::{chats, chats_users};
+
+        let chat_ids = chats_users::table
+            .filter(chats_users::user_id.eq(uid))
+            .select(chats_users::chat_id)
+            .union(
+                chats::table
+                    .filter(chats::owner_id.eq(uid))
+                    .select(chats::id),
+            )
+            .load::<u64>(conn)?;
+
+        let mut peers = chats_users::table
+            .filter(chats_users::chat_id.eq_any(&chat_ids))
+            .select(chats_users::user_id)
+            .distinct()
+            .load::<u64>(conn)?;
+        peers.extend(
+            chats::table
+                .filter(chats::id.eq_any(&chat_ids))
+                .select(chats::owner_id)
+                .load::<u64>(conn)?,
+        );
+        peers.sort_unstable();
+        peers.dedup();
+        Ok(peers)
+    }
+}
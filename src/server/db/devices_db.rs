@@ -0,0 +1,122 @@
+use chrono::NaiveDateTime;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::result::Error as DieselError;
+use diesel::{
+    AsChangeset, Connection, ExpressionMethods, Insertable, MysqlConnection, OptionalExtension,
+    QueryDsl, Queryable, RunQueryDsl, Selectable,
+};
+
+use crate::server::db::last_insert_id;
+
+pub struct DevicesDb;
+
+/// A client install of a user, identified by the public key it generated
+#[derive(Queryable, Selectable, Insertable, PartialEq, AsChangeset, Debug)]
+#[diesel(table_name = crate::server::db::schema::devices)]
+#[diesel(check_for_backend(diesel::mysql::Mysql))]
+pub struct DeviceRow {
+    pub id: u64,
+    pub user_id: u64,
+    pub label: String,
+    pub platform: String,
+    pub public_key: Vec<u8>,
+    pub created_at: NaiveDateTime,
+    pub last_seen_at: NaiveDateTime,
+}
+
+impl DevicesDb {
+    /// Returns the id of `uid`'s device with `key`, registering it first if it's new.
+    /// A known device gets the label and platform it logged in with now.
+    pub fn register_device(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        uid: u64,
+        device_label: &str,
+        device_platform: &str,
+        key: &[u8],
+        now: NaiveDateTime,
+    ) -> Result<u64, DieselError> {
+        use crate::server::db::schema::devices::dsl::*;
+
+        conn.transaction(|conn| {
+            let known = devices
+                .filter(user_id.eq(uid))
+                .filter(public_key.eq(key))
+                .select(id)
+                .for_update()
+                .first::<u64>(conn)
+                .optional()?;
+
+            if let Some(device_id) = known {
+                diesel::update(devices.filter(id.eq(device_id)))
+                    .set((
+                        label.eq(device_label),
+                        platform.eq(device_platform),
+                        last_seen_at.eq(now),
+                    ))
+                    .execute(conn)?;
+                return Ok(device_id);
+            }
+
+            let row = DeviceRow {
+                id: 0,
+                user_id: uid,
+                label: device_label.to_string(),
+                platform: device_platform.to_string(),
+                public_key: key.to_vec(),
+                created_at: now,
+                last_seen_at: now,
+            };
+            diesel::insert_into(devices).values(&row).execute(conn)?;
+            diesel::select(last_insert_id()).get_result::<u64>(conn)
+        })
+    }
+
+    pub fn find_devices_by_user_id(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        uid: u64,
+    ) -> Result<Vec<DeviceRow>, DieselError> {
+        use crate::server::db::schema::devices::dsl::*;
+
+        devices
+            .filter(user_id.eq(uid))
+            .order(last_seen_at.desc())
+            .load::<DeviceRow>(conn)
+    }
+
+    pub fn touch_device(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        device_id: u64,
+        now: NaiveDateTime,
+    ) -> Result<usize, DieselError> {
+        use crate::server::db::schema::devices::dsl::*;
+
+        diesel::update(devices.filter(id.eq(device_id)))
+            .set(last_seen_at.eq(now))
+            .execute(conn)
+    }
+
+    /// Deletes one of `uid`'s devices and every token issued to it, 0 rows means it
+    /// doesn't exist or isn't theirs
+    pub fn delete_device_for_user(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        uid: u64,
+        device_id: u64,
+    ) -> Result<usize, DieselError> {
+        use crate::server::db::schema::{devices, tokens};
+
+        conn.transaction(|conn| {
+            diesel::delete(
+                tokens::table
+                    .filter(tokens::device_id.eq(device_id))
+                    .filter(tokens::user_id.eq(uid)),
+            )
+            .execute(conn)?;
+            diesel::delete(
+                devices::table
+                    .filter(devices::id.eq(device_id))
+                    .filter(devices::user_id.eq(uid)),
+            )
+            .execute(conn)
+        })
+    }
+}
@@ -0,0 +1,119 @@
+use chrono::NaiveDateTime;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::result::Error as DieselError;
+use diesel::{
+    AsChangeset, Connection, ExpressionMethods, Insertable, MysqlConnection, OptionalExtension,
+    QueryDsl, Queryable, RunQueryDsl, Selectable,
+};
+
+use crate::server::db::last_insert_id;
+
+pub struct InvitesDb;
+
+/// Invite code for `InvitePolicy::mode` `InviteOnly`, see `invite_util`
+#[derive(Queryable, Selectable, Insertable, PartialEq, AsChangeset, Debug)]
+#[diesel(table_name = crate::server::db::schema::invites)]
+#[diesel(check_for_backend(diesel::mysql::Mysql))]
+pub struct InviteRow {
+    pub id: u64,
+    /// `invite_util::hash_invite_code`
+    pub code_hash: Vec<u8>,
+    pub created_by: u64,
+    pub max_uses: u32,
+    pub uses: u32,
+    pub expires_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+impl InvitesDb {
+    /// Returns the id of the new invite
+    pub fn create_invite(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        row: &InviteRow,
+    ) -> Result<u64, DieselError> {
+        use crate::server::db::schema::invites;
+
+        conn.transaction(|conn| {
+            diesel::insert_into(invites::table).values(row).execute(conn)?;
+            diesel::select(last_insert_id()).get_result::<u64>(conn)
+        })
+    }
+
+    /// Invites created by `uid`, or every invite if None
+    pub fn find_invites(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        uid: Option<u64>,
+    ) -> Result<Vec<InviteRow>, DieselError> {
+        use crate::server::db::schema::invites::dsl::*;
+
+        let mut query = invites.order(created_at.desc()).into_boxed();
+        if let Some(uid) = uid {
+            query = query.filter(created_by.eq(uid));
+        }
+        query.load::<InviteRow>(conn)
+    }
+
+    /// Revokes an invite created by `uid`, or by anyone if None. 0 rows means it doesn't
+    /// exist, isn't theirs or was already revoked.
+    pub fn revoke_invite(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        invite_id: u64,
+        uid: Option<u64>,
+        now: NaiveDateTime,
+    ) -> Result<usize, DieselError> {
+        use crate::server::db::schema::invites::dsl::*;
+
+        let mut target = invites
+            .filter(id.eq(invite_id))
+            .filter(revoked_at.is_null())
+            .into_boxed();
+        if let Some(uid) = uid {
+            target = target.filter(created_by.eq(uid));
+        }
+        // Boxed queries can't be updated, resolve the row first
+        let Some(invite) = target.select(id).first::<u64>(conn).optional()? else {
+            return Ok(0);
+        };
+        diesel::update(invites.filter(id.eq(invite)))
+            .set(revoked_at.eq(Some(now)))
+            .execute(conn)
+    }
+
+    /// Uses up one redemption of the invite with `hash`, `NotFound` if there is no live
+    /// invite with uses left. Meant to run in the transaction that creates the account,
+    /// so a failed registration doesn't consume it.
+    pub fn redeem(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        hash: &[u8],
+        now: NaiveDateTime,
+    ) -> Result<u64, DieselError> {
+        use crate::server::db::schema::invites::dsl::*;
+
+        conn.transaction(|conn| {
+            let (invite, used, limit) = invites
+                .filter(code_hash.eq(hash))
+                .filter(revoked_at.is_null())
+                .filter(expires_at.gt(now))
+                .select((id, uses, max_uses))
+                .for_update()
+                .first::<(u64, u32, u32)>(conn)?;
+            if used >= limit {
+                return Err(DieselError::NotFound);
+            }
+            diesel::update(invites.filter(id.eq(invite)))
+                .set(uses.eq(used + 1))
+                .execute(conn)?;
+            Ok(invite)
+        })
+    }
+
+    pub fn delete_expired(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        now: NaiveDateTime,
+    ) -> Result<usize, DieselError> {
+        use crate::server::db::schema::invites::dsl::*;
+
+        diesel::delete(invites.filter(expires_at.lt(now))).execute(conn)
+    }
+}
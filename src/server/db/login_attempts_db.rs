@@ -0,0 +1,70 @@
+use chrono::NaiveDateTime;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::result::Error as DieselError;
+use diesel::{
+    AsChangeset, BoolExpressionMethods, ExpressionMethods, Insertable, MysqlConnection, QueryDsl,
+    Queryable, RunQueryDsl, Selectable,
+};
+
+pub struct LoginAttemptsDb;
+
+/// Persisted state of `LoginThrottle`, `throttle_key` is `ThrottleKey::to_string`
+#[derive(Queryable, Selectable, Insertable, PartialEq, AsChangeset, Debug)]
+#[diesel(table_name = crate::server::db::schema::login_attempts)]
+#[diesel(check_for_backend(diesel::mysql::Mysql))]
+pub struct LoginAttemptRow {
+    pub throttle_key: String,
+    pub failures: u32,
+    pub last_failure_at: NaiveDateTime,
+    pub locked_until: Option<NaiveDateTime>,
+}
+
+impl LoginAttemptsDb {
+    pub fn upsert_attempt(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        row: &LoginAttemptRow,
+    ) -> Result<usize, DieselError> {
+        use crate::server::db::schema::login_attempts;
+
+        diesel::replace_into(login_attempts::table)
+            .values(row)
+            .execute(conn)
+    }
+
+    /// Rows that still matter at `now`: locked, or failed recently enough to count
+    pub fn find_recent_attempts(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        failed_after: NaiveDateTime,
+        now: NaiveDateTime,
+    ) -> Result<Vec<LoginAttemptRow>, DieselError> {
+        use crate::server::db::schema::login_attempts::dsl::*;
+
+        login_attempts
+            .filter(last_failure_at.gt(failed_after).or(locked_until.gt(now)))
+            .load::<LoginAttemptRow>(conn)
+    }
+
+    pub fn delete_attempt(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        key: &str,
+    ) -> Result<usize, DieselError> {
+        use crate::server::db::schema::login_attempts::dsl::*;
+
+        diesel::delete(login_attempts.filter(throttle_key.eq(key))).execute(conn)
+    }
+
+    pub fn delete_stale(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        failed_before: NaiveDateTime,
+        now: NaiveDateTime,
+    ) -> Result<usize, DieselError> {
+        use crate::server::db::schema::login_attempts::dsl::*;
+
+        diesel::delete(
+            login_attempts
+                .filter(last_failure_at.lt(failed_before))
+                .filter(locked_until.is_null().or(locked_until.lt(now))),
+        )
+        .execute(conn)
+    }
+}
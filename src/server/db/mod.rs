@@ -1,4 +1,31 @@
 pub mod schema;
 pub mod users_db;
 pub mod challenges_db;
-pub mod tokens_db;
\ No newline at end of file
+pub mod tokens_db;
+pub mod login_attempts_db;
+pub mod totp_db;
+pub mod chats_db;
+pub mod devices_db;
+pub mod invites_db;
+
+use crate::structures::error_code::ErrorCode;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+diesel::define_sql_function! {
+    /// MySQL's `LAST_INSERT_ID()`, valid on the connection that did the insert
+    fn last_insert_id() -> diesel::sql_types::Unsigned<diesel::sql_types::BigInt>;
+}
+
+/// Generic code for a failed query. Handlers that know what was looked up or inserted
+/// map `NotFound` and `UniqueViolation` to something more specific first.
+impl From<&DieselError> for ErrorCode {
+    fn from(err: &DieselError) -> Self {
+        match err {
+            DieselError::NotFound => ErrorCode::NotFound,
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                ErrorCode::AlreadyExists
+            }
+            _ => ErrorCode::Database,
+        }
+    }
+}
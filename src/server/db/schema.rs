@@ -6,7 +6,9 @@ diesel::table! {
         challenge -> Blob,
         solution -> Blob,
         user_id -> Unsigned<Bigint>,
-        nonce -> Blob,
+        client_public -> Blob,
+        created_at -> Datetime,
+        expires_at -> Datetime,
     }
 }
 
@@ -34,12 +36,79 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    devices (id) {
+        id -> Unsigned<Bigint>,
+        user_id -> Unsigned<Bigint>,
+        #[max_length = 255]
+        label -> Varchar,
+        #[max_length = 64]
+        platform -> Varchar,
+        public_key -> Blob,
+        created_at -> Datetime,
+        last_seen_at -> Datetime,
+    }
+}
+
+diesel::table! {
+    invites (id) {
+        id -> Unsigned<Bigint>,
+        #[max_length = 32]
+        code_hash -> Varbinary,
+        created_by -> Unsigned<Bigint>,
+        max_uses -> Unsigned<Integer>,
+        uses -> Unsigned<Integer>,
+        expires_at -> Datetime,
+        created_at -> Datetime,
+        revoked_at -> Nullable<Datetime>,
+    }
+}
+
+diesel::table! {
+    login_attempts (throttle_key) {
+        #[max_length = 255]
+        throttle_key -> Varchar,
+        failures -> Unsigned<Integer>,
+        last_failure_at -> Datetime,
+        locked_until -> Nullable<Datetime>,
+    }
+}
+
 diesel::table! {
     tokens (id) {
         id -> Unsigned<Bigint>,
-        token -> Unsigned<Bigint>,
         user_id -> Unsigned<Bigint>,
         expires_at -> Datetime,
+        session_secret -> Blob,
+        created_at -> Datetime,
+        last_used_at -> Datetime,
+        #[max_length = 255]
+        client_label -> Varchar,
+        #[max_length = 64]
+        peer_addr -> Varchar,
+        device_id -> Nullable<Unsigned<Bigint>>,
+        #[max_length = 32]
+        token_hash -> Varbinary,
+    }
+}
+
+diesel::table! {
+    totp_backup_codes (id) {
+        id -> Unsigned<Bigint>,
+        user_id -> Unsigned<Bigint>,
+        code_hash -> Blob,
+        used_at -> Nullable<Datetime>,
+    }
+}
+
+diesel::table! {
+    totp_secrets (user_id) {
+        user_id -> Unsigned<Bigint>,
+        secret_ciphertext -> Blob,
+        secret_nonce -> Blob,
+        confirmed -> Bool,
+        last_used_step -> Nullable<Unsigned<Bigint>>,
+        created_at -> Datetime,
     }
 }
 
@@ -50,7 +119,25 @@ diesel::table! {
         login -> Varchar,
         #[max_length = 255]
         name -> Varchar,
-        password_hash -> Blob,
+        password_hash -> Nullable<Blob>,
+        srp_salt -> Nullable<Blob>,
+        srp_verifier -> Nullable<Blob>,
+        #[max_length = 255]
+        kdf_params -> Nullable<Varchar>,
+        is_admin -> Bool,
+        credential_reset -> Bool,
+        #[max_length = 255]
+        login_key -> Nullable<Varchar>,
+        require_2fa -> Bool,
+        delete_after -> Nullable<Datetime>,
+        deleted_at -> Nullable<Datetime>,
+        #[max_length = 255]
+        display_name -> Nullable<Varchar>,
+        bio -> Nullable<Text>,
+        #[max_length = 255]
+        avatar_ref -> Nullable<Varchar>,
+        created_at -> Datetime,
+        discoverable -> Bool,
     }
 }
 
@@ -59,13 +146,23 @@ diesel::joinable!(chats -> users (owner_id));
 diesel::joinable!(chats_users -> chat_roles (role_id));
 diesel::joinable!(chats_users -> chats (chat_id));
 diesel::joinable!(chats_users -> users (user_id));
+diesel::joinable!(devices -> users (user_id));
+diesel::joinable!(invites -> users (created_by));
+diesel::joinable!(tokens -> devices (device_id));
 diesel::joinable!(tokens -> users (user_id));
+diesel::joinable!(totp_backup_codes -> users (user_id));
+diesel::joinable!(totp_secrets -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     challenges,
     chat_roles,
     chats,
     chats_users,
+    devices,
+    invites,
+    login_attempts,
     tokens,
+    totp_backup_codes,
+    totp_secrets,
     users,
 );
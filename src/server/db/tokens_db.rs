@@ -1,10 +1,9 @@
 use chrono::{NaiveDateTime, Utc};
-use diesel::{AsChangeset, Connection, ExpressionMethods, Insertable, MysqlConnection, QueryDsl, Queryable, RunQueryDsl, Selectable};
+use diesel::{AsChangeset, ExpressionMethods, Insertable, MysqlConnection, QueryDsl, Queryable, RunQueryDsl, Selectable};
 use diesel::r2d2::{ConnectionManager, PooledConnection};
 use diesel::result::Error as DieselError;
-// ... existing code ...
-use rand::rngs::OsRng;
-use rand::RngCore;
+use crate::server::db::last_insert_id;
+use crate::util::crypto::token_util::{generate_session_token, hash_session_token};
 
 pub struct TokensDb;
 
@@ -13,60 +12,104 @@ pub struct TokensDb;
 #[diesel(check_for_backend(diesel::mysql::Mysql))]
 pub struct TokenRow {
     pub id: u64,
-    pub token: u64,
     pub user_id: u64,
     pub expires_at: NaiveDateTime,
+    /// `codec_util::derive_session_secret` of the login's SRP key
+    pub session_secret: Vec<u8>,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: NaiveDateTime,
+    pub client_label: String,
+    pub peer_addr: String,
+    /// Device the session was issued to, None for clients that didn't register one
+    pub device_id: Option<u64>,
+    /// `token_util::hash_session_token`, the token itself is only known to the client
+    pub token_hash: Vec<u8>,
 }
 
 impl TokensDb {
+    /// Returns the row id and the token to hand to the client, see
+    /// `token_util::generate_session_token`
     pub fn create_token(
         conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
         user_id: u64,
         expires_at_value: NaiveDateTime,
-    ) -> Result<u64, DieselError> {
+        session_secret: Vec<u8>,
+        client_label: String,
+        peer_addr: String,
+        device_id: Option<u64>,
+    ) -> Result<(u64, String), DieselError> {
         use crate::server::db::schema::tokens;
 
-        const MAX_ATTEMPTS: usize = 32;
-
-        conn.transaction(|conn| {
-            for _ in 0..MAX_ATTEMPTS {
-                let mut token_value = rand::rng().next_u64();
-                if token_value == 0 {
-                    token_value = 1;
-                }
-
-                let row = TokenRow {
-                    id: 0,
-                    token: token_value,
-                    user_id,
-                    expires_at: expires_at_value,
-                };
-
-                match diesel::insert_into(tokens::table).values(&row).execute(conn) {
-                    Ok(_) => return Ok(token_value),
-                    Err(DieselError::DatabaseError(
-                            diesel::result::DatabaseErrorKind::UniqueViolation,
-                            _,
-                        )) => {
-                        // token collision, retry
-                        continue;
-                    }
-                    Err(e) => return Err(e),
-                }
-            }
-
-        //    let _ = Self::delete_expired(conn, Utc::now().naive_utc());
-            Err(DieselError::RollbackTransaction)
-        })
+        let now = Utc::now().naive_utc();
+        let (token_value, token_hash) = generate_session_token();
+
+        let row = TokenRow {
+            id: 0,
+            user_id,
+            expires_at: expires_at_value,
+            session_secret,
+            created_at: now,
+            last_used_at: now,
+            client_label,
+            peer_addr,
+            device_id,
+            token_hash,
+        };
+
+        // 256 random bits don't collide, the unique index is only a backstop
+        diesel::insert_into(tokens::table).values(&row).execute(conn)?;
+        let id = diesel::select(last_insert_id()).get_result::<u64>(conn)?;
+        Ok((id, token_value))
     }
 
     pub fn find_token_by_value(
         conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
-        token_value: u64,
+        token_value: &str,
     ) -> Result<TokenRow, DieselError> {
         use crate::server::db::schema::tokens::dsl::*;
 
-        tokens.filter(token.eq(token_value)).first::<TokenRow>(conn)
+        let hash = hash_session_token(token_value).ok_or(DieselError::NotFound)?;
+        tokens.filter(token_hash.eq(hash)).first::<TokenRow>(conn)
+    }
+
+    /// Looks up a token presented by a client, rejecting malformed and expired ones
+    pub fn find_valid_token(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        token_value: &str,
+        now: NaiveDateTime,
+    ) -> Result<TokenRow, DieselError> {
+        use crate::server::db::schema::tokens::dsl::*;
+
+        let Some(hash) = hash_session_token(token_value) else {
+            return Err(DieselError::NotFound);
+        };
+        Self::find_valid_token_by_hash(conn, &hash, now)
+    }
+
+    /// Like `find_valid_token`, for callers that only got the token's hash
+    pub fn find_valid_token_by_hash(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        hash: &[u8],
+        now: NaiveDateTime,
+    ) -> Result<TokenRow, DieselError> {
+        use crate::server::db::schema::tokens::dsl::*;
+
+        tokens
+            .filter(token_hash.eq(hash))
+            .filter(expires_at.gt(now))
+            .first::<TokenRow>(conn)
+    }
+
+    pub fn touch_token(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        token_id: u64,
+        now: NaiveDateTime,
+    ) -> Result<usize, DieselError> {
+        use crate::server::db::schema::tokens::dsl::*;
+
+        diesel::update(tokens.filter(id.eq(token_id)))
+            .set(last_used_at.eq(now))
+            .execute(conn)
     }
 
     pub fn find_tokens_by_user_id(
@@ -91,11 +134,25 @@ impl TokensDb {
 
     pub fn delete_token_by_value(
         conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
-        token_value: u64,
+        token_value: &str,
+    ) -> Result<usize, DieselError> {
+        use crate::server::db::schema::tokens::dsl::*;
+
+        let Some(hash) = hash_session_token(token_value) else {
+            return Ok(0);
+        };
+        diesel::delete(tokens.filter(token_hash.eq(hash))).execute(conn)
+    }
+
+    /// Deletes one of `uid`'s sessions, 0 rows means it doesn't exist or isn't theirs
+    pub fn delete_token_for_user(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        uid: u64,
+        token_id: u64,
     ) -> Result<usize, DieselError> {
         use crate::server::db::schema::tokens::dsl::*;
 
-        diesel::delete(tokens.filter(token.eq(token_value))).execute(conn)
+        diesel::delete(tokens.filter(id.eq(token_id)).filter(user_id.eq(uid))).execute(conn)
     }
 
     pub fn delete_tokens_for_user(
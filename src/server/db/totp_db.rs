@@ -0,0 +1,161 @@
+use crate::util::crypto::totp_util::TotpStore;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::result::Error as DieselError;
+use diesel::{
+    AsChangeset, BoolExpressionMethods, Connection, ExpressionMethods, Insertable,
+    MysqlConnection, OptionalExtension, QueryDsl, Queryable, RunQueryDsl, Selectable,
+};
+
+pub struct TotpDb;
+
+/// `TotpStore` on a database connection
+pub struct TotpDbStore<'a>(pub &'a mut PooledConnection<ConnectionManager<MysqlConnection>>);
+
+impl TotpStore for TotpDbStore<'_> {
+    type Error = DieselError;
+
+    fn accept_step(&mut self, user_id: u64, step: u64) -> Result<bool, DieselError> {
+        TotpDb::accept_step(self.0, user_id, step).map(|n| n > 0)
+    }
+
+    fn use_backup_code(
+        &mut self,
+        user_id: u64,
+        hash: &[u8],
+        now: DateTime<Utc>,
+    ) -> Result<bool, DieselError> {
+        TotpDb::use_backup_code(self.0, user_id, hash, now.naive_utc()).map(|n| n > 0)
+    }
+}
+
+/// TOTP secret of a user, encrypted with `totp_util::encrypt_secret`
+#[derive(Queryable, Selectable, Insertable, PartialEq, AsChangeset, Debug)]
+#[diesel(table_name = crate::server::db::schema::totp_secrets)]
+#[diesel(check_for_backend(diesel::mysql::Mysql))]
+pub struct TotpSecretRow {
+    pub user_id: u64,
+    pub secret_ciphertext: Vec<u8>,
+    pub secret_nonce: Vec<u8>,
+    /// False until the user proved their authenticator works, only then is it enforced
+    pub confirmed: bool,
+    /// Last time step a code was accepted for, earlier or equal steps are replays
+    pub last_used_step: Option<u64>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable, Insertable, PartialEq, AsChangeset, Debug)]
+#[diesel(table_name = crate::server::db::schema::totp_backup_codes)]
+#[diesel(check_for_backend(diesel::mysql::Mysql))]
+pub struct TotpBackupCodeRow {
+    pub id: u64,
+    pub user_id: u64,
+    /// `totp_util::hash_backup_code`
+    pub code_hash: Vec<u8>,
+    pub used_at: Option<NaiveDateTime>,
+}
+
+impl TotpDb {
+    /// Starts an enrollment. Replaces an unconfirmed secret and the backup codes; a
+    /// confirmed secret is left alone and `NotFound` returned.
+    pub fn begin_enrollment(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        row: &TotpSecretRow,
+        backup_code_hashes: Vec<Vec<u8>>,
+    ) -> Result<(), DieselError> {
+        use crate::server::db::schema::{totp_backup_codes, totp_secrets};
+
+        conn.transaction(|conn| {
+            let confirmed = totp_secrets::table
+                .filter(totp_secrets::user_id.eq(row.user_id))
+                .select(totp_secrets::confirmed)
+                .for_update()
+                .first::<bool>(conn)
+                .optional()?;
+            if confirmed == Some(true) {
+                return Err(DieselError::NotFound);
+            }
+
+            diesel::replace_into(totp_secrets::table)
+                .values(row)
+                .execute(conn)?;
+
+            diesel::delete(
+                totp_backup_codes::table.filter(totp_backup_codes::user_id.eq(row.user_id)),
+            )
+            .execute(conn)?;
+            let codes: Vec<TotpBackupCodeRow> = backup_code_hashes
+                .into_iter()
+                .map(|code_hash| TotpBackupCodeRow {
+                    id: 0,
+                    user_id: row.user_id,
+                    code_hash,
+                    used_at: None,
+                })
+                .collect();
+            diesel::insert_into(totp_backup_codes::table)
+                .values(&codes)
+                .execute(conn)?;
+            Ok(())
+        })
+    }
+
+    pub fn find_secret(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        uid: u64,
+    ) -> Result<TotpSecretRow, DieselError> {
+        use crate::server::db::schema::totp_secrets::dsl::*;
+
+        totp_secrets.filter(user_id.eq(uid)).first::<TotpSecretRow>(conn)
+    }
+
+    /// Records that a code for `step` was accepted and confirms the secret. Returns 0 if
+    /// a code for this or a later step was accepted before, which makes it a replay.
+    pub fn accept_step(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        uid: u64,
+        step: u64,
+    ) -> Result<usize, DieselError> {
+        use crate::server::db::schema::totp_secrets::dsl::*;
+
+        diesel::update(
+            totp_secrets
+                .filter(user_id.eq(uid))
+                .filter(last_used_step.is_null().or(last_used_step.lt(step))),
+        )
+        .set((last_used_step.eq(Some(step)), confirmed.eq(true)))
+        .execute(conn)
+    }
+
+    /// Marks a backup code as used, returns 0 if it doesn't exist or was used already
+    pub fn use_backup_code(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        uid: u64,
+        hash: &[u8],
+        now: NaiveDateTime,
+    ) -> Result<usize, DieselError> {
+        use crate::server::db::schema::totp_backup_codes::dsl::*;
+
+        diesel::update(
+            totp_backup_codes
+                .filter(user_id.eq(uid))
+                .filter(code_hash.eq(hash))
+                .filter(used_at.is_null()),
+        )
+        .set(used_at.eq(Some(now)))
+        .execute(conn)
+    }
+
+    pub fn delete_for_user(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        uid: u64,
+    ) -> Result<usize, DieselError> {
+        use crate::server::db::schema::{totp_backup_codes, totp_secrets};
+
+        conn.transaction(|conn| {
+            diesel::delete(totp_backup_codes::table.filter(totp_backup_codes::user_id.eq(uid)))
+                .execute(conn)?;
+            diesel::delete(totp_secrets::table.filter(totp_secrets::user_id.eq(uid))).execute(conn)
+        })
+    }
+}
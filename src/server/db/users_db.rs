@@ -1,12 +1,17 @@
+use chrono::{NaiveDateTime, Utc};
 use diesel::{
-    AsChangeset, Connection, Insertable, MysqlConnection, Queryable,
-    RunQueryDsl, Selectable, QueryDsl, ExpressionMethods,
+    AsChangeset, Connection, Insertable, MysqlConnection, OptionalExtension, Queryable,
+    RunQueryDsl, Selectable, SelectableHelper, QueryDsl, ExpressionMethods,
 };
 use diesel::r2d2::{ConnectionManager, PooledConnection};
 use diesel::result::Error as DieselError;
+use crate::server::deletion_policy::OwnedChatPolicy;
+use crate::util::crypto::srp_util::{compute_verifier, generate_salt};
 
 pub struct UsersDb;
 
+/// A `users` row. The generated `login_key` column is left out, MySQL refuses writes to
+/// it, so rows are loaded with `User::as_select()`.
 #[derive(Queryable, Selectable, Insertable, PartialEq, AsChangeset, Debug)]
 #[diesel(table_name = crate::server::db::schema::users)]
 #[diesel(check_for_backend(diesel::mysql::Mysql))]
@@ -14,15 +19,86 @@ pub struct User {
     pub id: u64,
     pub login: String,
     pub name: String,
-    pub password_hash: Vec<u8>
+    /// Legacy password-equivalent key, cleared by `migrate_legacy_credentials`
+    pub password_hash: Option<Vec<u8>>,
+    pub srp_salt: Option<Vec<u8>>,
+    pub srp_verifier: Option<Vec<u8>>,
+    /// `KdfParams::to_db_string`, NULL for accounts on the legacy HKDF derivation
+    pub kdf_params: Option<String>,
+    pub is_admin: bool,
+    /// Set on accounts without a usable credential, such as later duplicates of a login
+    /// from before logins were unique. They can't log in and are left out of the login's
+    /// unique index until the flag is cleared.
+    pub credential_reset: bool,
+    /// Login is refused a token until a TOTP code is given, enrolling first if needed
+    pub require_2fa: bool,
+    /// Set while a deletion is pending, the account is purged after this
+    pub delete_after: Option<NaiveDateTime>,
+    /// Set once purged, the row is then only an anonymous tombstone
+    pub deleted_at: Option<NaiveDateTime>,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub avatar_ref: Option<String>,
+    pub created_at: NaiveDateTime,
+    /// Whether the user shows up in `SearchUsers`
+    pub discoverable: bool,
+}
+
+/// The columns of `users` other users may see
+#[derive(Queryable, Selectable, PartialEq, Debug)]
+#[diesel(table_name = crate::server::db::schema::users)]
+#[diesel(check_for_backend(diesel::mysql::Mysql))]
+pub struct ProfileRow {
+    pub id: u64,
+    pub login: String,
+    pub name: String,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub avatar_ref: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub deleted_at: Option<NaiveDateTime>,
+}
+
+/// Profile fields to change, None leaves a column alone and Some(None) clears it
+#[derive(AsChangeset, Default, Debug)]
+#[diesel(table_name = crate::server::db::schema::users)]
+pub struct ProfileChangeset {
+    pub display_name: Option<Option<String>>,
+    pub bio: Option<Option<String>>,
+    pub avatar_ref: Option<Option<String>>,
+    pub discoverable: Option<bool>,
+}
+
+impl ProfileChangeset {
+    pub fn is_empty(&self) -> bool {
+        self.display_name.is_none()
+            && self.bio.is_none()
+            && self.avatar_ref.is_none()
+            && self.discoverable.is_none()
+    }
+}
+
+/// Makes `%` and `_` match literally, `\` is MySQL's default LIKE escape
+fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
 }
 
 impl UsersDb {
+    /// Fails with `DatabaseErrorKind::UniqueViolation` if the login is taken
     pub fn create_user(
         conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
         login: String,
         name: String,
-       hash: Vec<u8>
+        salt: Vec<u8>,
+        verifier: Vec<u8>,
+        kdf: Option<String>,
     ) -> Result<usize, DieselError> {
         use crate::server::db::schema::users;
 
@@ -30,7 +106,20 @@ impl UsersDb {
             id: 0,
             login,
             name,
-            password_hash: hash
+            password_hash: None,
+            srp_salt: Some(salt),
+            srp_verifier: Some(verifier),
+            kdf_params: kdf,
+            is_admin: false,
+            credential_reset: false,
+            require_2fa: false,
+            delete_after: None,
+            deleted_at: None,
+            display_name: None,
+            bio: None,
+            avatar_ref: None,
+            created_at: Utc::now().naive_utc(),
+            discoverable: true,
         };
 
         conn.transaction(|conn| {
@@ -40,6 +129,7 @@ impl UsersDb {
         })
     }
 
+    /// Accounts flagged with `credential_reset` aren't found, they may share the login
     pub fn find_user_by_login(
         conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
         user_login: &str,
@@ -48,31 +138,98 @@ impl UsersDb {
 
         users
             .filter(login.eq(user_login))
-            .first::<User>(conn)
+            .filter(credential_reset.eq(false))
+            .select(User::as_select())
+            .first(conn)
     }
 
-    pub fn is_user_exists(
+    pub fn find_user_by_id(
         conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
-        user_login: &str,
-    ) -> Result<bool, DieselError> {
+        user_id: u64,
+    ) -> Result<User, DieselError> {
         use crate::server::db::schema::users::dsl::*;
 
-        match users.filter(login.eq(user_login)).first::<User>(conn) {
-            Ok(_) => Ok(true),
-            Err(DieselError::NotFound) => Ok(false),
-            Err(e) => Err(e),
-        }
+        users
+            .filter(id.eq(user_id))
+            .select(User::as_select())
+            .first(conn)
     }
 
-    pub fn find_user_by_id(
+    pub fn find_profile(
         conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
         user_id: u64,
-    ) -> Result<User, DieselError> {
+    ) -> Result<ProfileRow, DieselError> {
         use crate::server::db::schema::users::dsl::*;
 
         users
             .filter(id.eq(user_id))
-            .first::<User>(conn)
+            .select(ProfileRow::as_select())
+            .first(conn)
+    }
+
+    pub fn find_profiles(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        user_ids: &[u64],
+    ) -> Result<Vec<ProfileRow>, DieselError> {
+        use crate::server::db::schema::users::dsl::*;
+
+        users
+            .filter(id.eq_any(user_ids))
+            .select(ProfileRow::as_select())
+            .load(conn)
+    }
+
+    /// Discoverable, live users whose login or display name starts with `query`, or
+    /// contains it if `substring` is set. Ordered by login, `after` is the last login of
+    /// the previous page.
+    pub fn search_users(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        query: &str,
+        substring: bool,
+        after: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<ProfileRow>, DieselError> {
+        use crate::server::db::schema::users::dsl::*;
+
+        let pattern = if substring {
+            format!("%{}%", escape_like(query))
+        } else {
+            format!("{}%", escape_like(query))
+        };
+
+        let mut select = users
+            .filter(discoverable.eq(true))
+            .filter(deleted_at.is_null())
+            .filter(delete_after.is_null())
+            .filter(
+                login
+                    .like(pattern.clone())
+                    .or(display_name.like(pattern.clone()))
+                    .or(display_name.is_null().and(name.like(pattern))),
+            )
+            .order(login.asc())
+            .limit(limit)
+            .select(ProfileRow::as_select())
+            .into_boxed();
+        if let Some(after) = after {
+            select = select.filter(login.gt(after.to_string()));
+        }
+        select.load(conn)
+    }
+
+    pub fn update_profile(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        user_id: u64,
+        changes: &ProfileChangeset,
+    ) -> Result<usize, DieselError> {
+        use crate::server::db::schema::users::dsl::*;
+
+        if changes.is_empty() {
+            return Ok(0);
+        }
+        diesel::update(users.filter(id.eq(user_id)))
+            .set(changes)
+            .execute(conn)
     }
 
     pub fn update_user(
@@ -86,13 +243,238 @@ impl UsersDb {
             .execute(conn)
     }
 
-    pub fn delete_user(
+    pub fn update_credentials(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        user_id: u64,
+        salt: Vec<u8>,
+        verifier: Vec<u8>,
+        kdf: Option<String>,
+    ) -> Result<usize, DieselError> {
+        use crate::server::db::schema::users::dsl::*;
+
+        diesel::update(users.filter(id.eq(user_id)))
+            .set((
+                srp_salt.eq(Some(salt)),
+                srp_verifier.eq(Some(verifier)),
+                kdf_params.eq(kdf),
+            ))
+            .execute(conn)
+    }
+
+    /// Swaps in a new credential and ends every other session of the user in one
+    /// transaction: all tokens but `keep_token` and all pending challenges are deleted.
+    pub fn replace_credentials(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        user_id: u64,
+        salt: Vec<u8>,
+        verifier: Vec<u8>,
+        kdf: Option<String>,
+        keep_token: u64,
+    ) -> Result<usize, DieselError> {
+        use crate::server::db::schema::{challenges, tokens};
+
+        conn.transaction(|conn| {
+            let updated = Self::update_credentials(conn, user_id, salt, verifier, kdf)?;
+            if updated != 1 {
+                return Err(DieselError::NotFound);
+            }
+            diesel::delete(
+                tokens::table
+                    .filter(tokens::user_id.eq(user_id))
+                    .filter(tokens::id.ne(keep_token)),
+            )
+            .execute(conn)?;
+            diesel::delete(challenges::table.filter(challenges::user_id.eq(user_id)))
+                .execute(conn)?;
+            Ok(updated)
+        })
+    }
+
+    /// Marks the account for purging at `purge_at` and signs it out everywhere: all its
+    /// tokens and pending challenges are deleted in the same transaction
+    pub fn schedule_deletion(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        user_id: u64,
+        purge_at: NaiveDateTime,
+    ) -> Result<usize, DieselError> {
+        use crate::server::db::schema::{challenges, tokens, users};
+
+        conn.transaction(|conn| {
+            let updated = diesel::update(users::table.filter(users::id.eq(user_id)))
+                .set(users::delete_after.eq(Some(purge_at)))
+                .execute(conn)?;
+            if updated != 1 {
+                return Err(DieselError::NotFound);
+            }
+            diesel::delete(tokens::table.filter(tokens::user_id.eq(user_id))).execute(conn)?;
+            diesel::delete(challenges::table.filter(challenges::user_id.eq(user_id)))
+                .execute(conn)?;
+            Ok(updated)
+        })
+    }
+
+    /// Returns 0 if no deletion was pending
+    pub fn cancel_deletion(
         conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
         user_id: u64,
     ) -> Result<usize, DieselError> {
         use crate::server::db::schema::users::dsl::*;
 
-        diesel::delete(users.filter(id.eq(user_id)))
+        diesel::update(users.filter(id.eq(user_id)).filter(delete_after.is_not_null()))
+            .set(delete_after.eq(None::<NaiveDateTime>))
             .execute(conn)
     }
+
+    /// Ids of accounts whose grace period ended by `now`
+    pub fn find_due_deletions(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        now: NaiveDateTime,
+    ) -> Result<Vec<u64>, DieselError> {
+        use crate::server::db::schema::users::dsl::*;
+
+        users
+            .filter(delete_after.le(now))
+            .select(id)
+            .load::<u64>(conn)
+    }
+
+    /// Purges an account whose deletion is due, all in one transaction. Sessions,
+    /// challenges, 2FA secrets and chat memberships are deleted and owned chats are
+    /// transferred or deleted according to `owned_chats`. A chat's heir also takes over
+    /// the role the owner had in it.
+    ///
+    /// The row itself is kept as an anonymous tombstone, login `deleted#<id>` and no name
+    /// or credentials, so whatever the user authored keeps a valid author without saying
+    /// who it was. Returns 0 if the deletion was cancelled in the meantime.
+    pub fn purge_user(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        user_id: u64,
+        owned_chats: OwnedChatPolicy,
+        now: NaiveDateTime,
+    ) -> Result<usize, DieselError> {
+        use crate::server::db::schema::{
+            challenges, chats, chats_users, devices, invites, tokens, totp_backup_codes,
+            totp_secrets, users,
+        };
+
+        conn.transaction(|conn| {
+            let due = users::table
+                .filter(users::id.eq(user_id))
+                .filter(users::delete_after.le(now))
+                .select(users::id)
+                .for_update()
+                .first::<u64>(conn)
+                .optional()?;
+            if due.is_none() {
+                return Ok(0);
+            }
+
+            diesel::delete(tokens::table.filter(tokens::user_id.eq(user_id))).execute(conn)?;
+            diesel::delete(devices::table.filter(devices::user_id.eq(user_id))).execute(conn)?;
+            diesel::delete(invites::table.filter(invites::created_by.eq(user_id)))
+                .execute(conn)?;
+            diesel::delete(challenges::table.filter(challenges::user_id.eq(user_id)))
+                .execute(conn)?;
+            diesel::delete(totp_backup_codes::table.filter(totp_backup_codes::user_id.eq(user_id)))
+                .execute(conn)?;
+            diesel::delete(totp_secrets::table.filter(totp_secrets::user_id.eq(user_id)))
+                .execute(conn)?;
+
+            let owned = chats::table
+                .filter(chats::owner_id.eq(user_id))
+                .select(chats::id)
+                .load::<u64>(conn)?;
+            for chat in owned {
+                let heir = match owned_chats {
+                    OwnedChatPolicy::Transfer => chats_users::table
+                        .filter(chats_users::chat_id.eq(chat))
+                        .filter(chats_users::user_id.ne(user_id))
+                        .order(chats_users::id.asc())
+                        .select((chats_users::id, chats_users::user_id))
+                        .first::<(u64, u64)>(conn)
+                        .optional()?,
+                    OwnedChatPolicy::Delete => None,
+                };
+                match heir {
+                    Some((membership, heir)) => {
+                        let owner_role = chats_users::table
+                            .filter(chats_users::chat_id.eq(chat))
+                            .filter(chats_users::user_id.eq(user_id))
+                            .select(chats_users::role_id)
+                            .first::<u64>(conn)
+                            .optional()?;
+                        if let Some(owner_role) = owner_role {
+                            diesel::update(
+                                chats_users::table.filter(chats_users::id.eq(membership)),
+                            )
+                            .set(chats_users::role_id.eq(owner_role))
+                            .execute(conn)?;
+                        }
+                        diesel::update(chats::table.filter(chats::id.eq(chat)))
+                            .set(chats::owner_id.eq(heir))
+                            .execute(conn)?;
+                    }
+                    None => {
+                        diesel::delete(chats_users::table.filter(chats_users::chat_id.eq(chat)))
+                            .execute(conn)?;
+                        diesel::delete(chats::table.filter(chats::id.eq(chat))).execute(conn)?;
+                    }
+                }
+            }
+            diesel::delete(chats_users::table.filter(chats_users::user_id.eq(user_id)))
+                .execute(conn)?;
+
+            diesel::update(users::table.filter(users::id.eq(user_id)))
+                .set((
+                    users::login.eq(format!("deleted#{}", user_id)),
+                    users::name.eq(""),
+                    users::display_name.eq(None::<String>),
+                    users::bio.eq(None::<String>),
+                    users::avatar_ref.eq(None::<String>),
+                    users::password_hash.eq(None::<Vec<u8>>),
+                    users::srp_salt.eq(None::<Vec<u8>>),
+                    users::srp_verifier.eq(None::<Vec<u8>>),
+                    users::kdf_params.eq(None::<String>),
+                    users::is_admin.eq(false),
+                    users::require_2fa.eq(false),
+                    users::delete_after.eq(None::<NaiveDateTime>),
+                    users::deleted_at.eq(Some(now)),
+                ))
+                .execute(conn)
+        })
+    }
+
+    /// Converts accounts registered before SRP. Their stored `password_hash` is exactly
+    /// the key clients feed into SRP, so the verifier can be computed server side and the
+    /// password-equivalent value dropped.
+    pub fn migrate_legacy_credentials(
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+    ) -> Result<usize, DieselError> {
+        use crate::server::db::schema::users::dsl::*;
+
+        conn.transaction(|conn| {
+            let legacy = users
+                .filter(srp_verifier.is_null())
+                .filter(password_hash.is_not_null())
+                .select(User::as_select())
+                .load(conn)?;
+
+            for user in &legacy {
+                let Some(key) = &user.password_hash else {
+                    continue;
+                };
+                let salt = generate_salt();
+                let verifier = compute_verifier(&user.login, key, &salt);
+
+                diesel::update(users.filter(id.eq(user.id)))
+                    .set((
+                        srp_salt.eq(Some(salt)),
+                        srp_verifier.eq(Some(verifier)),
+                        password_hash.eq(None::<Vec<u8>>),
+                    ))
+                    .execute(conn)?;
+            }
+            Ok(legacy.len())
+        })
+    }
 }
@@ -0,0 +1,38 @@
+use crate::util::crypto::srp_util::compute_verifier;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Stand-in SRP credentials for logins without an account. A challenge for an unknown
+/// login carries a salt and a `B` like a real one and its proof fails with
+/// `ErrorCode::InvalidCredentials` like a wrong password, so logins can't be enumerated.
+///
+/// Shared by both listeners, a login has the same decoy salt on each of them as a real
+/// account has.
+pub struct DecoyCredentials {
+    /// Per-process key, decoys stay the same for a login until the server restarts
+    key: [u8; 32],
+}
+
+impl DecoyCredentials {
+    pub fn generate() -> Self {
+        Self {
+            key: rand::random(),
+        }
+    }
+
+    fn derive(&self, label: &[u8], login: &str) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).unwrap();
+        mac.update(label);
+        mac.update(login.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    /// Salt and verifier for `login`. The verifier belongs to a password nobody knows,
+    /// `B` computed from it with a fresh secret looks like any other.
+    pub fn credential(&self, login: &str) -> (Vec<u8>, Vec<u8>) {
+        let salt = self.derive(b"decoy salt", login);
+        let password_key = self.derive(b"decoy password", login);
+        let verifier = compute_verifier(login, &password_key, &salt);
+        (salt, verifier)
+    }
+}
@@ -0,0 +1,53 @@
+use chrono::Duration;
+use std::env;
+
+const DEFAULT_GRACE_DAYS: i64 = 14;
+
+/// What happens to the chats a deleted account owned
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OwnedChatPolicy {
+    /// The longest standing member becomes the owner and gets the owner's role, chats
+    /// left without members are deleted
+    Transfer,
+    /// The chats are deleted with all their memberships
+    Delete,
+}
+
+/// How account deletion is carried out, see `UsersDb::schedule_deletion` and
+/// `UsersDb::purge_user`
+#[derive(Clone, Debug)]
+pub struct DeletionPolicy {
+    /// Time between the request and the purge. Logging in before then cancels it.
+    pub grace: Duration,
+    pub owned_chats: OwnedChatPolicy,
+}
+
+impl Default for DeletionPolicy {
+    fn default() -> Self {
+        Self {
+            grace: Duration::days(DEFAULT_GRACE_DAYS),
+            owned_chats: OwnedChatPolicy::Transfer,
+        }
+    }
+}
+
+impl DeletionPolicy {
+    /// Reads `ACCOUNT_DELETION_GRACE_DAYS` and `ACCOUNT_DELETION_OWNED_CHATS`
+    /// (`transfer` or `delete`), keeping the defaults for unset or invalid values
+    pub fn from_env() -> Self {
+        let mut policy = Self::default();
+        if let Some(v) = env::var("ACCOUNT_DELETION_GRACE_DAYS")
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .filter(|v| *v >= 0)
+        {
+            policy.grace = Duration::days(v);
+        }
+        match env::var("ACCOUNT_DELETION_OWNED_CHATS").as_deref() {
+            Ok("transfer") => policy.owned_chats = OwnedChatPolicy::Transfer,
+            Ok("delete") => policy.owned_chats = OwnedChatPolicy::Delete,
+            _ => {}
+        }
+        policy
+    }
+}
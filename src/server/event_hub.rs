@@ -0,0 +1,97 @@
+use crate::structures::protolink_stype::{EventStruct, ServerEventStruct};
+use chrono::Utc;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Events kept per user, the oldest are dropped beyond this
+pub const MAX_QUEUED_EVENTS: usize = 256;
+
+/// What `EventHub::poll` found for a user
+pub struct PolledEvents {
+    pub events: Vec<EventStruct>,
+    /// Latest seq handed out, the `after` of the next poll
+    pub cursor: u64,
+    /// Events after `after` were lost to a restart or a full inbox
+    pub missed: bool,
+}
+
+#[derive(Default)]
+struct Inbox {
+    events: VecDeque<EventStruct>,
+    /// Seq of the newest event dropped to make room, 0 if none was
+    dropped_up_to: u64,
+}
+
+/// Per user inboxes of `ServerEventStruct`. Handlers publish to the users an event
+/// concerns, clients collect theirs with `PollEvents`. Every session of a user reads the
+/// same inbox with its own cursor, so polling doesn't remove anything.
+///
+/// Kept in memory, events are hints to refetch. Sequence numbers start from the time the
+/// server started, so a cursor from before a restart is older than anything queued since
+/// and `poll` reports the events it missed instead of skipping new ones.
+pub struct EventHub {
+    /// First seq handed out by this process
+    first_seq: u64,
+    next_seq: AtomicU64,
+    inboxes: Mutex<HashMap<u64, Inbox>>,
+}
+
+impl Default for EventHub {
+    fn default() -> Self {
+        let first_seq = Utc::now().timestamp_micros().max(1) as u64;
+        Self {
+            first_seq,
+            next_seq: AtomicU64::new(first_seq),
+            inboxes: Mutex::default(),
+        }
+    }
+}
+
+impl EventHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn publish(&self, recipients: &[u64], event: ServerEventStruct) {
+        // Taken under the lock, so a cursor from `poll` never passes an event that isn't
+        // queued yet
+        let mut inboxes = self.inboxes.lock().unwrap();
+        let seq = self.next_seq.fetch_add(1, Ordering::SeqCst);
+        for user_id in recipients {
+            let inbox = inboxes.entry(*user_id).or_default();
+            if inbox.events.len() >= MAX_QUEUED_EVENTS {
+                if let Some(dropped) = inbox.events.pop_front() {
+                    inbox.dropped_up_to = dropped.seq;
+                }
+            }
+            inbox.events.push_back(EventStruct {
+                seq,
+                event: event.clone(),
+            });
+        }
+    }
+
+    /// Events of `user_id` after `after`. A first poll with `after` 0 has nothing to miss.
+    pub fn poll(&self, user_id: u64, after: u64) -> PolledEvents {
+        let inboxes = self.inboxes.lock().unwrap();
+        let cursor = self.next_seq.load(Ordering::SeqCst) - 1;
+        let Some(inbox) = inboxes.get(&user_id) else {
+            return PolledEvents {
+                events: vec![],
+                cursor,
+                missed: after != 0 && after < self.first_seq - 1,
+            };
+        };
+        PolledEvents {
+            events: inbox
+                .events
+                .iter()
+                .filter(|e| e.seq > after)
+                .cloned()
+                .collect(),
+            cursor,
+            missed: after != 0 && (after < self.first_seq - 1 || after < inbox.dropped_up_to),
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use crate::server::db::challenges_db::ChallengesDb;
+use crate::server::db::invites_db::InvitesDb;
+use crate::server::db::tokens_db::TokensDb;
+use crate::server::db::users_db::UsersDb;
+use crate::server::deletion_policy::DeletionPolicy;
+use crate::server::login_throttle::LoginThrottle;
+use chrono::Utc;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::MysqlConnection;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use tfserver::tokio;
+use tfserver::tokio::task::JoinHandle;
+
+const DEFAULT_SWEEP_INTERVAL_SECS: u64 = 60;
+
+/// Interval from `EXPIRY_SWEEP_INTERVAL_SECS`, one minute if unset or invalid
+pub fn sweep_interval_from_env() -> Duration {
+    let secs = env::var("EXPIRY_SWEEP_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(DEFAULT_SWEEP_INTERVAL_SECS);
+    Duration::from_secs(secs)
+}
+
+/// Periodically purges expired tokens, challenges and invites and forgets stale login attempts.
+/// Lookups already ignore expired rows, this only keeps the tables from growing forever.
+///
+/// Also purges accounts whose deletion grace period ended, see `UsersDb::purge_user`.
+pub fn spawn_expiry_sweeper(
+    pool: Pool<ConnectionManager<MysqlConnection>>,
+    throttle: Arc<LoginThrottle>,
+    deletion: DeletionPolicy,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+
+            throttle.prune(Utc::now());
+
+            let mut conn = match pool.get() {
+                Ok(c) => c,
+                Err(err) => {
+                    eprintln!("Expiry sweep skipped: {}", err);
+                    continue;
+                }
+            };
+            let now = Utc::now().naive_utc();
+
+            if let Err(err) = TokensDb::delete_expired(&mut conn, now) {
+                eprintln!("Expired token sweep failed: {}", err);
+            }
+            if let Err(err) = ChallengesDb::delete_expired(&mut conn, now) {
+                eprintln!("Expired challenge sweep failed: {}", err);
+            }
+            if let Err(err) = InvitesDb::delete_expired(&mut conn, now) {
+                eprintln!("Expired invite sweep failed: {}", err);
+            }
+            match UsersDb::find_due_deletions(&mut conn, now) {
+                Ok(due) => {
+                    for user_id in due {
+                        if let Err(err) =
+                            UsersDb::purge_user(&mut conn, user_id, deletion.owned_chats, now)
+                        {
+                            eprintln!("Purging user {} failed: {}", user_id, err);
+                        }
+                    }
+                }
+                Err(err) => eprintln!("Account deletion sweep failed: {}", err),
+            }
+        }
+    })
+}
@@ -0,0 +1,126 @@
+use crate::server::access_keyring::AccessKeyring;
+use crate::server::db::users_db::UsersDb;
+use crate::server::login_throttle::LoginThrottle;
+use crate::server::server_encrypted_codec::ServerEncriptedCodec;
+use crate::server::session_guard;
+use crate::server::session_registry::SessionRegistry;
+use crate::structures::error_code::ErrorCode;
+use crate::structures::protolink_stype::{
+    ListLockoutsRequestStruct, ListLockoutsResponse, LockoutInfoStruct, ProtoLinkSType,
+};
+
+use chrono::Utc;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use diesel::MysqlConnection;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tfserver::async_trait::async_trait;
+use tfserver::server::handler::Handler;
+use tfserver::structures::s_type;
+use tfserver::structures::s_type::StructureType;
+use tfserver::structures::traffic_proc::TrafficProcessorHolder;
+use tfserver::structures::transport::Transport;
+use tfserver::tokio::sync::{oneshot::Sender, Mutex};
+use tfserver::tokio_util::bytes::BytesMut;
+use tfserver::tokio_util::codec::Framed;
+
+/// Requests only users with `is_admin` set may make
+pub struct AdminHandler {
+    db: Arc<Mutex<Pool<ConnectionManager<MysqlConnection>>>>,
+    throttle: Arc<LoginThrottle>,
+    registry: Arc<SessionRegistry>,
+    keyring: Arc<AccessKeyring>,
+}
+
+impl AdminHandler {
+    pub fn new(
+        db: Arc<Mutex<Pool<ConnectionManager<MysqlConnection>>>>,
+        throttle: Arc<LoginThrottle>,
+        registry: Arc<SessionRegistry>,
+        keyring: Arc<AccessKeyring>,
+    ) -> Self {
+        Self {
+            db,
+            throttle,
+            registry,
+            keyring,
+        }
+    }
+
+    async fn conn(&self) -> PooledConnection<ConnectionManager<MysqlConnection>> {
+        self.db.lock().await.get().expect("DB connection failed")
+    }
+
+    /// Whether `token` belongs to a live session of an admin
+    fn is_admin(
+        &self,
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        token: &str,
+    ) -> bool {
+        session_guard::authorize(conn, &self.keyring, &self.registry, token)
+            .and_then(|row| UsersDb::find_user_by_id(conn, row.user_id).ok())
+            .is_some_and(|user| user.is_admin)
+    }
+
+    pub async fn list_lockouts(&self, req: ListLockoutsRequestStruct) -> ListLockoutsResponse {
+        let mut conn = self.conn().await;
+
+        if !self.is_admin(&mut conn, &req.token) {
+            return ListLockoutsResponse::error(ErrorCode::Forbidden);
+        }
+
+        ListLockoutsResponse::ok(
+            self.throttle
+                .lockouts(Utc::now())
+                .into_iter()
+                .map(|lockout| LockoutInfoStruct {
+                    key: lockout.key.to_string(),
+                    failures: lockout.failures,
+                    locked_until: lockout.locked_until.timestamp(),
+                })
+                .collect(),
+        )
+    }
+}
+
+#[async_trait]
+impl Handler for AdminHandler {
+    type Codec = ServerEncriptedCodec;
+
+    async fn serve_route(
+        &mut self,
+        _client_meta: (
+            SocketAddr,
+            &mut Option<Sender<Arc<Mutex<dyn Handler<Codec = Self::Codec>>>>>,
+        ),
+        s_type: Box<dyn StructureType>,
+        mut data: BytesMut,
+    ) -> Result<Vec<u8>, Vec<u8>> {
+        let s_type = s_type
+            .as_any()
+            .downcast_ref::<ProtoLinkSType>()
+            .unwrap()
+            .clone();
+        match s_type {
+            ProtoLinkSType::ListLockouts => {
+                let req = s_type::from_slice::<ListLockoutsRequestStruct>(data.as_mut())?;
+                let resp = self.list_lockouts(req).await;
+                Ok(s_type::to_vec(&resp).unwrap())
+            }
+            _ => Err("Malformed request".into()),
+        }
+    }
+
+    async fn accept_stream(
+        &mut self,
+        _addr: SocketAddr,
+        _stream: (
+            Framed<Transport, Self::Codec>,
+            TrafficProcessorHolder<Self::Codec>,
+        ),
+    ) {
+        todo!()
+    }
+}
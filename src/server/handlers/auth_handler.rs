@@ -1,14 +1,29 @@
-use crate::server::db::{challenges_db, tokens_db, users_db};
-use crate::server::server_encrypted_codec::ServerEncriptedCodec;
+use crate::server::access_keyring::AccessKeyring;
+use crate::server::db::{challenges_db, users_db};
+use crate::server::decoy_credentials::DecoyCredentials;
+use crate::server::deletion_policy::DeletionPolicy;
+use crate::server::login_throttle::{LoginThrottle, ThrottleKey};
+use crate::server::password_proof;
+use crate::server::pending_logins::{PendingLogin, PendingLogins, PENDING_LOGIN_TTL};
+use crate::server::registration_policy::RegistrationPolicy;
+use crate::server::server_auth_codec::ServerAuthCodec;
+use crate::server::session_guard;
+use crate::server::session_registry::SessionRegistry;
+use crate::structures::error_code::ErrorCode;
 use crate::structures::protolink_stype::{
-    AuthChallenge, AuthRequestStruct, AuthResponse, ProtoLinkSType,
+    AuthChallenge, AuthProof, AuthRequestStruct, AuthResponse, ChangePasswordRequestStruct,
+    CredentialUpdateStruct, DeleteAccountRequestStruct, PendingLoginStruct, ProtoLinkSType,
+};
+use crate::util::crypto::codec_util::derive_session_secret;
+use crate::util::crypto::srp_util::{
+    compute_server_public, generate_ephemeral_secret, verify_credential_update_mac,
 };
-use crate::util::crypto::challenge_util::{generate_challenge, verify_challenge};
 
-use aes_gcm::{Aes256Gcm, KeyInit};
 use chrono::{Duration, Utc};
-use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
 use diesel::MysqlConnection;
+use sha2::Sha256;
+use srp::server::SrpServerVerifier;
 
 use std::net::SocketAddr;
 use std::sync::Arc;
@@ -23,115 +38,407 @@ use tfserver::tokio::sync::{oneshot::Sender, Mutex};
 use tfserver::tokio_util::bytes::BytesMut;
 use tfserver::tokio_util::codec::Framed;
 
+/// How long a client has to answer an `AuthChallenge`
+const CHALLENGE_TTL: Duration = Duration::minutes(5);
+/// Unanswered challenges kept per user, enough for a handful of devices logging in at once
+const MAX_OUTSTANDING_CHALLENGES: usize = 8;
+
 pub struct AuthHandler {
     db: Arc<Mutex<Pool<ConnectionManager<MysqlConnection>>>>,
+    throttle: Arc<LoginThrottle>,
+    policy: RegistrationPolicy,
+    registry: Arc<SessionRegistry>,
+    pending: Arc<PendingLogins>,
+    deletion: DeletionPolicy,
+    keyring: Arc<AccessKeyring>,
+    decoy: Arc<DecoyCredentials>,
 }
 
 impl AuthHandler {
-    pub fn new(db: Arc<Mutex<Pool<ConnectionManager<MysqlConnection>>>>) -> Self {
-        Self { db }
+    pub fn new(
+        db: Arc<Mutex<Pool<ConnectionManager<MysqlConnection>>>>,
+        throttle: Arc<LoginThrottle>,
+        policy: RegistrationPolicy,
+        registry: Arc<SessionRegistry>,
+        pending: Arc<PendingLogins>,
+        deletion: DeletionPolicy,
+        keyring: Arc<AccessKeyring>,
+        decoy: Arc<DecoyCredentials>,
+    ) -> Self {
+        Self {
+            db,
+            throttle,
+            policy,
+            registry,
+            pending,
+            deletion,
+            keyring,
+            decoy,
+        }
     }
 
-    async fn conn(&self) -> diesel::r2d2::PooledConnection<ConnectionManager<MysqlConnection>> {
+    async fn conn(&self) -> PooledConnection<ConnectionManager<MysqlConnection>> {
         self.db.lock().await.get().expect("DB connection failed")
     }
 
-    fn empty_challenge() -> AuthChallenge {
-        AuthChallenge::new(vec![], [0u8; 12], String::new())
+    /// Checks a replacement credential against the registration policy and its MAC,
+    /// returns the `users.kdf_params` value to store with it
+    fn check_credential_update(
+        &self,
+        session_key: &[u8],
+        update: &CredentialUpdateStruct,
+    ) -> Result<String, ErrorCode> {
+        self.policy
+            .validate_credential(&update.srp_salt, &update.srp_verifier, &update.kdf_params)
+            .map_err(|err| ErrorCode::from(&err))?;
+        let kdf = update
+            .kdf_params
+            .to_db_string()
+            .ok_or(ErrorCode::KdfParamsRejected)?;
+        if !verify_credential_update_mac(
+            session_key,
+            &kdf,
+            &update.srp_salt,
+            &update.srp_verifier,
+            &update.mac,
+        ) {
+            return Err(ErrorCode::InvalidCredentialUpdate);
+        }
+        Ok(kdf)
     }
 
-    pub async fn auth_request(&self, req: AuthRequestStruct) -> AuthChallenge {
+    /// Moves a legacy HKDF account to the Argon2id credential it sent along with its proof
+    fn upgrade_legacy_credential(
+        &self,
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        user_id: u64,
+        session_key: &[u8],
+        update: CredentialUpdateStruct,
+    ) -> bool {
+        let Ok(kdf) = self.check_credential_update(session_key, &update) else {
+            return false;
+        };
+        users_db::UsersDb::update_credentials(
+            conn,
+            user_id,
+            update.srp_salt,
+            update.srp_verifier,
+            Some(kdf),
+        )
+        .is_ok()
+    }
+
+    /// Challenges are throttled by address only, a login key here would let anyone lock
+    /// an account by asking for challenges. Past `MAX_OUTSTANDING_CHALLENGES` new ones are
+    /// refused and each refusal counts as a failed attempt of the address.
+    pub async fn auth_request(&self, req: AuthRequestStruct, peer: SocketAddr) -> AuthChallenge {
+        let addr = [ThrottleKey::addr(peer)];
+        if let Some(until) = self.throttle.check(&addr, Utc::now()) {
+            return AuthChallenge::locked(until.timestamp());
+        }
+
         let mut conn = self.conn().await;
 
         let user = match users_db::UsersDb::find_user_by_login(&mut conn, &req.login) {
             Ok(user) => user,
-            Err(_) => return Self::empty_challenge(),
+            Err(_) => return self.decoy_challenge(&mut conn, req.login),
         };
 
-        let nonce = rand::random::<[u8; 12]>();
-        let cipher = match Aes256Gcm::new_from_slice(&user.password_hash) {
-            Ok(c) => c,
-            Err(_) => return Self::empty_challenge(),
+        let (Some(salt), Some(verifier)) = (user.srp_salt, user.srp_verifier) else {
+            return self.decoy_challenge(&mut conn, req.login);
         };
 
-        let (solution, challenge) = generate_challenge(&cipher, &nonce, 128, 256);
+        let server_secret = generate_ephemeral_secret();
+        let server_public = compute_server_public(&server_secret, &verifier);
 
-        if challenges_db::ChallengesDb::create_challenge(
+        let challenge_id = match challenges_db::ChallengesDb::create_challenge(
             &mut conn,
             user.id,
-            challenge.clone(),
-            solution,
-            nonce.to_vec(),
-        )
-        .is_err()
-        {
-            return Self::empty_challenge();
-        }
+            server_public.clone(),
+            server_secret,
+            req.client_public,
+            (Utc::now() + CHALLENGE_TTL).naive_utc(),
+            MAX_OUTSTANDING_CHALLENGES,
+        ) {
+            Ok(Some(id)) => id,
+            Ok(None) => {
+                return match self.throttle.record_failure(&addr, Utc::now()) {
+                    Some(until) => AuthChallenge::locked(until.timestamp()),
+                    None => AuthChallenge::error(ErrorCode::TooManyChallenges),
+                };
+            }
+            Err(err) => return AuthChallenge::error(ErrorCode::from(&err)),
+        };
+
+        // The login as sent, the stored spelling could tell a decoy apart
+        AuthChallenge::new(challenge_id, req.login, salt, server_public)
+    }
+
+    /// Answers a request for a login that can't prove a password like one for a real
+    /// account, `auth_proof` then fails with `InvalidCredentials`. Nothing is stored. Real
+    /// challenge ids are sequential, so the decoy takes the next one; proofs are matched
+    /// by user as well, a real challenge getting the same id is never mistaken for it.
+    fn decoy_challenge(
+        &self,
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        login: String,
+    ) -> AuthChallenge {
+        let (salt, verifier) = self.decoy.credential(&login);
+        let server_public = compute_server_public(&generate_ephemeral_secret(), &verifier);
+        let challenge_id = challenges_db::ChallengesDb::latest_id(conn).unwrap_or_default() + 1;
+        AuthChallenge::new(challenge_id, login, salt, server_public)
+    }
 
-        AuthChallenge::new(challenge, nonce, user.login)
+    /// Counts a wrong proof against the login and the address, answering with the
+    /// lockout if this was one failure too many
+    fn reject_attempt(&self, keys: &[ThrottleKey], code: ErrorCode) -> AuthResponse {
+        password_proof::reject_attempt(&self.throttle, keys, code, Utc::now()).into()
     }
 
-    pub async fn auth_challenge(&self, req: AuthChallenge) -> AuthResponse {
+    /// Consumes challenge `challenge_id` of the user and checks `client_proof` against it,
+    /// counting a wrong proof towards `keys`
+    fn verify_proof(
+        &self,
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        user_id: u64,
+        verifier: &[u8],
+        challenge_id: u64,
+        client_proof: &[u8],
+        keys: &[ThrottleKey],
+    ) -> Result<SrpServerVerifier<Sha256>, AuthResponse> {
+        password_proof::verify_password_proof(
+            conn,
+            &self.throttle,
+            user_id,
+            verifier,
+            challenge_id,
+            client_proof,
+            keys,
+            Utc::now(),
+        )
+        .map_err(AuthResponse::from)
+    }
+
+    /// Proofs are what guesses a password, so this is where attempts are throttled by
+    /// login as well as by address.
+    pub async fn auth_proof(&self, req: AuthProof, peer: SocketAddr) -> AuthResponse {
+        let keys = [ThrottleKey::login(&req.login), ThrottleKey::addr(peer)];
+        if let Some(until) = self.throttle.check(&keys, Utc::now()) {
+            return AuthResponse::locked(until.timestamp());
+        }
+        if let Some(Err(code)) = req.device.as_ref().map(session_guard::check_device) {
+            return AuthResponse::error(code);
+        }
+
         let mut conn = self.conn().await;
 
         let user = match users_db::UsersDb::find_user_by_login(&mut conn, &req.login) {
             Ok(user) => user,
             Err(_) => {
-                return AuthResponse::error("user not found");
+                return self.reject_attempt(&keys, ErrorCode::InvalidCredentials);
             }
         };
 
-        let challenges =
-            match challenges_db::ChallengesDb::find_challenges_by_user_id(&mut conn, user.id) {
-                Ok(c) if !c.is_empty() => c,
-                _ => {
-                    return AuthResponse::error("challenge not found");
-                }
-            };
+        let Some(verifier) = user.srp_verifier else {
+            return self.reject_attempt(&keys, ErrorCode::InvalidCredentials);
+        };
 
-        let chal = &challenges[0];
+        let srp_session = match self.verify_proof(
+            &mut conn,
+            user.id,
+            &verifier,
+            req.challenge_id,
+            &req.client_proof,
+            &keys,
+        ) {
+            Ok(session) => session,
+            Err(resp) => return resp,
+        };
 
-        if !verify_challenge(&chal.solution, &req.challenge) {
-            let _ = challenges_db::ChallengesDb::delete_challenge(&mut conn, chal.id);
-            return AuthResponse::error("incorrect");
+        if let Some(update) = req.credential_update {
+            if user.kdf_params.is_none()
+                && !self.upgrade_legacy_credential(&mut conn, user.id, srp_session.key(), update)
+            {
+                eprintln!("Credential upgrade rejected for user {}", user.id);
+            }
+        }
+
+        let session_secret = derive_session_secret(srp_session.key()).to_vec();
+        let now = Utc::now();
+
+        if let Some(enrollment_required) =
+            session_guard::second_factor(&mut conn, user.id, user.require_2fa)
+        {
+            let expires_at = now + PENDING_LOGIN_TTL;
+            let id = self.pending.insert(
+                PendingLogin::new(
+                    user.id,
+                    session_secret,
+                    req.client_label,
+                    req.device,
+                    peer,
+                    enrollment_required,
+                    expires_at,
+                ),
+                now,
+            );
+            let mut resp = AuthResponse::ok(String::new());
+            resp.server_proof = srp_session.proof().to_vec();
+            resp.pending_login = Some(PendingLoginStruct {
+                id,
+                enrollment_required,
+                expires_at: expires_at.timestamp(),
+            });
+            return resp;
         }
 
-        let token = match tokens_db::TokensDb::create_token(
+        let issued = match session_guard::issue_session(
             &mut conn,
+            &self.keyring,
             user.id,
-            (Utc::now() + Duration::hours(2)).naive_utc(),
+            session_secret,
+            req.client_label,
+            req.device.as_ref(),
+            peer,
+            now,
         ) {
-            Ok(token) => token,
+            Ok(issued) => issued,
             Err(_) => {
-                return AuthResponse::error("token creation failed");
+                return AuthResponse::error(ErrorCode::TokenCreationFailed);
             }
         };
 
-        AuthResponse {
-            success: true,
-            s_type: ProtoLinkSType::AuthResponse,
-            message: token.to_string(),
+        let mut resp = AuthResponse::ok(String::new());
+        resp.server_proof = srp_session.proof().to_vec();
+        resp.session = Some(issued.into());
+        resp
+    }
+
+    /// Replaces the password of a logged in user. The old password is proven with a
+    /// challenge like a login. Every other token is deleted and every other encrypted
+    /// connection has to handshake again, the token making the request stays valid.
+    pub async fn change_password(
+        &self,
+        req: ChangePasswordRequestStruct,
+        peer: SocketAddr,
+    ) -> AuthResponse {
+        let mut conn = self.conn().await;
+
+        let Some(session) = session_guard::authorize_session(&mut conn, &req.token) else {
+            return AuthResponse::error(ErrorCode::Unauthorized);
+        };
+
+        let user = match users_db::UsersDb::find_user_by_id(&mut conn, session.user_id) {
+            Ok(user) => user,
+            Err(_) => return AuthResponse::error(ErrorCode::UserNotFound),
+        };
+
+        let keys = [ThrottleKey::login(&user.login), ThrottleKey::addr(peer)];
+        if let Some(until) = self.throttle.check(&keys, Utc::now()) {
+            return AuthResponse::locked(until.timestamp());
+        }
+
+        let Some(verifier) = user.srp_verifier else {
+            return AuthResponse::error(ErrorCode::UserNotFound);
+        };
+
+        let srp_session = match self.verify_proof(
+            &mut conn,
+            user.id,
+            &verifier,
+            req.challenge_id,
+            &req.client_proof,
+            &keys,
+        ) {
+            Ok(session) => session,
+            Err(resp) => return resp,
+        };
+
+        let kdf = match self.check_credential_update(srp_session.key(), &req.credential_update) {
+            Ok(kdf) => kdf,
+            Err(code) => return AuthResponse::error(code),
+        };
+
+        let update = req.credential_update;
+        if let Err(err) = users_db::UsersDb::replace_credentials(
+            &mut conn,
+            user.id,
+            update.srp_salt,
+            update.srp_verifier,
+            Some(kdf),
+            session.id,
+        ) {
+            return AuthResponse::error(ErrorCode::from(&err));
         }
+        self.registry.revoke_user(user.id, Some(session.id));
+
+        let mut resp = AuthResponse::ok(String::new());
+        resp.server_proof = srp_session.proof().to_vec();
+        resp
     }
-}
 
-impl AuthResponse {
-    fn error(msg: &str) -> Self {
-        Self {
-            success: false,
-            s_type: ProtoLinkSType::AuthResponse,
-            message: msg.to_string(),
+    /// Schedules the account for purging after the grace period, proving the password
+    /// like `change_password`. The account is signed out everywhere at once, logging in
+    /// again before the purge cancels it.
+    pub async fn delete_account(
+        &self,
+        req: DeleteAccountRequestStruct,
+        peer: SocketAddr,
+    ) -> AuthResponse {
+        let mut conn = self.conn().await;
+
+        let Some(session) = session_guard::authorize_session(&mut conn, &req.token) else {
+            return AuthResponse::error(ErrorCode::Unauthorized);
+        };
+
+        let user = match users_db::UsersDb::find_user_by_id(&mut conn, session.user_id) {
+            Ok(user) => user,
+            Err(_) => return AuthResponse::error(ErrorCode::UserNotFound),
+        };
+
+        let keys = [ThrottleKey::login(&user.login), ThrottleKey::addr(peer)];
+        if let Some(until) = self.throttle.check(&keys, Utc::now()) {
+            return AuthResponse::locked(until.timestamp());
         }
+
+        let Some(verifier) = user.srp_verifier else {
+            return AuthResponse::error(ErrorCode::UserNotFound);
+        };
+
+        let srp_session = match self.verify_proof(
+            &mut conn,
+            user.id,
+            &verifier,
+            req.challenge_id,
+            &req.client_proof,
+            &keys,
+        ) {
+            Ok(session) => session,
+            Err(resp) => return resp,
+        };
+
+        let purge_at = Utc::now() + self.deletion.grace;
+        if let Err(err) =
+            users_db::UsersDb::schedule_deletion(&mut conn, user.id, purge_at.naive_utc())
+        {
+            return AuthResponse::error(ErrorCode::from(&err));
+        }
+        self.registry.revoke_user(user.id, None);
+
+        let mut resp = AuthResponse::ok(String::new());
+        resp.server_proof = srp_session.proof().to_vec();
+        resp.expires_at = purge_at.timestamp();
+        resp
     }
 }
 
 #[async_trait]
 impl Handler for AuthHandler {
-    type Codec = ServerEncriptedCodec;
+    type Codec = ServerAuthCodec;
 
     async fn serve_route(
         &mut self,
-        _client_meta: (
+        client_meta: (
             SocketAddr,
             &mut Option<Sender<Arc<Mutex<dyn Handler<Codec = Self::Codec>>>>>,
         ),
@@ -146,12 +453,22 @@ impl Handler for AuthHandler {
         match s_type {
             ProtoLinkSType::AuthRequest => {
                 let req = s_type::from_slice::<AuthRequestStruct>(data.as_mut())?;
-                let resp = self.auth_request(req).await;
+                let resp = self.auth_request(req, client_meta.0).await;
+                Ok(s_type::to_vec(&resp).unwrap())
+            }
+            ProtoLinkSType::AuthProof => {
+                let proof = s_type::from_slice::<AuthProof>(data.as_mut())?;
+                let resp = self.auth_proof(proof, client_meta.0).await;
+                Ok(s_type::to_vec(&resp).unwrap())
+            }
+            ProtoLinkSType::ChangePassword => {
+                let req = s_type::from_slice::<ChangePasswordRequestStruct>(data.as_mut())?;
+                let resp = self.change_password(req, client_meta.0).await;
                 Ok(s_type::to_vec(&resp).unwrap())
             }
-            ProtoLinkSType::AuthChallenge => {
-                let chal = s_type::from_slice::<AuthChallenge>(data.as_mut())?;
-                let resp = self.auth_challenge(chal).await;
+            ProtoLinkSType::DeleteAccount => {
+                let req = s_type::from_slice::<DeleteAccountRequestStruct>(data.as_mut())?;
+                let resp = self.delete_account(req, client_meta.0).await;
                 Ok(s_type::to_vec(&resp).unwrap())
             }
             _ => Err("Malformed request".into()),
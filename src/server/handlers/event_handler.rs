@@ -0,0 +1,112 @@
+use crate::server::access_keyring::AccessKeyring;
+use crate::server::event_hub::EventHub;
+use crate::server::server_encrypted_codec::ServerEncriptedCodec;
+use crate::server::session_guard::{self, AuthorizedSession};
+use crate::server::session_registry::SessionRegistry;
+use crate::structures::error_code::ErrorCode;
+use crate::structures::protolink_stype::{
+    PollEventsRequestStruct, PollEventsResponse, ProtoLinkSType,
+};
+
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use diesel::MysqlConnection;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tfserver::async_trait::async_trait;
+use tfserver::server::handler::Handler;
+use tfserver::structures::s_type;
+use tfserver::structures::s_type::StructureType;
+use tfserver::structures::traffic_proc::TrafficProcessorHolder;
+use tfserver::structures::transport::Transport;
+use tfserver::tokio::sync::{oneshot::Sender, Mutex};
+use tfserver::tokio_util::bytes::BytesMut;
+use tfserver::tokio_util::codec::Framed;
+
+/// Hands out what other handlers published to `EventHub`
+pub struct EventHandler {
+    db: Arc<Mutex<Pool<ConnectionManager<MysqlConnection>>>>,
+    events: Arc<EventHub>,
+    registry: Arc<SessionRegistry>,
+    keyring: Arc<AccessKeyring>,
+}
+
+impl EventHandler {
+    pub fn new(
+        db: Arc<Mutex<Pool<ConnectionManager<MysqlConnection>>>>,
+        events: Arc<EventHub>,
+        registry: Arc<SessionRegistry>,
+        keyring: Arc<AccessKeyring>,
+    ) -> Self {
+        Self {
+            db,
+            events,
+            registry,
+            keyring,
+        }
+    }
+
+    async fn conn(&self) -> PooledConnection<ConnectionManager<MysqlConnection>> {
+        self.db.lock().await.get().expect("DB connection failed")
+    }
+
+    fn authorize(
+        &self,
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        token: &str,
+    ) -> Option<AuthorizedSession> {
+        session_guard::authorize(conn, &self.keyring, &self.registry, token)
+    }
+
+    pub async fn poll_events(&self, req: PollEventsRequestStruct) -> PollEventsResponse {
+        let mut conn = self.conn().await;
+
+        let Some(session) = self.authorize(&mut conn, &req.token) else {
+            return PollEventsResponse::error(ErrorCode::Unauthorized);
+        };
+
+        let polled = self.events.poll(session.user_id, req.after);
+        PollEventsResponse::ok(polled.events, polled.cursor, polled.missed)
+    }
+}
+
+#[async_trait]
+impl Handler for EventHandler {
+    type Codec = ServerEncriptedCodec;
+
+    async fn serve_route(
+        &mut self,
+        _client_meta: (
+            SocketAddr,
+            &mut Option<Sender<Arc<Mutex<dyn Handler<Codec = Self::Codec>>>>>,
+        ),
+        s_type: Box<dyn StructureType>,
+        mut data: BytesMut,
+    ) -> Result<Vec<u8>, Vec<u8>> {
+        let s_type = s_type
+            .as_any()
+            .downcast_ref::<ProtoLinkSType>()
+            .unwrap()
+            .clone();
+        match s_type {
+            ProtoLinkSType::PollEvents => {
+                let req = s_type::from_slice::<PollEventsRequestStruct>(data.as_mut())?;
+                let resp = self.poll_events(req).await;
+                Ok(s_type::to_vec(&resp).unwrap())
+            }
+            _ => Err("Malformed request".into()),
+        }
+    }
+
+    async fn accept_stream(
+        &mut self,
+        _addr: SocketAddr,
+        _stream: (
+            Framed<Transport, Self::Codec>,
+            TrafficProcessorHolder<Self::Codec>,
+        ),
+    ) {
+        todo!()
+    }
+}
@@ -0,0 +1,210 @@
+use crate::server::access_keyring::AccessKeyring;
+use crate::server::db::invites_db::{InviteRow, InvitesDb};
+use crate::server::db::users_db::UsersDb;
+use crate::server::invite_policy::{InviteIssuers, InvitePolicy};
+use crate::server::server_encrypted_codec::ServerEncriptedCodec;
+use crate::server::session_guard;
+use crate::server::session_registry::SessionRegistry;
+use crate::structures::error_code::ErrorCode;
+use crate::structures::protolink_stype::{
+    CreateInviteRequestStruct, CreateInviteResponse, InviteHandlerResponseStruct,
+    InviteInfoStruct, ListInvitesRequestStruct, ListInvitesResponse, ProtoLinkSType,
+    RevokeInviteRequestStruct,
+};
+use crate::util::crypto::invite_util::{generate_invite_code, hash_invite_code};
+
+use chrono::{Duration, Utc};
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use diesel::MysqlConnection;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tfserver::async_trait::async_trait;
+use tfserver::server::handler::Handler;
+use tfserver::structures::s_type;
+use tfserver::structures::s_type::StructureType;
+use tfserver::structures::traffic_proc::TrafficProcessorHolder;
+use tfserver::structures::transport::Transport;
+use tfserver::tokio::sync::{oneshot::Sender, Mutex};
+use tfserver::tokio_util::bytes::BytesMut;
+use tfserver::tokio_util::codec::Framed;
+
+/// Creating, listing and revoking the invites `RegisterHandler` asks for in
+/// `RegistrationMode::InviteOnly`
+pub struct InviteHandler {
+    db: Arc<Mutex<Pool<ConnectionManager<MysqlConnection>>>>,
+    policy: InvitePolicy,
+    registry: Arc<SessionRegistry>,
+    keyring: Arc<AccessKeyring>,
+}
+
+/// Who made an invite request
+struct Caller {
+    user_id: u64,
+    is_admin: bool,
+}
+
+impl InviteHandler {
+    pub fn new(
+        db: Arc<Mutex<Pool<ConnectionManager<MysqlConnection>>>>,
+        policy: InvitePolicy,
+        registry: Arc<SessionRegistry>,
+        keyring: Arc<AccessKeyring>,
+    ) -> Self {
+        Self {
+            db,
+            policy,
+            registry,
+            keyring,
+        }
+    }
+
+    async fn conn(&self) -> PooledConnection<ConnectionManager<MysqlConnection>> {
+        self.db.lock().await.get().expect("DB connection failed")
+    }
+
+    fn caller(
+        &self,
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        token: &str,
+    ) -> Option<Caller> {
+        let session = session_guard::authorize(conn, &self.keyring, &self.registry, token)?;
+        let user = UsersDb::find_user_by_id(conn, session.user_id).ok()?;
+        Some(Caller {
+            user_id: user.id,
+            is_admin: user.is_admin,
+        })
+    }
+
+    fn invite_info(row: InviteRow) -> InviteInfoStruct {
+        InviteInfoStruct {
+            id: row.id,
+            created_by: row.created_by,
+            max_uses: row.max_uses,
+            uses: row.uses,
+            expires_at: row.expires_at.and_utc().timestamp(),
+            created_at: row.created_at.and_utc().timestamp(),
+            revoked: row.revoked_at.is_some(),
+        }
+    }
+
+    pub async fn create_invite(&self, req: CreateInviteRequestStruct) -> CreateInviteResponse {
+        let mut conn = self.conn().await;
+
+        let Some(caller) = self.caller(&mut conn, &req.token) else {
+            return CreateInviteResponse::error(ErrorCode::Unauthorized);
+        };
+        if self.policy.issuers == InviteIssuers::Admins && !caller.is_admin {
+            return CreateInviteResponse::error(ErrorCode::Forbidden);
+        }
+
+        let ttl = match req.valid_for_secs {
+            0 => self.policy.default_ttl,
+            secs => Duration::seconds(secs.min(self.policy.max_ttl.num_seconds() as u64) as i64),
+        };
+        let now = Utc::now().naive_utc();
+        let code = generate_invite_code();
+        let mut row = InviteRow {
+            id: 0,
+            code_hash: hash_invite_code(&code),
+            created_by: caller.user_id,
+            max_uses: req.max_uses.clamp(1, self.policy.max_uses),
+            uses: 0,
+            expires_at: now + ttl,
+            created_at: now,
+            revoked_at: None,
+        };
+
+        match InvitesDb::create_invite(&mut conn, &row) {
+            Ok(id) => {
+                row.id = id;
+                CreateInviteResponse::ok(code, Self::invite_info(row))
+            }
+            Err(err) => CreateInviteResponse::error(ErrorCode::from(&err)),
+        }
+    }
+
+    pub async fn list_invites(&self, req: ListInvitesRequestStruct) -> ListInvitesResponse {
+        let mut conn = self.conn().await;
+
+        let Some(caller) = self.caller(&mut conn, &req.token) else {
+            return ListInvitesResponse::error(ErrorCode::Unauthorized);
+        };
+
+        let creator = (!caller.is_admin).then_some(caller.user_id);
+        match InvitesDb::find_invites(&mut conn, creator) {
+            Ok(rows) => ListInvitesResponse::ok(rows.into_iter().map(Self::invite_info).collect()),
+            Err(err) => ListInvitesResponse::error(ErrorCode::from(&err)),
+        }
+    }
+
+    pub async fn revoke_invite(
+        &self,
+        req: RevokeInviteRequestStruct,
+    ) -> InviteHandlerResponseStruct {
+        let mut conn = self.conn().await;
+
+        let Some(caller) = self.caller(&mut conn, &req.token) else {
+            return InviteHandlerResponseStruct::error(ErrorCode::Unauthorized);
+        };
+
+        let creator = (!caller.is_admin).then_some(caller.user_id);
+        let now = Utc::now().naive_utc();
+        match InvitesDb::revoke_invite(&mut conn, req.invite_id, creator, now) {
+            Ok(0) => InviteHandlerResponseStruct::error(ErrorCode::InviteNotFound),
+            Ok(_) => InviteHandlerResponseStruct::ok(),
+            Err(err) => InviteHandlerResponseStruct::error(ErrorCode::from(&err)),
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for InviteHandler {
+    type Codec = ServerEncriptedCodec;
+
+    async fn serve_route(
+        &mut self,
+        _client_meta: (
+            SocketAddr,
+            &mut Option<Sender<Arc<Mutex<dyn Handler<Codec = Self::Codec>>>>>,
+        ),
+        s_type: Box<dyn StructureType>,
+        mut data: BytesMut,
+    ) -> Result<Vec<u8>, Vec<u8>> {
+        let s_type = s_type
+            .as_any()
+            .downcast_ref::<ProtoLinkSType>()
+            .unwrap()
+            .clone();
+        match s_type {
+            ProtoLinkSType::CreateInvite => {
+                let req = s_type::from_slice::<CreateInviteRequestStruct>(data.as_mut())?;
+                let resp = self.create_invite(req).await;
+                Ok(s_type::to_vec(&resp).unwrap())
+            }
+            ProtoLinkSType::ListInvites => {
+                let req = s_type::from_slice::<ListInvitesRequestStruct>(data.as_mut())?;
+                let resp = self.list_invites(req).await;
+                Ok(s_type::to_vec(&resp).unwrap())
+            }
+            ProtoLinkSType::RevokeInvite => {
+                let req = s_type::from_slice::<RevokeInviteRequestStruct>(data.as_mut())?;
+                let resp = self.revoke_invite(req).await;
+                Ok(s_type::to_vec(&resp).unwrap())
+            }
+            _ => Err("Malformed request".into()),
+        }
+    }
+
+    async fn accept_stream(
+        &mut self,
+        _addr: SocketAddr,
+        _stream: (
+            Framed<Transport, Self::Codec>,
+            TrafficProcessorHolder<Self::Codec>,
+        ),
+    ) {
+        todo!()
+    }
+}
@@ -1,3 +1,9 @@
 pub mod register_handler;
 pub mod chat_handler;
-mod auth_handler;
\ No newline at end of file
+pub mod auth_handler;
+pub mod session_handler;
+pub mod admin_handler;
+pub mod two_factor_handler;
+pub mod profile_handler;
+pub mod event_handler;
+pub mod invite_handler;
@@ -0,0 +1,303 @@
+use crate::server::access_keyring::AccessKeyring;
+use crate::server::db::chats_db::ChatsDb;
+use crate::server::db::users_db::{ProfileChangeset, ProfileRow, UsersDb};
+use crate::server::event_hub::EventHub;
+use crate::server::registration_policy::RegistrationPolicy;
+use crate::server::server_encrypted_codec::ServerEncriptedCodec;
+use crate::server::session_guard::{self, AuthorizedSession};
+use crate::server::session_registry::SessionRegistry;
+use crate::structures::error_code::ErrorCode;
+use crate::structures::protolink_stype::{
+    GetUserProfileRequestStruct, GetUsersBatchRequestStruct, ProtoLinkSType, SearchMode,
+    SearchUsersRequestStruct, SearchUsersResponse, ServerEventStruct, UpdateProfileRequestStruct,
+    UserProfileResponse, UserProfileStruct, UsersBatchResponse,
+};
+
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use diesel::MysqlConnection;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tfserver::async_trait::async_trait;
+use tfserver::server::handler::Handler;
+use tfserver::structures::s_type;
+use tfserver::structures::s_type::StructureType;
+use tfserver::structures::traffic_proc::TrafficProcessorHolder;
+use tfserver::structures::transport::Transport;
+use tfserver::tokio::sync::{oneshot::Sender, Mutex};
+use tfserver::tokio_util::bytes::BytesMut;
+use tfserver::tokio_util::codec::Framed;
+
+/// Characters, not bytes. `users.bio` is TEXT, so this is the only limit.
+const MAX_BIO_LEN: usize = 1000;
+/// `users.avatar_ref` is VARCHAR(255)
+const MAX_AVATAR_REF_LEN: usize = 255;
+/// Ids per `GetUsersBatch`
+const MAX_BATCH_SIZE: usize = 100;
+/// Shorter queries would page through most of the directory
+const MIN_QUERY_LEN: usize = 2;
+const MAX_QUERY_LEN: usize = 64;
+/// Results per `SearchUsers` page, whatever the client asks for
+const MAX_SEARCH_RESULTS: u32 = 50;
+const DEFAULT_SEARCH_RESULTS: u32 = 20;
+
+/// Reading profiles of any user and changing one's own
+pub struct ProfileHandler {
+    db: Arc<Mutex<Pool<ConnectionManager<MysqlConnection>>>>,
+    policy: RegistrationPolicy,
+    events: Arc<EventHub>,
+    registry: Arc<SessionRegistry>,
+    keyring: Arc<AccessKeyring>,
+}
+
+impl ProfileHandler {
+    pub fn new(
+        db: Arc<Mutex<Pool<ConnectionManager<MysqlConnection>>>>,
+        policy: RegistrationPolicy,
+        events: Arc<EventHub>,
+        registry: Arc<SessionRegistry>,
+        keyring: Arc<AccessKeyring>,
+    ) -> Self {
+        Self {
+            db,
+            policy,
+            events,
+            registry,
+            keyring,
+        }
+    }
+
+    async fn conn(&self) -> PooledConnection<ConnectionManager<MysqlConnection>> {
+        self.db.lock().await.get().expect("DB connection failed")
+    }
+
+    fn authorize(
+        &self,
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        token: &str,
+    ) -> Option<AuthorizedSession> {
+        session_guard::authorize(conn, &self.keyring, &self.registry, token)
+    }
+
+    fn profile(row: ProfileRow) -> UserProfileStruct {
+        if row.deleted_at.is_some() {
+            return UserProfileStruct {
+                user_id: row.id,
+                login: String::new(),
+                display_name: String::new(),
+                bio: String::new(),
+                avatar_ref: None,
+                created_at: row.created_at.and_utc().timestamp(),
+                deleted: true,
+            };
+        }
+        UserProfileStruct {
+            user_id: row.id,
+            login: row.login,
+            display_name: row.display_name.unwrap_or(row.name),
+            bio: row.bio.unwrap_or_default(),
+            avatar_ref: row.avatar_ref,
+            created_at: row.created_at.and_utc().timestamp(),
+            deleted: false,
+        }
+    }
+
+    /// Checks the requested changes, empty strings become NULL
+    fn changeset(&self, req: UpdateProfileRequestStruct) -> Result<ProfileChangeset, ErrorCode> {
+        let mut changes = ProfileChangeset::default();
+
+        if let Some(display_name) = req.display_name {
+            if !display_name.is_empty() {
+                self.policy
+                    .validate_name(&display_name)
+                    .map_err(|err| ErrorCode::from(&err))?;
+            }
+            changes.display_name = Some(Some(display_name).filter(|v| !v.is_empty()));
+        }
+        if let Some(bio) = req.bio {
+            if bio.chars().count() > MAX_BIO_LEN {
+                return Err(ErrorCode::BioTooLong);
+            }
+            if bio.chars().any(|c| c.is_control() && c != '\n' && c != '\t') {
+                return Err(ErrorCode::BioInvalidChars);
+            }
+            changes.bio = Some(Some(bio).filter(|v| !v.is_empty()));
+        }
+        if let Some(avatar_ref) = req.avatar_ref {
+            if avatar_ref.len() > MAX_AVATAR_REF_LEN
+                || !avatar_ref.bytes().all(|b| b.is_ascii_graphic())
+            {
+                return Err(ErrorCode::InvalidAvatarRef);
+            }
+            changes.avatar_ref = Some(Some(avatar_ref).filter(|v| !v.is_empty()));
+        }
+        changes.discoverable = req.discoverable;
+        Ok(changes)
+    }
+
+    pub async fn get_user_profile(&self, req: GetUserProfileRequestStruct) -> UserProfileResponse {
+        let mut conn = self.conn().await;
+
+        if self.authorize(&mut conn, &req.token).is_none() {
+            return UserProfileResponse::error(ErrorCode::Unauthorized);
+        }
+
+        match UsersDb::find_profile(&mut conn, req.user_id) {
+            Ok(row) => UserProfileResponse::ok(Some(Self::profile(row))),
+            Err(diesel::result::Error::NotFound) => {
+                UserProfileResponse::error(ErrorCode::UserNotFound)
+            }
+            Err(err) => UserProfileResponse::error(ErrorCode::from(&err)),
+        }
+    }
+
+    pub async fn get_users_batch(&self, req: GetUsersBatchRequestStruct) -> UsersBatchResponse {
+        if req.user_ids.len() > MAX_BATCH_SIZE {
+            return UsersBatchResponse::error(ErrorCode::BatchTooLarge);
+        }
+
+        let mut conn = self.conn().await;
+
+        if self.authorize(&mut conn, &req.token).is_none() {
+            return UsersBatchResponse::error(ErrorCode::Unauthorized);
+        }
+
+        match UsersDb::find_profiles(&mut conn, &req.user_ids) {
+            Ok(rows) => UsersBatchResponse::ok(rows.into_iter().map(Self::profile).collect()),
+            Err(err) => UsersBatchResponse::error(ErrorCode::from(&err)),
+        }
+    }
+
+    /// One page of the user directory. Users who opted out, deleted accounts and
+    /// accounts pending deletion never show up.
+    pub async fn search_users(&self, req: SearchUsersRequestStruct) -> SearchUsersResponse {
+        let query = req.query.trim();
+        let len = query.chars().count();
+        if len < MIN_QUERY_LEN {
+            return SearchUsersResponse::error(ErrorCode::QueryTooShort);
+        }
+        if len > MAX_QUERY_LEN {
+            return SearchUsersResponse::error(ErrorCode::QueryTooLong);
+        }
+        let limit = match req.limit {
+            0 => DEFAULT_SEARCH_RESULTS,
+            n => n.min(MAX_SEARCH_RESULTS),
+        } as usize;
+
+        let mut conn = self.conn().await;
+
+        if self.authorize(&mut conn, &req.token).is_none() {
+            return SearchUsersResponse::error(ErrorCode::Unauthorized);
+        }
+
+        // One extra row tells whether there is another page
+        let mut rows = match UsersDb::search_users(
+            &mut conn,
+            query,
+            req.mode == SearchMode::Substring,
+            req.cursor.as_deref(),
+            limit as i64 + 1,
+        ) {
+            Ok(rows) => rows,
+            Err(err) => return SearchUsersResponse::error(ErrorCode::from(&err)),
+        };
+        let next_cursor = if rows.len() > limit {
+            rows.truncate(limit);
+            rows.last().map(|row| row.login.clone())
+        } else {
+            None
+        };
+
+        SearchUsersResponse::ok(rows.into_iter().map(Self::profile).collect(), next_cursor)
+    }
+
+    /// Saves the changes and tells everyone sharing a chat with the user
+    pub async fn update_profile(&self, req: UpdateProfileRequestStruct) -> UserProfileResponse {
+        let mut conn = self.conn().await;
+
+        let Some(session) = self.authorize(&mut conn, &req.token) else {
+            return UserProfileResponse::error(ErrorCode::Unauthorized);
+        };
+
+        let changes = match self.changeset(req) {
+            Ok(changes) => changes,
+            Err(code) => return UserProfileResponse::error(code),
+        };
+        if let Err(err) = UsersDb::update_profile(&mut conn, session.user_id, &changes) {
+            return UserProfileResponse::error(ErrorCode::from(&err));
+        }
+
+        let profile = match UsersDb::find_profile(&mut conn, session.user_id) {
+            Ok(row) => Self::profile(row),
+            Err(err) => return UserProfileResponse::error(ErrorCode::from(&err)),
+        };
+
+        if !changes.is_empty() {
+            let mut recipients =
+                ChatsDb::find_chat_peers(&mut conn, session.user_id).unwrap_or_default();
+            if !recipients.contains(&session.user_id) {
+                recipients.push(session.user_id);
+            }
+            self.events
+                .publish(&recipients, ServerEventStruct::ProfileUpdated(profile.clone()));
+        }
+
+        UserProfileResponse::ok(Some(profile))
+    }
+}
+
+#[async_trait]
+impl Handler for ProfileHandler {
+    type Codec = ServerEncriptedCodec;
+
+    async fn serve_route(
+        &mut self,
+        _client_meta: (
+            SocketAddr,
+            &mut Option<Sender<Arc<Mutex<dyn Handler<Codec = Self::Codec>>>>>,
+        ),
+        s_type: Box<dyn StructureType>,
+        mut data: BytesMut,
+    ) -> Result<Vec<u8>, Vec<u8>> {
+        let s_type = s_type
+            .as_any()
+            .downcast_ref::<ProtoLinkSType>()
+            .unwrap()
+            .clone();
+        match s_type {
+            ProtoLinkSType::GetUserProfile => {
+                let req = s_type::from_slice::<GetUserProfileRequestStruct>(data.as_mut())?;
+                let resp = self.get_user_profile(req).await;
+                Ok(s_type::to_vec(&resp).unwrap())
+            }
+            ProtoLinkSType::GetUsersBatch => {
+                let req = s_type::from_slice::<GetUsersBatchRequestStruct>(data.as_mut())?;
+                let resp = self.get_users_batch(req).await;
+                Ok(s_type::to_vec(&resp).unwrap())
+            }
+            ProtoLinkSType::UpdateProfile => {
+                let req = s_type::from_slice::<UpdateProfileRequestStruct>(data.as_mut())?;
+                let resp = self.update_profile(req).await;
+                Ok(s_type::to_vec(&resp).unwrap())
+            }
+            ProtoLinkSType::SearchUsers => {
+                let req = s_type::from_slice::<SearchUsersRequestStruct>(data.as_mut())?;
+                let resp = self.search_users(req).await;
+                Ok(s_type::to_vec(&resp).unwrap())
+            }
+            _ => Err("Malformed request".into()),
+        }
+    }
+
+    async fn accept_stream(
+        &mut self,
+        _addr: SocketAddr,
+        _stream: (
+            Framed<Transport, Self::Codec>,
+            TrafficProcessorHolder<Self::Codec>,
+        ),
+    ) {
+        todo!()
+    }
+}
@@ -1,14 +1,22 @@
-use crate::server::db::users_db;
+use crate::server::db::invites_db::InvitesDb;
 use crate::server::db::users_db::UsersDb;
+use crate::server::invite_policy::{InvitePolicy, RegistrationMode};
+use crate::server::registration_policy::RegistrationPolicy;
+use crate::server::server_auth_codec::ServerAuthCodec;
+use crate::structures::error_code::ErrorCode;
 use crate::structures::protolink_stype::{
-    AuthRequestStruct, AuthResponse, ProtoLinkSType, RegisterRequestStruct,
+    AuthResponse, KdfParamsRequestStruct, KdfParamsResponse, ProtoLinkSType, RegisterRequestStruct,
 };
+use crate::util::crypto::invite_util::hash_invite_code;
+use crate::util::crypto::kdf_util::{KdfParams, KDF_SALT_LEN};
+use sha2::{Digest, Sha256};
+use chrono::Utc;
 use diesel::r2d2::{ConnectionManager, Pool};
-use diesel::MysqlConnection;
-use std::net::{SocketAddr, TcpStream};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::{Connection, MysqlConnection};
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tfserver::async_trait::async_trait;
-use tfserver::codec::length_delimited::LengthDelimitedCodec;
 use tfserver::server::handler::Handler;
 use tfserver::structures::s_type;
 use tfserver::structures::s_type::StructureType;
@@ -18,48 +26,90 @@ use tfserver::tokio::sync::Mutex;
 use tfserver::tokio_util::bytes::BytesMut;
 use tfserver::tokio_util::codec::{Framed};
 
-pub struct AuthHandler {
+pub struct RegisterHandler {
     db_connection: Arc<Mutex<Pool<ConnectionManager<MysqlConnection>>>>,
+    /// Per-process key for the decoy parameters handed out for unknown logins
+    decoy_key: [u8; 32],
+    policy: RegistrationPolicy,
+    invites: InvitePolicy,
 }
-impl AuthHandler {
-    pub fn new(db_connection: Arc<Mutex<Pool<ConnectionManager<MysqlConnection>>>>) -> Self {
-        Self { db_connection }
+impl RegisterHandler {
+    pub fn new(
+        db_connection: Arc<Mutex<Pool<ConnectionManager<MysqlConnection>>>>,
+        policy: RegistrationPolicy,
+        invites: InvitePolicy,
+    ) -> Self {
+        Self {
+            db_connection,
+            decoy_key: rand::random(),
+            policy,
+            invites,
+        }
+    }
+
+    /// Stable per-login parameters so unknown logins look like Argon2id accounts
+    fn decoy_params(&self, login: &str) -> KdfParams {
+        let mut hasher = Sha256::new();
+        hasher.update(self.decoy_key);
+        hasher.update(login.as_bytes());
+        KdfParams::with_salt(hasher.finalize()[..KDF_SALT_LEN].to_vec())
+    }
+
+    async fn kdf_params_request(&self, request: KdfParamsRequestStruct) -> KdfParamsResponse {
+        let mut conn = self.db_connection.lock().await.get().unwrap();
+        let params = match UsersDb::find_user_by_login(&mut conn, &request.login) {
+            Ok(user) => KdfParams::from_db_string(user.kdf_params.as_deref())
+                .unwrap_or_else(|| self.decoy_params(&request.login)),
+            Err(_) => self.decoy_params(&request.login),
+        };
+        KdfParamsResponse::new(request.login, params)
     }
-    
 
+    /// The unique index on `users.login_key` decides between concurrent registrations
+    /// of the same login, there is no separate existence check.
+    ///
+    /// In `RegistrationMode::InviteOnly` the invite is redeemed in the transaction that
+    /// creates the account, a refused registration leaves it untouched.
     async fn register_request(&self, request: RegisterRequestStruct) -> AuthResponse {
+        if let Err(err) = self.policy.validate(&request) {
+            return AuthResponse::error_with(ErrorCode::from(&err), &err.to_string());
+        }
+        let invite_hash = match (self.invites.mode, &request.invite_code) {
+            (RegistrationMode::Open, _) => None,
+            (RegistrationMode::InviteOnly, Some(code)) if !code.trim().is_empty() => {
+                Some(hash_invite_code(code))
+            }
+            (RegistrationMode::InviteOnly, _) => {
+                return AuthResponse::error(ErrorCode::InviteRequired)
+            }
+        };
         let mut conn = self.db_connection.lock().await.get().unwrap();
-        if let Ok(exists) = users_db::UsersDb::is_user_exists(&mut conn, request.login.as_str()) {
-            if exists {
-                return AuthResponse {
-                    s_type: ProtoLinkSType::AuthResponse,
-                    success: false,
-                    message: "User already exists".into(),
-                };
+        let created = conn.transaction(|conn| {
+            if let Some(hash) = &invite_hash {
+                InvitesDb::redeem(conn, hash, Utc::now().naive_utc())?;
             }
-            if let Ok(res) = UsersDb::create_user(
-                &mut conn,
+            UsersDb::create_user(
+                conn,
                 request.login,
                 request.name,
-                request.password_hash_sha256_hkdf
-            ) {
-                return AuthResponse {
-                    s_type: ProtoLinkSType::AuthResponse,
-                    success: true,
-                    message: "".into(),
-                };
+                request.srp_salt,
+                request.srp_verifier,
+                request.kdf_params.to_db_string(),
+            )
+        });
+        match created {
+            Ok(_) => AuthResponse::ok("".into()),
+            Err(DieselError::NotFound) => AuthResponse::error(ErrorCode::InvalidInvite),
+            Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                AuthResponse::error(ErrorCode::LoginTaken)
             }
-        }
-        AuthResponse {
-            s_type: ProtoLinkSType::AuthResponse,
-            success: false,
-            message: "internal database error".into(),
+            Err(err) => AuthResponse::error(ErrorCode::from(&err)),
         }
     }
 }
 #[async_trait]
-impl Handler for AuthHandler {
-    type Codec = LengthDelimitedCodec;
+impl Handler for RegisterHandler {
+    type Codec = ServerAuthCodec;
 
     async fn serve_route(
         &mut self,
@@ -67,7 +117,7 @@ impl Handler for AuthHandler {
             SocketAddr,
             &mut Option<
                 tfserver::tokio::sync::oneshot::Sender<
-                    Arc<Mutex<(dyn Handler<Codec = LengthDelimitedCodec> + 'static)>>,
+                    Arc<Mutex<(dyn Handler<Codec = ServerAuthCodec> + 'static)>>,
                 >,
             >,
         ),
@@ -90,7 +140,12 @@ impl Handler for AuthHandler {
                     return Ok(s_type::to_vec(&resp).unwrap());
                 }
             }
-            
+            ProtoLinkSType::KdfParamsRequest => {
+                let request = s_type::from_slice::<KdfParamsRequestStruct>(data.as_mut())?;
+                let resp = self.kdf_params_request(request).await;
+                return Ok(s_type::to_vec(&resp).unwrap());
+            }
+
             _ => {
                 return Err("Malformed request".into());
             }
@@ -101,8 +156,8 @@ impl Handler for AuthHandler {
         &mut self,
         add: SocketAddr,
         stream: (
-            Framed<Transport, LengthDelimitedCodec>,
-            TrafficProcessorHolder<LengthDelimitedCodec>,
+            Framed<Transport, ServerAuthCodec>,
+            TrafficProcessorHolder<ServerAuthCodec>,
         ),
     ) {
         todo!()
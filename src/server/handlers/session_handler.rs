@@ -0,0 +1,294 @@
+use crate::server::access_keyring::AccessKeyring;
+use crate::server::db::devices_db::{DeviceRow, DevicesDb};
+use crate::server::db::tokens_db::{TokenRow, TokensDb};
+use crate::server::server_encrypted_codec::ServerEncriptedCodec;
+use crate::server::session_guard::{self, AuthorizedSession};
+use crate::server::session_registry::SessionRegistry;
+use crate::structures::error_code::ErrorCode;
+use crate::structures::protolink_stype::{
+    DeviceInfoStruct, ListDevicesRequestStruct, ListDevicesResponse, ListSessionsRequestStruct,
+    ListSessionsResponse, LogoutAllDevicesRequestStruct, LogoutRequestStruct, ProtoLinkSType,
+    RefreshAccessRequestStruct, RefreshAccessResponse, RevokeDeviceRequestStruct,
+    RevokeSessionRequestStruct, SessionHandlerResponseStruct, SessionInfoStruct,
+};
+
+use chrono::Utc;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use diesel::MysqlConnection;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tfserver::async_trait::async_trait;
+use tfserver::server::handler::Handler;
+use tfserver::structures::s_type;
+use tfserver::structures::s_type::StructureType;
+use tfserver::structures::traffic_proc::TrafficProcessorHolder;
+use tfserver::structures::transport::Transport;
+use tfserver::tokio::sync::{oneshot::Sender, Mutex};
+use tfserver::tokio_util::bytes::BytesMut;
+use tfserver::tokio_util::codec::Framed;
+
+/// Lets users see their sessions and devices and end them
+pub struct SessionHandler {
+    db: Arc<Mutex<Pool<ConnectionManager<MysqlConnection>>>>,
+    registry: Arc<SessionRegistry>,
+    keyring: Arc<AccessKeyring>,
+}
+
+impl SessionHandler {
+    pub fn new(
+        db: Arc<Mutex<Pool<ConnectionManager<MysqlConnection>>>>,
+        registry: Arc<SessionRegistry>,
+        keyring: Arc<AccessKeyring>,
+    ) -> Self {
+        Self {
+            db,
+            registry,
+            keyring,
+        }
+    }
+
+    async fn conn(&self) -> PooledConnection<ConnectionManager<MysqlConnection>> {
+        self.db.lock().await.get().expect("DB connection failed")
+    }
+
+    fn authorize(
+        &self,
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        token: &str,
+    ) -> Option<AuthorizedSession> {
+        session_guard::authorize(conn, &self.keyring, &self.registry, token)
+    }
+
+    /// Stops the access tokens and live connections of an ended session
+    fn revoke_access(&self, session_id: u64) {
+        self.registry.revoke_session(session_id);
+    }
+
+    fn session_info(row: TokenRow, current_id: u64) -> SessionInfoStruct {
+        SessionInfoStruct {
+            id: row.id,
+            client_label: row.client_label,
+            peer_addr: row.peer_addr,
+            created_at: row.created_at.and_utc().timestamp(),
+            last_used_at: row.last_used_at.and_utc().timestamp(),
+            expires_at: row.expires_at.and_utc().timestamp(),
+            current: row.id == current_id,
+            device_id: row.device_id,
+        }
+    }
+
+    fn device_info(row: DeviceRow, current_id: Option<u64>) -> DeviceInfoStruct {
+        DeviceInfoStruct {
+            id: row.id,
+            label: row.label,
+            platform: row.platform,
+            public_key: row.public_key,
+            created_at: row.created_at.and_utc().timestamp(),
+            last_seen_at: row.last_seen_at.and_utc().timestamp(),
+            current: Some(row.id) == current_id,
+        }
+    }
+
+    pub async fn list_sessions(&self, req: ListSessionsRequestStruct) -> ListSessionsResponse {
+        let mut conn = self.conn().await;
+
+        let Some(current) = self.authorize(&mut conn, &req.token) else {
+            return ListSessionsResponse::error(ErrorCode::Unauthorized);
+        };
+
+        match TokensDb::find_tokens_by_user_id(&mut conn, current.user_id) {
+            Ok(rows) => ListSessionsResponse::ok(
+                rows.into_iter()
+                    .map(|row| Self::session_info(row, current.id))
+                    .collect(),
+            ),
+            Err(err) => ListSessionsResponse::error(ErrorCode::from(&err)),
+        }
+    }
+
+    /// Signs a new access token for a live session. Takes the session token itself, so a
+    /// leaked access token can't be used to extend its own life.
+    pub async fn refresh_access(&self, req: RefreshAccessRequestStruct) -> RefreshAccessResponse {
+        let mut conn = self.conn().await;
+
+        let Some(session) = session_guard::resume(&mut conn, &req.token) else {
+            return RefreshAccessResponse::error(ErrorCode::Unauthorized);
+        };
+
+        let session_expires_at = session.expires_at.and_utc();
+        let (access_token, expires_at) = self.keyring.issue(
+            session.user_id,
+            session.id,
+            session.device_id,
+            session_expires_at,
+            Utc::now(),
+        );
+        RefreshAccessResponse::ok(access_token, expires_at.timestamp())
+    }
+
+    pub async fn logout(&self, req: LogoutRequestStruct) -> SessionHandlerResponseStruct {
+        let mut conn = self.conn().await;
+
+        let Some(current) = self.authorize(&mut conn, &req.token) else {
+            return SessionHandlerResponseStruct::error(ErrorCode::Unauthorized);
+        };
+
+        match TokensDb::delete_token_for_user(&mut conn, current.user_id, current.id) {
+            Ok(_) => {
+                self.revoke_access(current.id);
+                SessionHandlerResponseStruct::ok()
+            }
+            Err(err) => SessionHandlerResponseStruct::error(ErrorCode::from(&err)),
+        }
+    }
+
+    pub async fn revoke_session(
+        &self,
+        req: RevokeSessionRequestStruct,
+    ) -> SessionHandlerResponseStruct {
+        let mut conn = self.conn().await;
+
+        let Some(current) = session_guard::authorize_session(&mut conn, &req.token) else {
+            return SessionHandlerResponseStruct::error(ErrorCode::Unauthorized);
+        };
+
+        match TokensDb::delete_token_for_user(&mut conn, current.user_id, req.session_id) {
+            Ok(0) => SessionHandlerResponseStruct::error(ErrorCode::SessionNotFound),
+            Ok(_) => {
+                self.revoke_access(req.session_id);
+                SessionHandlerResponseStruct::ok()
+            }
+            Err(err) => SessionHandlerResponseStruct::error(ErrorCode::from(&err)),
+        }
+    }
+
+    pub async fn logout_all_devices(
+        &self,
+        req: LogoutAllDevicesRequestStruct,
+    ) -> SessionHandlerResponseStruct {
+        let mut conn = self.conn().await;
+
+        let Some(current) = session_guard::authorize_session(&mut conn, &req.token) else {
+            return SessionHandlerResponseStruct::error(ErrorCode::Unauthorized);
+        };
+
+        match TokensDb::delete_tokens_for_user(&mut conn, current.user_id) {
+            Ok(_) => {
+                self.registry.revoke_user(current.user_id, None);
+                SessionHandlerResponseStruct::ok()
+            }
+            Err(err) => SessionHandlerResponseStruct::error(ErrorCode::from(&err)),
+        }
+    }
+
+    pub async fn list_devices(&self, req: ListDevicesRequestStruct) -> ListDevicesResponse {
+        let mut conn = self.conn().await;
+
+        let Some(current) = self.authorize(&mut conn, &req.token) else {
+            return ListDevicesResponse::error(ErrorCode::Unauthorized);
+        };
+
+        match DevicesDb::find_devices_by_user_id(&mut conn, current.user_id) {
+            Ok(rows) => ListDevicesResponse::ok(
+                rows.into_iter()
+                    .map(|row| Self::device_info(row, current.device_id))
+                    .collect(),
+            ),
+            Err(err) => ListDevicesResponse::error(ErrorCode::from(&err)),
+        }
+    }
+
+    /// Deletes the device with its tokens and drops the connections resumed with them.
+    /// The device registers again on its next login.
+    pub async fn revoke_device(
+        &self,
+        req: RevokeDeviceRequestStruct,
+    ) -> SessionHandlerResponseStruct {
+        let mut conn = self.conn().await;
+
+        let Some(current) = session_guard::authorize_session(&mut conn, &req.token) else {
+            return SessionHandlerResponseStruct::error(ErrorCode::Unauthorized);
+        };
+
+        match DevicesDb::delete_device_for_user(&mut conn, current.user_id, req.device_id) {
+            Ok(0) => SessionHandlerResponseStruct::error(ErrorCode::DeviceNotFound),
+            Ok(_) => {
+                self.registry.revoke_device(req.device_id);
+                SessionHandlerResponseStruct::ok()
+            }
+            Err(err) => SessionHandlerResponseStruct::error(ErrorCode::from(&err)),
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for SessionHandler {
+    type Codec = ServerEncriptedCodec;
+
+    async fn serve_route(
+        &mut self,
+        _client_meta: (
+            SocketAddr,
+            &mut Option<Sender<Arc<Mutex<dyn Handler<Codec = Self::Codec>>>>>,
+        ),
+        s_type: Box<dyn StructureType>,
+        mut data: BytesMut,
+    ) -> Result<Vec<u8>, Vec<u8>> {
+        let s_type = s_type
+            .as_any()
+            .downcast_ref::<ProtoLinkSType>()
+            .unwrap()
+            .clone();
+        match s_type {
+            ProtoLinkSType::ListSessions => {
+                let req = s_type::from_slice::<ListSessionsRequestStruct>(data.as_mut())?;
+                let resp = self.list_sessions(req).await;
+                Ok(s_type::to_vec(&resp).unwrap())
+            }
+            ProtoLinkSType::Logout => {
+                let req = s_type::from_slice::<LogoutRequestStruct>(data.as_mut())?;
+                let resp = self.logout(req).await;
+                Ok(s_type::to_vec(&resp).unwrap())
+            }
+            ProtoLinkSType::RevokeSession => {
+                let req = s_type::from_slice::<RevokeSessionRequestStruct>(data.as_mut())?;
+                let resp = self.revoke_session(req).await;
+                Ok(s_type::to_vec(&resp).unwrap())
+            }
+            ProtoLinkSType::LogoutAllDevices => {
+                let req = s_type::from_slice::<LogoutAllDevicesRequestStruct>(data.as_mut())?;
+                let resp = self.logout_all_devices(req).await;
+                Ok(s_type::to_vec(&resp).unwrap())
+            }
+            ProtoLinkSType::ListDevices => {
+                let req = s_type::from_slice::<ListDevicesRequestStruct>(data.as_mut())?;
+                let resp = self.list_devices(req).await;
+                Ok(s_type::to_vec(&resp).unwrap())
+            }
+            ProtoLinkSType::RevokeDevice => {
+                let req = s_type::from_slice::<RevokeDeviceRequestStruct>(data.as_mut())?;
+                let resp = self.revoke_device(req).await;
+                Ok(s_type::to_vec(&resp).unwrap())
+            }
+            ProtoLinkSType::RefreshAccess => {
+                let req = s_type::from_slice::<RefreshAccessRequestStruct>(data.as_mut())?;
+                let resp = self.refresh_access(req).await;
+                Ok(s_type::to_vec(&resp).unwrap())
+            }
+            _ => Err("Malformed request".into()),
+        }
+    }
+
+    async fn accept_stream(
+        &mut self,
+        _addr: SocketAddr,
+        _stream: (
+            Framed<Transport, Self::Codec>,
+            TrafficProcessorHolder<Self::Codec>,
+        ),
+    ) {
+        todo!()
+    }
+}
@@ -0,0 +1,343 @@
+use crate::server::access_keyring::AccessKeyring;
+use crate::server::db::totp_db::{TotpDb, TotpDbStore, TotpSecretRow};
+use crate::server::db::users_db::UsersDb;
+use crate::server::login_throttle::{LoginThrottle, ThrottleKey};
+use crate::server::password_proof::verify_password_proof;
+use crate::server::pending_logins::PendingLogins;
+use crate::server::server_auth_codec::ServerAuthCodec;
+use crate::server::session_guard;
+use crate::structures::error_code::ErrorCode;
+use crate::structures::protolink_stype::{
+    AuthResponse, ProtoLinkSType, TotpConfirmRequestStruct, TotpEnrollRequestStruct,
+    TotpEnrollResponse, TotpVerifyRequestStruct, TwoFactorResponseStruct,
+};
+use crate::util::clock::Clock;
+use crate::util::crypto::totp_util::{
+    base32_encode, check_code, decrypt_secret, encrypt_secret, generate_backup_codes,
+    generate_secret, hash_backup_code, otpauth_uri,
+};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use diesel::MysqlConnection;
+
+use std::env;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tfserver::async_trait::async_trait;
+use tfserver::server::handler::Handler;
+use tfserver::structures::s_type;
+use tfserver::structures::s_type::StructureType;
+use tfserver::structures::traffic_proc::TrafficProcessorHolder;
+use tfserver::structures::transport::Transport;
+use tfserver::tokio::sync::{oneshot::Sender, Mutex};
+use tfserver::tokio_util::bytes::BytesMut;
+use tfserver::tokio_util::codec::Framed;
+
+/// Where TOTP secrets are encrypted with and how they are labelled in authenticator apps
+#[derive(Clone)]
+pub struct TotpConfig {
+    /// `TOTP_SECRET_KEY`, base64 of 32 bytes. Without it 2FA can't be used.
+    pub secret_key: Option<[u8; 32]>,
+    /// `TOTP_ISSUER`
+    pub issuer: String,
+}
+
+impl TotpConfig {
+    pub fn from_env() -> Self {
+        let secret_key = env::var("TOTP_SECRET_KEY")
+            .ok()
+            .and_then(|v| STANDARD.decode(v.trim()).ok())
+            .and_then(|v| <[u8; 32]>::try_from(v).ok());
+        if secret_key.is_none() {
+            eprintln!("TOTP_SECRET_KEY is missing or not 32 bytes of base64, 2FA is disabled");
+        }
+        Self {
+            secret_key,
+            issuer: env::var("TOTP_ISSUER").unwrap_or_else(|_| "ProtoLink".to_string()),
+        }
+    }
+}
+
+/// Enrollment of authenticators and the second step of logins that need one
+pub struct TwoFactorHandler {
+    db: Arc<Mutex<Pool<ConnectionManager<MysqlConnection>>>>,
+    pending: Arc<PendingLogins>,
+    throttle: Arc<LoginThrottle>,
+    clock: Arc<dyn Clock>,
+    config: TotpConfig,
+    keyring: Arc<AccessKeyring>,
+}
+
+impl TwoFactorHandler {
+    pub fn new(
+        db: Arc<Mutex<Pool<ConnectionManager<MysqlConnection>>>>,
+        pending: Arc<PendingLogins>,
+        throttle: Arc<LoginThrottle>,
+        clock: Arc<dyn Clock>,
+        config: TotpConfig,
+        keyring: Arc<AccessKeyring>,
+    ) -> Self {
+        Self {
+            db,
+            pending,
+            throttle,
+            clock,
+            config,
+            keyring,
+        }
+    }
+
+    async fn conn(&self) -> PooledConnection<ConnectionManager<MysqlConnection>> {
+        self.db.lock().await.get().expect("DB connection failed")
+    }
+
+    /// Stores a new unconfirmed secret with fresh backup codes for `user_id`
+    fn enroll(
+        &self,
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        user_id: u64,
+    ) -> TotpEnrollResponse {
+        let Some(key) = &self.config.secret_key else {
+            return TotpEnrollResponse::error(ErrorCode::TwoFactorUnavailable);
+        };
+        let user = match UsersDb::find_user_by_id(conn, user_id) {
+            Ok(user) => user,
+            Err(_) => return TotpEnrollResponse::error(ErrorCode::UserNotFound),
+        };
+
+        let secret = generate_secret();
+        let (secret_ciphertext, secret_nonce) = encrypt_secret(key, user.id, &secret);
+        let backup_codes = generate_backup_codes();
+        let row = TotpSecretRow {
+            user_id: user.id,
+            secret_ciphertext,
+            secret_nonce,
+            confirmed: false,
+            last_used_step: None,
+            created_at: self.clock.now().naive_utc(),
+        };
+
+        match TotpDb::begin_enrollment(
+            conn,
+            &row,
+            backup_codes.iter().map(|c| hash_backup_code(c)).collect(),
+        ) {
+            Ok(()) => TotpEnrollResponse::ok(
+                base32_encode(&secret),
+                otpauth_uri(&self.config.issuer, &user.login, &secret),
+                backup_codes,
+            ),
+            Err(diesel::result::Error::NotFound) => {
+                TotpEnrollResponse::error(ErrorCode::TwoFactorAlreadyEnabled)
+            }
+            Err(err) => TotpEnrollResponse::error(ErrorCode::from(&err)),
+        }
+    }
+
+    /// Checks a TOTP code of `user_id`, or a backup code once the secret is confirmed.
+    /// Each time step is accepted once, so an observed code can't be replayed.
+    fn check_code(
+        &self,
+        conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+        user_id: u64,
+        code: &str,
+    ) -> Result<(), ErrorCode> {
+        let key = self
+            .config
+            .secret_key
+            .as_ref()
+            .ok_or(ErrorCode::TwoFactorUnavailable)?;
+        let row = TotpDb::find_secret(conn, user_id).map_err(|_| ErrorCode::InvalidTotpCode)?;
+        let secret = decrypt_secret(key, user_id, &row.secret_ciphertext, &row.secret_nonce)
+            .ok_or(ErrorCode::Internal)?;
+
+        let mut store = TotpDbStore(conn);
+        match check_code(
+            &mut store,
+            self.clock.as_ref(),
+            user_id,
+            &secret,
+            row.confirmed,
+            code,
+        ) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(ErrorCode::InvalidTotpCode),
+            Err(err) => Err(ErrorCode::from(&err)),
+        }
+    }
+
+    /// Enrollment from a login that can't finish before enrolling, or from a session that
+    /// proved the password again. The password proof counts towards the same throttle as
+    /// logins.
+    pub async fn totp_enroll(
+        &self,
+        req: TotpEnrollRequestStruct,
+        peer: SocketAddr,
+    ) -> TotpEnrollResponse {
+        let mut conn = self.conn().await;
+
+        if req.pending_login != 0 {
+            return match self.pending.get(req.pending_login, self.clock.now()) {
+                Some(login) if login.enrollment_required => self.enroll(&mut conn, login.user_id),
+                _ => TotpEnrollResponse::error(ErrorCode::PendingLoginNotFound),
+            };
+        }
+
+        let Some(session) = session_guard::authorize_session(&mut conn, &req.token) else {
+            return TotpEnrollResponse::error(ErrorCode::Unauthorized);
+        };
+        let user = match UsersDb::find_user_by_id(&mut conn, session.user_id) {
+            Ok(user) => user,
+            Err(_) => return TotpEnrollResponse::error(ErrorCode::UserNotFound),
+        };
+
+        let now = self.clock.now();
+        let keys = [ThrottleKey::login(&user.login), ThrottleKey::addr(peer)];
+        if let Some(until) = self.throttle.check(&keys, now) {
+            return TotpEnrollResponse::locked(until.timestamp());
+        }
+        let Some(verifier) = user.srp_verifier else {
+            return TotpEnrollResponse::error(ErrorCode::UserNotFound);
+        };
+        if let Err(rejection) = verify_password_proof(
+            &mut conn,
+            &self.throttle,
+            user.id,
+            &verifier,
+            req.challenge_id,
+            &req.client_proof,
+            &keys,
+            now,
+        ) {
+            return rejection.into();
+        }
+
+        self.enroll(&mut conn, user.id)
+    }
+
+    /// Turns 2FA on for a session that enrolled, from then on logins need a code
+    pub async fn totp_confirm(&self, req: TotpConfirmRequestStruct) -> TwoFactorResponseStruct {
+        let mut conn = self.conn().await;
+
+        let Some(session) = session_guard::authorize_session(&mut conn, &req.token) else {
+            return TwoFactorResponseStruct::error(ErrorCode::Unauthorized);
+        };
+
+        match self.check_code(&mut conn, session.user_id, &req.code) {
+            Ok(()) => TwoFactorResponseStruct::ok(),
+            Err(code) => TwoFactorResponseStruct::error(code),
+        }
+    }
+
+    /// Finishes a pending login. Wrong codes count towards the same throttle as wrong
+    /// passwords, and a pending login only takes `MAX_CODE_ATTEMPTS` of them.
+    pub async fn totp_verify(
+        &self,
+        req: TotpVerifyRequestStruct,
+        peer: SocketAddr,
+    ) -> AuthResponse {
+        let now = self.clock.now();
+        let Some(login) = self.pending.get(req.pending_login, now) else {
+            return AuthResponse::error(ErrorCode::PendingLoginNotFound);
+        };
+
+        let mut conn = self.conn().await;
+
+        let user = match UsersDb::find_user_by_id(&mut conn, login.user_id) {
+            Ok(user) => user,
+            Err(_) => return AuthResponse::error(ErrorCode::UserNotFound),
+        };
+
+        let keys = [ThrottleKey::login(&user.login), ThrottleKey::addr(peer)];
+        if let Some(until) = self.throttle.check(&keys, now) {
+            return AuthResponse::locked(until.timestamp());
+        }
+
+        match self.check_code(&mut conn, user.id, &req.code) {
+            Ok(()) => {}
+            Err(ErrorCode::InvalidTotpCode) => {
+                self.pending.record_failed_code(req.pending_login);
+                return match self.throttle.record_failure(&keys, now) {
+                    Some(until) => AuthResponse::locked(until.timestamp()),
+                    None => AuthResponse::error(ErrorCode::InvalidTotpCode),
+                };
+            }
+            Err(code) => return AuthResponse::error(code),
+        }
+
+        let Some(login) = self.pending.take(req.pending_login, now) else {
+            return AuthResponse::error(ErrorCode::PendingLoginNotFound);
+        };
+
+        let issued = match session_guard::issue_session(
+            &mut conn,
+            &self.keyring,
+            login.user_id,
+            login.session_secret,
+            login.client_label,
+            login.device.as_ref(),
+            login.peer,
+            now,
+        ) {
+            Ok(issued) => issued,
+            Err(_) => return AuthResponse::error(ErrorCode::TokenCreationFailed),
+        };
+
+        let mut resp = AuthResponse::ok(String::new());
+        resp.session = Some(issued.into());
+        resp
+    }
+}
+
+#[async_trait]
+impl Handler for TwoFactorHandler {
+    type Codec = ServerAuthCodec;
+
+    async fn serve_route(
+        &mut self,
+        client_meta: (
+            SocketAddr,
+            &mut Option<Sender<Arc<Mutex<dyn Handler<Codec = Self::Codec>>>>>,
+        ),
+        s_type: Box<dyn StructureType>,
+        mut data: BytesMut,
+    ) -> Result<Vec<u8>, Vec<u8>> {
+        let s_type = s_type
+            .as_any()
+            .downcast_ref::<ProtoLinkSType>()
+            .unwrap()
+            .clone();
+        match s_type {
+            ProtoLinkSType::TotpEnroll => {
+                let req = s_type::from_slice::<TotpEnrollRequestStruct>(data.as_mut())?;
+                let resp = self.totp_enroll(req, client_meta.0).await;
+                Ok(s_type::to_vec(&resp).unwrap())
+            }
+            ProtoLinkSType::TotpConfirm => {
+                let req = s_type::from_slice::<TotpConfirmRequestStruct>(data.as_mut())?;
+                let resp = self.totp_confirm(req).await;
+                Ok(s_type::to_vec(&resp).unwrap())
+            }
+            ProtoLinkSType::TotpVerify => {
+                let req = s_type::from_slice::<TotpVerifyRequestStruct>(data.as_mut())?;
+                let resp = self.totp_verify(req, client_meta.0).await;
+                Ok(s_type::to_vec(&resp).unwrap())
+            }
+            _ => Err("Malformed request".into()),
+        }
+    }
+
+    async fn accept_stream(
+        &mut self,
+        _addr: SocketAddr,
+        _stream: (
+            Framed<Transport, Self::Codec>,
+            TrafficProcessorHolder<Self::Codec>,
+        ),
+    ) {
+        todo!()
+    }
+}
@@ -0,0 +1,86 @@
+use chrono::Duration;
+use std::env;
+
+const DEFAULT_INVITE_TTL_DAYS: i64 = 7;
+const DEFAULT_MAX_INVITE_TTL_DAYS: i64 = 30;
+const DEFAULT_MAX_INVITE_USES: u32 = 100;
+
+/// Who may create an account through `REGISTER_HANDLER`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RegistrationMode {
+    Open,
+    /// `RegisterRequestStruct::invite_code` has to name a live invite
+    InviteOnly,
+}
+
+/// Who may create invites
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InviteIssuers {
+    Users,
+    Admins,
+}
+
+/// How registration is gated and what invites may look like, see `InviteHandler`
+#[derive(Clone, Debug)]
+pub struct InvitePolicy {
+    pub mode: RegistrationMode,
+    pub issuers: InviteIssuers,
+    /// Lifetime of an invite created without one
+    pub default_ttl: Duration,
+    pub max_ttl: Duration,
+    pub max_uses: u32,
+}
+
+impl Default for InvitePolicy {
+    fn default() -> Self {
+        Self {
+            mode: RegistrationMode::Open,
+            issuers: InviteIssuers::Users,
+            default_ttl: Duration::days(DEFAULT_INVITE_TTL_DAYS),
+            max_ttl: Duration::days(DEFAULT_MAX_INVITE_TTL_DAYS),
+            max_uses: DEFAULT_MAX_INVITE_USES,
+        }
+    }
+}
+
+fn env_days(name: &str) -> Option<Duration> {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|v| *v > 0)
+        .map(Duration::days)
+}
+
+impl InvitePolicy {
+    /// Reads `REGISTRATION_MODE` (`open` or `invite`), `INVITE_ISSUERS` (`users` or
+    /// `admins`), `INVITE_TTL_DAYS`, `INVITE_MAX_TTL_DAYS` and `INVITE_MAX_USES`, keeping
+    /// the defaults for unset or invalid values
+    pub fn from_env() -> Self {
+        let mut policy = Self::default();
+        match env::var("REGISTRATION_MODE").as_deref() {
+            Ok("open") => policy.mode = RegistrationMode::Open,
+            Ok("invite") => policy.mode = RegistrationMode::InviteOnly,
+            _ => {}
+        }
+        match env::var("INVITE_ISSUERS").as_deref() {
+            Ok("users") => policy.issuers = InviteIssuers::Users,
+            Ok("admins") => policy.issuers = InviteIssuers::Admins,
+            _ => {}
+        }
+        if let Some(v) = env_days("INVITE_MAX_TTL_DAYS") {
+            policy.max_ttl = v;
+        }
+        if let Some(v) = env_days("INVITE_TTL_DAYS") {
+            policy.default_ttl = v;
+        }
+        policy.default_ttl = policy.default_ttl.min(policy.max_ttl);
+        if let Some(v) = env::var("INVITE_MAX_USES")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .filter(|v| *v > 0)
+        {
+            policy.max_uses = v;
+        }
+        policy
+    }
+}
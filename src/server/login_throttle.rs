@@ -0,0 +1,324 @@
+use crate::server::db::login_attempts_db::{LoginAttemptRow, LoginAttemptsDb};
+use chrono::{DateTime, Duration, Utc};
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::MysqlConnection;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+
+const DEFAULT_FREE_ATTEMPTS: u32 = 5;
+const DEFAULT_MAX_LOCKOUT_SECS: i64 = 15 * 60;
+
+/// What failed logins are counted against. Addresses are keyed by IP alone since the
+/// port changes with every connection.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ThrottleKey {
+    /// Counted but never locked itself, see `LoginThrottle::record_failure`
+    Login(String),
+    Addr(IpAddr),
+    /// Login on the encrypted listener, which can't see the address. Locks SRP handshakes
+    /// there and nothing else.
+    Handshake(String),
+}
+
+impl ThrottleKey {
+    /// Logins compare case-insensitively in MySQL, so they are counted that way too
+    pub fn login(login: &str) -> Self {
+        ThrottleKey::Login(login.to_lowercase())
+    }
+
+    pub fn addr(addr: SocketAddr) -> Self {
+        ThrottleKey::Addr(addr.ip())
+    }
+
+    pub fn handshake(login: &str) -> Self {
+        ThrottleKey::Handshake(login.to_lowercase())
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value.split_once(':')? {
+            ("login", login) => Some(ThrottleKey::Login(login.to_string())),
+            ("addr", ip) => ip.parse().ok().map(ThrottleKey::Addr),
+            ("handshake", login) => Some(ThrottleKey::Handshake(login.to_string())),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ThrottleKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThrottleKey::Login(login) => write!(f, "login:{}", login),
+            ThrottleKey::Addr(ip) => write!(f, "addr:{}", ip),
+            ThrottleKey::Handshake(login) => write!(f, "handshake:{}", login),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ThrottleConfig {
+    /// Failures allowed before a key gets locked
+    pub free_attempts: u32,
+    /// Lockout after the first failure past `free_attempts`, doubled for each one after
+    pub base_lockout: Duration,
+    pub max_lockout: Duration,
+    /// A key with no failures for this long starts counting from zero again
+    pub reset_after: Duration,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        Self {
+            free_attempts: DEFAULT_FREE_ATTEMPTS,
+            base_lockout: Duration::seconds(1),
+            max_lockout: Duration::seconds(DEFAULT_MAX_LOCKOUT_SECS),
+            reset_after: Duration::hours(1),
+        }
+    }
+}
+
+impl ThrottleConfig {
+    /// Defaults overridden by `LOGIN_THROTTLE_FREE_ATTEMPTS` and
+    /// `LOGIN_THROTTLE_MAX_LOCKOUT_SECS` where set
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Some(v) = env::var("LOGIN_THROTTLE_FREE_ATTEMPTS")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+        {
+            config.free_attempts = v;
+        }
+        if let Some(v) = env::var("LOGIN_THROTTLE_MAX_LOCKOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .filter(|v| *v > 0)
+        {
+            config.max_lockout = Duration::seconds(v);
+        }
+        config
+    }
+}
+
+/// Whether `LOGIN_THROTTLE_PERSIST` asks for attempts to survive a restart
+pub fn persistence_from_env() -> bool {
+    matches!(
+        env::var("LOGIN_THROTTLE_PERSIST").as_deref(),
+        Ok("1") | Ok("true")
+    )
+}
+
+#[derive(Clone, Debug)]
+struct AttemptState {
+    failures: u32,
+    last_failure: DateTime<Utc>,
+    locked_until: Option<DateTime<Utc>>,
+}
+
+/// A key that currently may not attempt a login
+#[derive(Clone, Debug)]
+pub struct Lockout {
+    pub key: ThrottleKey,
+    pub failures: u32,
+    pub locked_until: DateTime<Utc>,
+}
+
+/// Counts failed logins per login and per address and locks addresses out with
+/// exponential backoff. A login is never locked as a whole, anyone could do that to any
+/// account by failing at it. Shared by `AuthHandler` and `ServerEncriptedCodec`, the
+/// encrypted listener counts under `ThrottleKey::Handshake` as it can't see addresses.
+///
+/// State lives in memory, with a pool it is also written to `login_attempts` and reloaded
+/// on startup.
+pub struct LoginThrottle {
+    config: ThrottleConfig,
+    attempts: Mutex<HashMap<ThrottleKey, AttemptState>>,
+    pool: Option<Pool<ConnectionManager<MysqlConnection>>>,
+}
+
+impl LoginThrottle {
+    pub fn new(
+        config: ThrottleConfig,
+        pool: Option<Pool<ConnectionManager<MysqlConnection>>>,
+    ) -> Self {
+        let attempts = pool
+            .as_ref()
+            .map(|pool| Self::load(pool, &config))
+            .unwrap_or_default();
+        Self {
+            config,
+            attempts: Mutex::new(attempts),
+            pool,
+        }
+    }
+
+    fn load(
+        pool: &Pool<ConnectionManager<MysqlConnection>>,
+        config: &ThrottleConfig,
+    ) -> HashMap<ThrottleKey, AttemptState> {
+        let now = Utc::now();
+        let rows = pool.get().ok().and_then(|mut conn| {
+            LoginAttemptsDb::find_recent_attempts(
+                &mut conn,
+                (now - config.reset_after).naive_utc(),
+                now.naive_utc(),
+            )
+            .ok()
+        });
+
+        rows.unwrap_or_default()
+            .into_iter()
+            .filter_map(|row| {
+                let key = ThrottleKey::parse(&row.throttle_key)?;
+                let locked_until = match key {
+                    ThrottleKey::Login(_) => None,
+                    _ => row.locked_until.map(|t| t.and_utc()),
+                };
+                let state = AttemptState {
+                    failures: row.failures,
+                    last_failure: row.last_failure_at.and_utc(),
+                    locked_until,
+                };
+                Some((key, state))
+            })
+            .collect()
+    }
+
+    fn lockout_for(&self, failures: u32) -> Option<Duration> {
+        let over = failures.checked_sub(self.config.free_attempts)?.min(30);
+        if over == 0 {
+            return None;
+        }
+        let lockout = self.config.base_lockout * (1i32 << (over - 1));
+        Some(lockout.min(self.config.max_lockout))
+    }
+
+    /// Latest lockout among `keys` still in force at `now`, None if a login may be attempted
+    pub fn check(&self, keys: &[ThrottleKey], now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let attempts = self.attempts.lock().unwrap();
+        keys.iter()
+            .filter_map(|key| attempts.get(key)?.locked_until)
+            .filter(|until| *until > now)
+            .max()
+    }
+
+    /// Counts a failed attempt against every key, returns the resulting lockout if any.
+    ///
+    /// A `ThrottleKey::Login` past its free attempts isn't locked, its lockout goes to the
+    /// addresses among `keys` instead. Each address still guessing at that login is
+    /// locked after its next failure, while the owner can log in from anywhere else.
+    pub fn record_failure(
+        &self,
+        keys: &[ThrottleKey],
+        now: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        let mut updated = Vec::with_capacity(keys.len());
+        {
+            let mut attempts = self.attempts.lock().unwrap();
+            let mut login_lockout = None;
+            for key in keys {
+                let state = attempts.entry(key.clone()).or_insert(AttemptState {
+                    failures: 0,
+                    last_failure: now,
+                    locked_until: None,
+                });
+                if now - state.last_failure > self.config.reset_after {
+                    state.failures = 0;
+                }
+                state.failures = state.failures.saturating_add(1);
+                state.last_failure = now;
+                let lockout = self.lockout_for(state.failures).map(|d| now + d);
+                if let ThrottleKey::Login(_) = key {
+                    login_lockout = login_lockout.max(lockout);
+                } else {
+                    state.locked_until = lockout;
+                }
+            }
+            for key in keys {
+                let state = attempts.get_mut(key).unwrap();
+                if let ThrottleKey::Addr(_) = key {
+                    state.locked_until = state.locked_until.max(login_lockout);
+                }
+                updated.push((key.clone(), state.clone()));
+            }
+        }
+
+        for (key, state) in &updated {
+            if let Some(until) = state.locked_until {
+                eprintln!(
+                    "Login locked for {} until {} after {} failures",
+                    key, until, state.failures
+                );
+            }
+            self.persist(key, Some(state));
+        }
+        updated.iter().filter_map(|(_, s)| s.locked_until).max()
+    }
+
+    /// Clears the failures of `key` after a successful login. Only the login is cleared,
+    /// an address guessing at several accounts keeps its count.
+    pub fn record_success(&self, key: &ThrottleKey) {
+        if self.attempts.lock().unwrap().remove(key).is_some() {
+            self.persist(key, None);
+        }
+    }
+
+    /// Every key locked at `now`, for the admin view
+    pub fn lockouts(&self, now: DateTime<Utc>) -> Vec<Lockout> {
+        let attempts = self.attempts.lock().unwrap();
+        let mut res: Vec<Lockout> = attempts
+            .iter()
+            .filter_map(|(key, state)| {
+                let locked_until = state.locked_until.filter(|until| *until > now)?;
+                Some(Lockout {
+                    key: key.clone(),
+                    failures: state.failures,
+                    locked_until,
+                })
+            })
+            .collect();
+        res.sort_by(|a, b| b.locked_until.cmp(&a.locked_until));
+        res
+    }
+
+    /// Forgets keys that are neither locked nor failed within `reset_after`
+    pub fn prune(&self, now: DateTime<Utc>) {
+        let reset_after = self.config.reset_after;
+        self.attempts.lock().unwrap().retain(|_, state| {
+            state.locked_until.is_some_and(|until| until > now)
+                || now - state.last_failure <= reset_after
+        });
+
+        if let Some(mut conn) = self.pool.as_ref().and_then(|pool| pool.get().ok()) {
+            if let Err(err) = LoginAttemptsDb::delete_stale(
+                &mut conn,
+                (now - reset_after).naive_utc(),
+                now.naive_utc(),
+            ) {
+                eprintln!("Login attempt sweep failed: {}", err);
+            }
+        }
+    }
+
+    fn persist(&self, key: &ThrottleKey, state: Option<&AttemptState>) {
+        let Some(mut conn) = self.pool.as_ref().and_then(|pool| pool.get().ok()) else {
+            return;
+        };
+        let res = match state {
+            Some(state) => LoginAttemptsDb::upsert_attempt(
+                &mut conn,
+                &LoginAttemptRow {
+                    throttle_key: key.to_string(),
+                    failures: state.failures,
+                    last_failure_at: state.last_failure.naive_utc(),
+                    locked_until: state.locked_until.map(|t| t.naive_utc()),
+                },
+            ),
+            None => LoginAttemptsDb::delete_attempt(&mut conn, &key.to_string()),
+        };
+        if let Err(err) = res {
+            eprintln!("Persisting login attempts for {} failed: {}", key, err);
+        }
+    }
+}
@@ -1,3 +1,17 @@
+pub mod access_keyring;
 pub mod db;
+pub mod decoy_credentials;
+pub mod deletion_policy;
+pub mod event_hub;
+pub mod expiry_sweeper;
 pub mod handlers;
-pub mod server_encrypted_codec;
\ No newline at end of file
+pub mod invite_policy;
+pub mod login_throttle;
+pub mod password_proof;
+pub mod pending_logins;
+pub mod registration_policy;
+pub mod server_auth_codec;
+pub mod server_encrypted_codec;
+pub mod server_identity;
+pub mod session_guard;
+pub mod session_registry;
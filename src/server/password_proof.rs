@@ -0,0 +1,90 @@
+use crate::server::db::challenges_db::ChallengesDb;
+use crate::server::login_throttle::{LoginThrottle, ThrottleKey};
+use crate::structures::error_code::ErrorCode;
+use crate::structures::protolink_stype::{AuthResponse, TotpEnrollResponse};
+use crate::util::crypto::srp_util::process_client_reply;
+
+use chrono::{DateTime, Utc};
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::MysqlConnection;
+use sha2::Sha256;
+use srp::server::SrpServerVerifier;
+
+/// Why `verify_password_proof` refused a proof
+#[derive(Clone, Copy, Debug)]
+pub enum ProofRejection {
+    Failed(ErrorCode),
+    /// The failure was one too many, attempts are refused until then
+    Locked(DateTime<Utc>),
+}
+
+impl From<ProofRejection> for AuthResponse {
+    fn from(rejection: ProofRejection) -> Self {
+        match rejection {
+            ProofRejection::Failed(code) => AuthResponse::error(code),
+            ProofRejection::Locked(until) => AuthResponse::locked(until.timestamp()),
+        }
+    }
+}
+
+impl From<ProofRejection> for TotpEnrollResponse {
+    fn from(rejection: ProofRejection) -> Self {
+        match rejection {
+            ProofRejection::Failed(code) => TotpEnrollResponse::error(code),
+            ProofRejection::Locked(until) => TotpEnrollResponse::locked(until.timestamp()),
+        }
+    }
+}
+
+/// Counts a failed attempt against `keys`, answering with the lockout if this was one
+/// failure too many
+pub fn reject_attempt(
+    throttle: &LoginThrottle,
+    keys: &[ThrottleKey],
+    code: ErrorCode,
+    now: DateTime<Utc>,
+) -> ProofRejection {
+    match throttle.record_failure(keys, now) {
+        Some(until) => ProofRejection::Locked(until),
+        None => ProofRejection::Failed(code),
+    }
+}
+
+/// Consumes challenge `challenge_id` of the user and checks `client_proof` against it,
+/// counting a wrong proof towards `keys`. The first key is cleared on success, so it
+/// should be the login and not the address.
+///
+/// Every request that needs the password again on top of a session (password changes,
+/// account deletion, TOTP enrollment) goes through here, so a stolen token alone can't
+/// make them.
+#[allow(clippy::too_many_arguments)]
+pub fn verify_password_proof(
+    conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+    throttle: &LoginThrottle,
+    user_id: u64,
+    verifier: &[u8],
+    challenge_id: u64,
+    client_proof: &[u8],
+    keys: &[ThrottleKey],
+    now: DateTime<Utc>,
+) -> Result<SrpServerVerifier<Sha256>, ProofRejection> {
+    let chal = match ChallengesDb::consume_challenge(conn, challenge_id, user_id, now.naive_utc()) {
+        Ok(c) => c,
+        Err(_) => return Err(ProofRejection::Failed(ErrorCode::ChallengeNotFound)),
+    };
+
+    let srp_session = process_client_reply(&chal.solution, verifier, &chal.client_public)
+        .filter(|session| session.verify_client(client_proof).is_ok());
+    match srp_session {
+        Some(srp_session) => {
+            throttle.record_success(&keys[0]);
+            Ok(srp_session)
+        }
+        None => Err(reject_attempt(
+            throttle,
+            keys,
+            ErrorCode::InvalidCredentials,
+            now,
+        )),
+    }
+}
@@ -0,0 +1,111 @@
+use crate::structures::protolink_stype::DeviceRegistrationStruct;
+use chrono::{DateTime, Duration, Utc};
+use rand::Rng;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+
+/// How long a login may wait for its TOTP code
+pub const PENDING_LOGIN_TTL: Duration = Duration::minutes(5);
+/// Wrong codes a pending login survives, after that the password has to be proven again
+pub const MAX_CODE_ATTEMPTS: u32 = 5;
+
+/// Login whose SRP proof was accepted but whose token is only issued once a TOTP code is.
+/// Holds what `session_guard::issue_session` needs then.
+#[derive(Clone, Debug)]
+pub struct PendingLogin {
+    pub user_id: u64,
+    pub session_secret: Vec<u8>,
+    pub client_label: String,
+    /// Only registered once the login completes
+    pub device: Option<DeviceRegistrationStruct>,
+    pub peer: SocketAddr,
+    /// The user has to enroll an authenticator before the code can be checked
+    pub enrollment_required: bool,
+    pub expires_at: DateTime<Utc>,
+    failed_codes: u32,
+}
+
+impl PendingLogin {
+    pub fn new(
+        user_id: u64,
+        session_secret: Vec<u8>,
+        client_label: String,
+        device: Option<DeviceRegistrationStruct>,
+        peer: SocketAddr,
+        enrollment_required: bool,
+        expires_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            user_id,
+            session_secret,
+            client_label,
+            device,
+            peer,
+            enrollment_required,
+            expires_at,
+            failed_codes: 0,
+        }
+    }
+}
+
+/// Logins between `AuthResponse` with a `PendingLoginStruct` and `TotpVerify`, shared by
+/// `AuthHandler` and `TwoFactorHandler`. Kept in memory, a restart only means logging
+/// in again.
+#[derive(Default)]
+pub struct PendingLogins {
+    logins: Mutex<HashMap<u64, PendingLogin>>,
+}
+
+impl PendingLogins {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores `login` under a fresh random id, dropping expired ones on the way
+    pub fn insert(&self, login: PendingLogin, now: DateTime<Utc>) -> u64 {
+        let mut logins = self.logins.lock().unwrap();
+        logins.retain(|_, l| l.expires_at > now);
+        let mut rng = rand::rng();
+        loop {
+            let id: u64 = rng.random_range(1..=u64::MAX);
+            if let std::collections::hash_map::Entry::Vacant(entry) = logins.entry(id) {
+                entry.insert(login);
+                return id;
+            }
+        }
+    }
+
+    pub fn get(&self, id: u64, now: DateTime<Utc>) -> Option<PendingLogin> {
+        let mut logins = self.logins.lock().unwrap();
+        match logins.get(&id) {
+            Some(login) if login.expires_at > now => Some(login.clone()),
+            Some(_) => {
+                logins.remove(&id);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Removes the login for good, None if it expired or someone else took it first
+    pub fn take(&self, id: u64, now: DateTime<Utc>) -> Option<PendingLogin> {
+        self.logins
+            .lock()
+            .unwrap()
+            .remove(&id)
+            .filter(|login| login.expires_at > now)
+    }
+
+    /// Counts a wrong code, dropping the login after `MAX_CODE_ATTEMPTS`
+    pub fn record_failed_code(&self, id: u64) {
+        let mut logins = self.logins.lock().unwrap();
+        let Some(login) = logins.get_mut(&id) else {
+            return;
+        };
+        login.failed_codes += 1;
+        if login.failed_codes >= MAX_CODE_ATTEMPTS {
+            logins.remove(&id);
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use crate::structures::error_code::ErrorCode;
+use crate::structures::protolink_stype::RegisterRequestStruct;
+use crate::util::crypto::kdf_util::KdfParams;
+use std::env;
+use std::fmt;
+
+/// `users.login` and `users.name` are VARCHAR(255), limits above that can't be stored
+const COLUMN_MAX_LEN: usize = 255;
+const DEFAULT_LOGIN_MIN_LEN: usize = 3;
+const DEFAULT_LOGIN_MAX_LEN: usize = 32;
+const DEFAULT_NAME_MAX_LEN: usize = 64;
+const DEFAULT_LOGIN_EXTRA_CHARS: &str = "._-";
+const DEFAULT_RESERVED_LOGINS: &str = "admin,administrator,root,system,support,moderator";
+/// Bounds on the client supplied blobs. A G_2048 verifier is at most 256 bytes.
+const MIN_SRP_SALT_LEN: usize = 16;
+const MAX_SRP_SALT_LEN: usize = 64;
+const MAX_SRP_VERIFIER_LEN: usize = 256;
+const MAX_KDF_SALT_LEN: usize = 64;
+
+/// Why a registration was refused, `Display` is the reason sent back to the client
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegistrationError {
+    LoginTooShort(usize),
+    LoginTooLong(usize),
+    LoginInvalidChars,
+    LoginMustStartWithLetter,
+    LoginReserved,
+    NameEmpty,
+    NameTooLong(usize),
+    NameInvalidChars,
+    SaltSize,
+    VerifierSize,
+    KdfParamsRejected,
+}
+
+impl fmt::Display for RegistrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistrationError::LoginTooShort(min) => {
+                write!(f, "login must be at least {} characters", min)
+            }
+            RegistrationError::LoginTooLong(max) => {
+                write!(f, "login must be at most {} characters", max)
+            }
+            RegistrationError::LoginInvalidChars => {
+                write!(f, "login may only contain letters, digits and allowed punctuation")
+            }
+            RegistrationError::LoginMustStartWithLetter => {
+                write!(f, "login must start with a letter")
+            }
+            RegistrationError::LoginReserved => write!(f, "login is reserved"),
+            RegistrationError::NameEmpty => write!(f, "name must not be empty"),
+            RegistrationError::NameTooLong(max) => {
+                write!(f, "name must be at most {} characters", max)
+            }
+            RegistrationError::NameInvalidChars => {
+                write!(f, "name must not contain control characters")
+            }
+            RegistrationError::SaltSize => write!(f, "SRP salt has an invalid size"),
+            RegistrationError::VerifierSize => write!(f, "SRP verifier has an invalid size"),
+            RegistrationError::KdfParamsRejected => write!(f, "KDF parameters rejected"),
+        }
+    }
+}
+
+impl From<&RegistrationError> for ErrorCode {
+    fn from(err: &RegistrationError) -> Self {
+        match err {
+            RegistrationError::LoginTooShort(_) => ErrorCode::LoginTooShort,
+            RegistrationError::LoginTooLong(_) => ErrorCode::LoginTooLong,
+            RegistrationError::LoginInvalidChars => ErrorCode::LoginInvalidChars,
+            RegistrationError::LoginMustStartWithLetter => ErrorCode::LoginMustStartWithLetter,
+            RegistrationError::LoginReserved => ErrorCode::LoginReserved,
+            RegistrationError::NameEmpty => ErrorCode::NameEmpty,
+            RegistrationError::NameTooLong(_) => ErrorCode::NameTooLong,
+            RegistrationError::NameInvalidChars => ErrorCode::NameInvalidChars,
+            RegistrationError::SaltSize => ErrorCode::InvalidSalt,
+            RegistrationError::VerifierSize => ErrorCode::InvalidVerifier,
+            RegistrationError::KdfParamsRejected => ErrorCode::KdfParamsRejected,
+        }
+    }
+}
+
+/// What `RegisterHandler` accepts as a new account
+#[derive(Clone, Debug)]
+pub struct RegistrationPolicy {
+    pub login_min_len: usize,
+    pub login_max_len: usize,
+    /// Allowed in logins besides ASCII letters and digits
+    pub login_extra_chars: String,
+    /// Compared case-insensitively
+    pub reserved_logins: Vec<String>,
+    pub name_max_len: usize,
+}
+
+impl Default for RegistrationPolicy {
+    fn default() -> Self {
+        Self {
+            login_min_len: DEFAULT_LOGIN_MIN_LEN,
+            login_max_len: DEFAULT_LOGIN_MAX_LEN,
+            login_extra_chars: DEFAULT_LOGIN_EXTRA_CHARS.to_string(),
+            reserved_logins: parse_list(DEFAULT_RESERVED_LOGINS),
+            name_max_len: DEFAULT_NAME_MAX_LEN,
+        }
+    }
+}
+
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|v| v.trim().to_lowercase())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+fn env_usize(name: &str) -> Option<usize> {
+    env::var(name).ok().and_then(|v| v.parse::<usize>().ok())
+}
+
+impl RegistrationPolicy {
+    /// Defaults overridden by the `REGISTRATION_*` variables where set. Lengths are capped
+    /// at what the columns can hold.
+    pub fn from_env() -> Self {
+        let mut policy = Self::default();
+        if let Some(v) = env_usize("REGISTRATION_LOGIN_MIN_LEN") {
+            policy.login_min_len = v.max(1);
+        }
+        if let Some(v) = env_usize("REGISTRATION_LOGIN_MAX_LEN") {
+            policy.login_max_len = v.min(COLUMN_MAX_LEN);
+        }
+        if let Some(v) = env_usize("REGISTRATION_NAME_MAX_LEN") {
+            policy.name_max_len = v.min(COLUMN_MAX_LEN);
+        }
+        if let Ok(v) = env::var("REGISTRATION_LOGIN_EXTRA_CHARS") {
+            policy.login_extra_chars = v.chars().filter(|c| c.is_ascii_punctuation()).collect();
+        }
+        if let Ok(v) = env::var("REGISTRATION_RESERVED_LOGINS") {
+            policy.reserved_logins = parse_list(&v);
+        }
+        policy
+    }
+
+    pub fn validate_login(&self, login: &str) -> Result<(), RegistrationError> {
+        let len = login.chars().count();
+        if len < self.login_min_len {
+            return Err(RegistrationError::LoginTooShort(self.login_min_len));
+        }
+        if len > self.login_max_len {
+            return Err(RegistrationError::LoginTooLong(self.login_max_len));
+        }
+        if !login
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || self.login_extra_chars.contains(c))
+        {
+            return Err(RegistrationError::LoginInvalidChars);
+        }
+        if !login.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return Err(RegistrationError::LoginMustStartWithLetter);
+        }
+        let lower = login.to_lowercase();
+        if self.reserved_logins.iter().any(|r| *r == lower) {
+            return Err(RegistrationError::LoginReserved);
+        }
+        Ok(())
+    }
+
+    pub fn validate_name(&self, name: &str) -> Result<(), RegistrationError> {
+        if name.trim().is_empty() {
+            return Err(RegistrationError::NameEmpty);
+        }
+        if name.chars().count() > self.name_max_len {
+            return Err(RegistrationError::NameTooLong(self.name_max_len));
+        }
+        if name.chars().any(char::is_control) {
+            return Err(RegistrationError::NameInvalidChars);
+        }
+        Ok(())
+    }
+
+    /// Checks the SRP salt and verifier and the KDF parameters they were made with
+    pub fn validate_credential(
+        &self,
+        srp_salt: &[u8],
+        srp_verifier: &[u8],
+        kdf_params: &KdfParams,
+    ) -> Result<(), RegistrationError> {
+        if !(MIN_SRP_SALT_LEN..=MAX_SRP_SALT_LEN).contains(&srp_salt.len()) {
+            return Err(RegistrationError::SaltSize);
+        }
+        if srp_verifier.is_empty() || srp_verifier.len() > MAX_SRP_VERIFIER_LEN {
+            return Err(RegistrationError::VerifierSize);
+        }
+        let kdf_salt_ok = match kdf_params {
+            KdfParams::Argon2id { salt, .. } => salt.len() <= MAX_KDF_SALT_LEN,
+            KdfParams::LegacyHkdf => false,
+        };
+        if !kdf_salt_ok || !kdf_params.is_acceptable() {
+            return Err(RegistrationError::KdfParamsRejected);
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self, request: &RegisterRequestStruct) -> Result<(), RegistrationError> {
+        self.validate_login(&request.login)?;
+        self.validate_name(&request.name)?;
+        self.validate_credential(&request.srp_salt, &request.srp_verifier, &request.kdf_params)
+    }
+}
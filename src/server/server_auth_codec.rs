@@ -0,0 +1,156 @@
+use crate::server::server_identity::ServerIdentity;
+use crate::structures::protocol_hello::{Capabilities, ClientHello, HelloReply, Negotiated};
+use crate::util::crypto::codec_util::{
+    derive_auth_traffic_keys, ephemeral_shared_secret, generate_ephemeral_key, CipherSuite,
+    CryptoState, RekeyLimits, Transcript, AUTH_NONCE_LEN, EPHEMERAL_KEY_LEN,
+    NONCE_CLIENT_TO_SERVER, NONCE_SERVER_TO_CLIENT,
+};
+use crate::util::crypto::identity_util::SIGN_AUTH_HANDSHAKE;
+use rand::RngCore;
+use std::io;
+use std::sync::Arc;
+use tfserver::async_trait::async_trait;
+use tfserver::codec::codec_trait::TfCodec;
+use tfserver::futures_util::{SinkExt, StreamExt};
+use tfserver::structures::temp_transport::TempTransport;
+use tfserver::structures::transport::Transport;
+use tfserver::tokio_util::bytes::{Bytes, BytesMut};
+use tfserver::tokio_util::codec::{Decoder, Encoder, Framed, LengthDelimitedCodec};
+
+/// Codec of the auth listener. There is no account behind a connection yet, so only the
+/// server proves itself: it signs the handshake, including both sides' ephemeral X25519
+/// keys, with its identity key. The shared secret keys an encrypted channel like the one
+/// of `ServerEncriptedCodec`, so registrations, proofs, TOTP secrets and the tokens a login
+/// ends with are only readable by the client and the server it pinned.
+#[derive(Clone)]
+pub struct ServerAuthCodec {
+    identity: Arc<ServerIdentity>,
+    negotiated: Option<Negotiated>,
+    crypto: CryptoState,
+    base_codec: LengthDelimitedCodec,
+}
+
+impl ServerAuthCodec {
+    pub fn new(identity: Arc<ServerIdentity>) -> Self {
+        ServerAuthCodec {
+            identity,
+            negotiated: None,
+            crypto: CryptoState::Uninitialized,
+            base_codec: LengthDelimitedCodec::new(),
+        }
+    }
+
+    /// Protocol version and capabilities of the connection. Requests on this listener
+    /// carry no session to look them up by as `SessionRegistry::negotiated` does, nothing
+    /// here depends on them beyond the codec itself yet.
+    pub fn negotiated(&self) -> Option<Negotiated> {
+        self.negotiated
+    }
+}
+
+#[async_trait]
+impl TfCodec for ServerAuthCodec {
+    async fn initial_setup(&mut self, transport: &mut Transport) -> bool {
+        let tmp_transport = TempTransport::new(transport);
+        let mut framed = Framed::new(tmp_transport, LengthDelimitedCodec::new());
+
+        // The client's hello carries its nonce and ephemeral key, the reply to it goes out
+        // before anything else so a client with no common version learns why it was refused
+        let client_hello = match framed.next().await {
+            Some(Ok(v)) => v,
+            _ => return false,
+        };
+        let Some((hello, client_data)) = ClientHello::parse(&client_hello) else {
+            return false;
+        };
+        if client_data.len() != AUTH_NONCE_LEN + EPHEMERAL_KEY_LEN {
+            return false;
+        }
+        let client_ephemeral = &client_data[AUTH_NONCE_LEN..];
+        let hello_reply = hello.negotiate();
+        let hello_reply_frame = hello_reply.encode();
+        if framed
+            .send(Bytes::copy_from_slice(&hello_reply_frame))
+            .await
+            .is_err()
+        {
+            return false;
+        }
+        let HelloReply::Accepted(negotiated) = hello_reply else {
+            return false;
+        };
+
+        let mut server_nonce = [0u8; AUTH_NONCE_LEN];
+        rand::rng().fill_bytes(&mut server_nonce);
+        let (ephemeral_secret, server_ephemeral) = generate_ephemeral_key();
+        let Some(shared) = ephemeral_shared_secret(&ephemeral_secret, client_ephemeral) else {
+            return false;
+        };
+        drop(ephemeral_secret);
+        let public_key = self.identity.public_key();
+
+        let mut transcript = Transcript::new();
+        transcript.add(&client_hello);
+        transcript.add(&hello_reply_frame);
+        transcript.add(&server_nonce);
+        transcript.add(&server_ephemeral);
+        transcript.add(&public_key);
+        let binding = transcript.hash();
+
+        let mut reply = Vec::with_capacity(AUTH_NONCE_LEN + EPHEMERAL_KEY_LEN + 32 + 64);
+        reply.extend_from_slice(&server_nonce);
+        reply.extend_from_slice(&server_ephemeral);
+        reply.extend_from_slice(&public_key);
+        reply.extend_from_slice(&self.identity.sign(SIGN_AUTH_HANDSHAKE, &binding));
+        if framed.send(Bytes::from(reply)).await.is_err() {
+            return false;
+        }
+
+        let (client_traffic, server_traffic) = derive_auth_traffic_keys(&shared, &binding);
+        self.crypto = CryptoState::established(
+            CipherSuite::Aes256Gcm,
+            server_traffic,
+            NONCE_SERVER_TO_CLIENT,
+            client_traffic,
+            NONCE_CLIENT_TO_SERVER,
+            RekeyLimits::default(),
+            negotiated.capabilities.contains(Capabilities::KEY_UPDATE),
+        );
+        self.negotiated = Some(negotiated);
+        true
+    }
+}
+
+impl Decoder for ServerAuthCodec {
+    type Item = BytesMut;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let CryptoState::Established(traffic) = &mut self.crypto else {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"));
+        };
+
+        // Key updates carry nothing for the handler, keep reading past them
+        while let Some(data) = self.base_codec.decode(src)? {
+            if let Some(decrypted) = traffic.open(&data)? {
+                return Ok(Some(BytesMut::from(Bytes::from(decrypted))));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Encoder<Bytes> for ServerAuthCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: Bytes, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let CryptoState::Established(traffic) = &mut self.crypto else {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"));
+        };
+
+        for encrypted in traffic.seal(&item)? {
+            self.base_codec.encode(Bytes::from(encrypted), dst)?;
+        }
+        Ok(())
+    }
+}
@@ -1,153 +1,313 @@
+use crate::server::db::tokens_db::TokenRow;
 use crate::server::db::users_db::UsersDb;
+use crate::server::decoy_credentials::DecoyCredentials;
+use crate::server::login_throttle::{LoginThrottle, ThrottleKey};
+use crate::server::server_identity::ServerIdentity;
+use crate::server::session_guard;
+use crate::server::session_registry::{SessionOwner, SessionRegistry};
+use crate::structures::protocol_hello::{Capabilities, ClientHello, HelloReply, Negotiated};
 
-use crate::util::crypto::challenge_util::{generate_challenge, verify_challenge};
 use crate::util::crypto::codec_util::{
-    derive_handshake_key, derive_traffic_key, make_nonce, CryptoState, NONCE_CLIENT_TO_SERVER,
-    NONCE_SERVER_TO_CLIENT,
+    ephemeral_shared_secret, finished_mac, generate_ephemeral_key, verify_finished,
+    CipherSuite, CryptoState, HandshakeKeys, RekeyLimits, Transcript, HANDSHAKE_RESUME,
+    HANDSHAKE_SRP, NONCE_CLIENT_TO_SERVER, NONCE_MESSAGE_LEN, NONCE_SERVER_TO_CLIENT,
 };
-use aes_gcm::aead::consts::U12;
-use aes_gcm::aead::generic_array::GenericArray;
-use aes_gcm::aead::Aead;
-use aes_gcm::aes::Aes256;
-use aes_gcm::{Aes256Gcm, AesGcm, KeyInit, Nonce};
+use crate::util::crypto::identity_util::SIGN_ENCRYPTED_HANDSHAKE;
+use crate::util::crypto::srp_util::{
+    compute_server_public, generate_ephemeral_secret, process_client_reply,
+};
+use chrono::Utc;
 use diesel::r2d2::{ConnectionManager, Pool};
 use diesel::MysqlConnection;
-use hkdf::Hkdf;
-use rand::{Rng, RngCore};
-use sha2::Sha256;
+use rand::RngCore;
 use std::io;
-use std::io::Error;
+use std::sync::Arc;
 use tfserver::async_trait::async_trait;
 use tfserver::codec::codec_trait::TfCodec;
 use tfserver::structures::temp_transport::TempTransport;
 use tfserver::structures::transport::Transport;
-use tfserver::tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tfserver::tokio_util::bytes::{Bytes, BytesMut};
 use tfserver::tokio_util::codec::{Decoder, Encoder, Framed, LengthDelimitedCodec};
-use tfserver::futures_util::{SinkExt, StreamExt};
+use tfserver::futures_util::{Sink, SinkExt, Stream, StreamExt};
 #[derive(Clone)]
 pub struct ServerEncriptedCodec {
     pool: Pool<ConnectionManager<MysqlConnection>>,
+    throttle: Arc<LoginThrottle>,
+    registry: Arc<SessionRegistry>,
+    identity: Arc<ServerIdentity>,
+    decoy: Arc<DecoyCredentials>,
+    rekey_limits: RekeyLimits,
+    /// Most preferred first, the first one the client offers is used
+    cipher_suites: Vec<CipherSuite>,
+    /// Set once the handshake succeeded
+    owner: Option<SessionOwner>,
+    negotiated: Option<Negotiated>,
+    /// Id from `SessionRegistry::open_connection`, closed on drop
+    connection: Option<u64>,
     crypto: CryptoState,
     base_codec: LengthDelimitedCodec,
 }
 
 impl ServerEncriptedCodec {
-    pub fn new(pool: Pool<ConnectionManager<MysqlConnection>>) -> Self {
+    pub fn new(
+        pool: Pool<ConnectionManager<MysqlConnection>>,
+        throttle: Arc<LoginThrottle>,
+        registry: Arc<SessionRegistry>,
+        identity: Arc<ServerIdentity>,
+        decoy: Arc<DecoyCredentials>,
+        rekey_limits: RekeyLimits,
+        cipher_suites: Vec<CipherSuite>,
+    ) -> Self {
         ServerEncriptedCodec {
             pool,
+            throttle,
+            registry,
+            identity,
+            decoy,
+            rekey_limits,
+            cipher_suites,
+            owner: None,
+            negotiated: None,
+            connection: None,
             crypto: CryptoState::Uninitialized,
             base_codec: LengthDelimitedCodec::new(),
         }
     }
-}
-#[async_trait]
-impl TfCodec for ServerEncriptedCodec {
-    async fn initial_setup(&mut self, transport: &mut Transport) -> bool {
-        let tmp_transport = TempTransport::new(transport);
-        let mut framed = Framed::new(tmp_transport, LengthDelimitedCodec::new());
 
-        let login_msg = match framed.next().await {
+    /// Protocol version and capabilities of the connection. Request handlers read them
+    /// through `SessionRegistry::negotiated`.
+    pub fn negotiated(&self) -> Option<Negotiated> {
+        self.negotiated
+    }
+
+    /// Switches to the traffic keys of a finished handshake and registers the connection
+    fn establish(&mut self, crypto: CryptoState, negotiated: Negotiated, owner: SessionOwner) {
+        self.crypto = crypto;
+        self.negotiated = Some(negotiated);
+        self.owner = Some(owner);
+        self.connection = Some(self.registry.open_connection(owner, negotiated));
+    }
+
+    /// Full SRP exchange for `login`, returns the user id and the shared session key.
+    ///
+    /// Failures count towards the same `LoginThrottle` as `AuthHandler`. The transport
+    /// doesn't expose the peer address here, so they are counted under
+    /// `ThrottleKey::Handshake`, which only locks this handshake and never logins on the
+    /// auth listener.
+    ///
+    /// Accounts with 2FA or a pending deletion are refused after a correct proof, they
+    /// have to log in through `AuthHandler` and resume with the token.
+    async fn srp_handshake<F>(
+        &self,
+        framed: &mut F,
+        transcript: &mut Transcript,
+        login: &str,
+    ) -> Option<(u64, Vec<u8>)>
+    where
+        F: Stream<Item = io::Result<BytesMut>> + Sink<Bytes, Error = io::Error> + Unpin + Send,
+    {
+        let key = [ThrottleKey::handshake(login)];
+        if self.throttle.check(&key, Utc::now()).is_some() {
+            return None;
+        }
+
+        let client_public = match framed.next().await {
             Some(Ok(v)) => v,
-            _ => return false,
+            _ => return None,
+        };
+        transcript.add(&client_public);
+
+        // Logins that can't prove a password go through the exchange with decoy
+        // credentials and fail at the proof, the same as a wrong password
+        let mut conn = self.pool.get().ok()?;
+        let user = UsersDb::find_user_by_login(&mut conn, login)
+            .ok()
+            .filter(|user| user.srp_salt.is_some() && user.srp_verifier.is_some());
+        let (salt, verifier) = match &user {
+            Some(user) => (user.srp_salt.clone()?, user.srp_verifier.clone()?),
+            None => self.decoy.credential(login),
         };
 
-        let login = String::from_utf8_lossy(&login_msg).to_string();
+        let server_secret = generate_ephemeral_secret();
+        let server_public = compute_server_public(&server_secret, &verifier);
 
-        let mut conn = match self.pool.get() {
-            Ok(c) => c,
-            Err(_) => return false,
-        };
+        transcript.add(&salt);
+        transcript.add(&server_public);
+        framed.send(Bytes::from(salt)).await.ok()?;
+        framed.send(Bytes::from(server_public)).await.ok()?;
 
-        let user = match UsersDb::find_user_by_login(&mut conn, &login) {
-            Ok(u) => u,
-            Err(_) => return false,
+        let client_proof = match framed.next().await {
+            Some(Ok(v)) => v,
+            _ => return None,
         };
+        transcript.add(&client_proof);
 
-        let key = derive_handshake_key(user.password_hash.as_slice());
-        let cipher = match Aes256Gcm::new_from_slice(&key) {
-            Ok(res) => res,
-            Err(err) => {
-                eprintln!("{}", err.to_string());
-                return false;
-            }
+        let srp_session = process_client_reply(&server_secret, &verifier, &client_public);
+        let verified = srp_session.filter(|s| s.verify_client(&client_proof).is_ok());
+        let (Some(user), Some(srp_session)) = (user, verified) else {
+            self.throttle.record_failure(&key, Utc::now());
+            return None;
         };
+        self.throttle.record_success(&key[0]);
 
-        let mut nonce = [0u8; 12];
-        rand::rng().fill_bytes(&mut nonce);
-        
-        
-        
-        let (challenge, ciphertext) = generate_challenge(&cipher, &nonce, rand::rng().random_range(128..256), rand::rng().random_range(257..1024));
-
-        let msg = make_challenge_message(nonce, ciphertext);
-        if framed.send(Bytes::copy_from_slice(&msg)).await.is_err() {
-            return false;
+        if user.delete_after.is_some()
+            || session_guard::second_factor(&mut conn, user.id, user.require_2fa).is_some()
+        {
+            return None;
         }
 
-        let response = match framed.next().await {
+        transcript.add(srp_session.proof());
+        framed
+            .send(Bytes::copy_from_slice(srp_session.proof()))
+            .await
+            .ok()?;
+
+        Some((user.id, srp_session.key().to_vec()))
+    }
+
+    /// Resumes a session issued by the login flow, returns the token row whose session
+    /// secret keys the connection. No password key or SRP work is involved.
+    async fn resume_handshake(&self, token_hash: &[u8]) -> Option<TokenRow> {
+        let mut conn = self.pool.get().ok()?;
+        session_guard::resume_by_hash(&mut conn, token_hash)
+    }
+}
+#[async_trait]
+impl TfCodec for ServerEncriptedCodec {
+    async fn initial_setup(&mut self, transport: &mut Transport) -> bool {
+        let tmp_transport = TempTransport::new(transport);
+        let mut framed = Framed::new(tmp_transport, LengthDelimitedCodec::new());
+
+        let hello = match framed.next().await {
             Some(Ok(v)) => v,
             _ => return false,
         };
+        let mut transcript = Transcript::new();
+        transcript.add(&hello);
+        let Some((client_hello, rest)) = ClientHello::parse(&hello) else {
+            return false;
+        };
+        let Some((&mode, identity)) = rest.split_first() else {
+            return false;
+        };
 
-        if !verify_challenge(&challenge, &response) {
+        // Answered before any credential work, a client with no common version is told so
+        let hello_reply = client_hello.negotiate();
+        let hello_reply_frame = hello_reply.encode();
+        transcript.add(&hello_reply_frame);
+        if framed.send(Bytes::from(hello_reply_frame)).await.is_err() {
             return false;
         }
+        let HelloReply::Accepted(negotiated) = hello_reply else {
+            return false;
+        };
 
-        let mut client_nonce = [0u8; 12];
+        let epoch = self.registry.current_epoch();
+        let established = match mode {
+            HANDSHAKE_SRP => self
+                .srp_handshake(
+                    &mut framed,
+                    &mut transcript,
+                    &String::from_utf8_lossy(identity),
+                )
+                .await
+                .map(|(user_id, key)| (user_id, None, None, key)),
+            HANDSHAKE_RESUME => self
+                .resume_handshake(identity)
+                .await
+                .map(|row| (row.user_id, Some(row.id), row.device_id, row.session_secret)),
+            _ => None,
+        };
+        let Some((user_id, token_id, device_id, base_key)) = established else {
+            return false;
+        };
+
+        // Nonce and ephemeral X25519 key from each side, the client's first. The client
+        // follows with the cipher suites it offers, the server with the one it picked.
         let client_nonce_msg = match framed.next().await {
             Some(Ok(v)) => v,
             _ => return false,
         };
-        if client_nonce_msg.len() != 12 {
+        if client_nonce_msg.len() <= NONCE_MESSAGE_LEN {
             return false;
         }
-        client_nonce.copy_from_slice(&client_nonce_msg);
+        let client_ephemeral = &client_nonce_msg[12..NONCE_MESSAGE_LEN];
+        let Some(suite) =
+            CipherSuite::select(&self.cipher_suites, &client_nonce_msg[NONCE_MESSAGE_LEN..])
+        else {
+            return false;
+        };
+        transcript.add(&client_nonce_msg);
+
+        let (ephemeral_secret, server_ephemeral) = generate_ephemeral_key();
+        let Some(ephemeral_shared) = ephemeral_shared_secret(&ephemeral_secret, client_ephemeral)
+        else {
+            return false;
+        };
+        drop(ephemeral_secret);
 
         let mut server_nonce = [0u8; 12];
-        rand::thread_rng().fill_bytes(&mut server_nonce);
-        if framed.send(Bytes::copy_from_slice(&server_nonce)).await.is_err() {
+        rand::rng().fill_bytes(&mut server_nonce);
+        let mut server_nonce_msg = server_nonce.to_vec();
+        server_nonce_msg.extend_from_slice(&server_ephemeral);
+        server_nonce_msg.push(suite.id());
+        transcript.add(&server_nonce_msg);
+        if framed.send(Bytes::from(server_nonce_msg)).await.is_err() {
             return false;
         }
 
-        let traffic_key =
-            derive_traffic_key(user.password_hash.as_slice(), &client_nonce, &server_nonce);
+        let keys = HandshakeKeys::derive(&base_key, &ephemeral_shared, &transcript.hash());
 
-        let traffic_cipher = Aes256Gcm::new_from_slice(&traffic_key).unwrap();
+        // Proves the server holds its identity key, not just the user's verifier or token
+        let mut server_identity = self.identity.public_key().to_vec();
+        server_identity
+            .extend_from_slice(&self.identity.sign(SIGN_ENCRYPTED_HANDSHAKE, &transcript.hash()));
+        transcript.add(&server_identity);
+        if framed.send(Bytes::from(server_identity)).await.is_err() {
+            return false;
+        }
+
+        let server_finished = finished_mac(&keys.server_finished, &transcript.hash());
+        transcript.add(&server_finished);
+        if framed.send(Bytes::copy_from_slice(&server_finished)).await.is_err() {
+            return false;
+        }
+
+        let client_finished = match framed.next().await {
+            Some(Ok(v)) => v,
+            _ => return false,
+        };
+        if !verify_finished(&keys.client_finished, &transcript.hash(), &client_finished) {
+            return false;
+        }
 
-        self.crypto = CryptoState::Established {
-            cipher: traffic_cipher,
-            send_ctr: 0,
-            recv_ctr: 0,
+        let crypto = CryptoState::established(
+            suite,
+            keys.server_traffic,
+            NONCE_SERVER_TO_CLIENT,
+            keys.client_traffic,
+            NONCE_CLIENT_TO_SERVER,
+            self.rekey_limits,
+            negotiated.capabilities.contains(Capabilities::KEY_UPDATE),
+        );
+        let owner = SessionOwner {
+            user_id,
+            token_id,
+            device_id,
+            epoch,
         };
+        self.establish(crypto, negotiated, owner);
 
         true
     }
 }
 
-fn generate_nonce() -> [u8; 12] {
-    let mut n = [0u8; 12];
-    rand::thread_rng().fill_bytes(&mut n);
-    n
-}
-
-async fn exchange_traffic_nonces(transport: &mut Transport) -> Option<([u8; 12], [u8; 12])> {
-    // Client sends first
-    let mut client_nonce = [0u8; 12];
-    transport.read_exact(&mut client_nonce).await.ok()?;
-
-    // Server responds
-    let server_nonce = generate_nonce();
-    transport.write_all(&server_nonce).await.ok()?;
-
-    Some((client_nonce, server_nonce))
-}
-
-fn make_challenge_message(nonce: [u8; 12], challenge: Vec<u8>) -> Vec<u8> {
-    let mut res = Vec::with_capacity(challenge.len() + nonce.len());
-    res.extend_from_slice(&challenge);
-    res.extend_from_slice(&nonce);
-    res
+impl Drop for ServerEncriptedCodec {
+    fn drop(&mut self) {
+        if let Some(id) = self.connection.take() {
+            self.registry.close_connection(id);
+        }
+    }
 }
 
 impl Decoder for ServerEncriptedCodec {
@@ -155,25 +315,28 @@ impl Decoder for ServerEncriptedCodec {
     type Error = io::Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let CryptoState::Established {
-            cipher, recv_ctr, ..
-        } = &mut self.crypto
-        else {
-            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"));
-        };
+        if self
+            .owner
+            .as_ref()
+            .is_some_and(|owner| self.registry.is_revoked(owner))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "session revoked",
+            ));
+        }
 
-        let Some(data) = self.base_codec.decode(src)? else {
-            return Ok(None);
+        let CryptoState::Established(traffic) = &mut self.crypto else {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"));
         };
 
-        let nonce = make_nonce(*recv_ctr, NONCE_CLIENT_TO_SERVER);
-        *recv_ctr += 1;
-
-        let decrypted = cipher
-            .decrypt(&nonce, data.as_ref())
-            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"))?;
-
-        Ok(Some(BytesMut::from(Bytes::from(decrypted))))
+        // Key updates carry nothing for the handler, keep reading past them
+        while let Some(data) = self.base_codec.decode(src)? {
+            if let Some(decrypted) = traffic.open(&data)? {
+                return Ok(Some(BytesMut::from(Bytes::from(decrypted))));
+            }
+        }
+        Ok(None)
     }
 }
 
@@ -181,20 +344,93 @@ impl Encoder<Bytes> for ServerEncriptedCodec {
     type Error = io::Error;
 
     fn encode(&mut self, item: Bytes, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let CryptoState::Established {
-            cipher, send_ctr, ..
-        } = &mut self.crypto
-        else {
+        let CryptoState::Established(traffic) = &mut self.crypto else {
             return Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"));
         };
 
-        let nonce = make_nonce(*send_ctr, NONCE_SERVER_TO_CLIENT);
-        *send_ctr += 1;
+        for encrypted in traffic.seal(&item)? {
+            self.base_codec.encode(Bytes::from(encrypted), dst)?;
+        }
+        Ok(())
+    }
+}
 
-        let encrypted = cipher
-            .encrypt(&nonce, item.as_ref())
-            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"))?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::login_throttle::ThrottleConfig;
+    use crate::structures::protocol_hello::PROTOCOL_VERSION_MAX;
+
+    /// An established connection of user 1 resumed with `token_id`, the pool never
+    /// connects
+    fn resumed_connection(
+        registry: &Arc<SessionRegistry>,
+        token_id: u64,
+        capabilities: Capabilities,
+    ) -> ServerEncriptedCodec {
+        let pool = Pool::builder().build_unchecked(ConnectionManager::new("mysql://unused"));
+        let mut codec = ServerEncriptedCodec::new(
+            pool,
+            Arc::new(LoginThrottle::new(ThrottleConfig::default(), None)),
+            registry.clone(),
+            Arc::new(ServerIdentity::from_seed([7u8; 32])),
+            Arc::new(DecoyCredentials::generate()),
+            RekeyLimits::default(),
+            CipherSuite::ALL.to_vec(),
+        );
+        let crypto = CryptoState::established(
+            CipherSuite::Aes256Gcm,
+            [1u8; 32],
+            NONCE_SERVER_TO_CLIENT,
+            [2u8; 32],
+            NONCE_CLIENT_TO_SERVER,
+            RekeyLimits::default(),
+            capabilities.contains(Capabilities::KEY_UPDATE),
+        );
+        let negotiated = Negotiated {
+            version: PROTOCOL_VERSION_MAX,
+            capabilities,
+        };
+        let owner = SessionOwner {
+            user_id: 1,
+            token_id: Some(token_id),
+            device_id: None,
+            epoch: registry.current_epoch(),
+        };
+        codec.establish(crypto, negotiated, owner);
+        codec
+    }
+
+    #[test]
+    fn revoking_a_session_drops_only_its_connections() {
+        let registry = Arc::new(SessionRegistry::new());
+        let mut revoked = resumed_connection(&registry, 10, Capabilities::SUPPORTED);
+        let mut other = resumed_connection(&registry, 11, Capabilities::SUPPORTED);
+        let mut src = BytesMut::new();
+        assert!(revoked.decode(&mut src).unwrap().is_none());
+
+        registry.revoke_session(10);
+
+        let err = revoked.decode(&mut src).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(other.decode(&mut src).unwrap().is_none());
+    }
 
-        self.base_codec.encode(Bytes::from(encrypted), dst)
+    #[test]
+    fn handlers_read_the_negotiated_protocol_of_open_connections() {
+        let registry = Arc::new(SessionRegistry::new());
+        let first = resumed_connection(&registry, 10, Capabilities::SUPPORTED);
+        assert_eq!(registry.negotiated(10, 1), first.negotiated());
+        assert_eq!(registry.negotiated(11, 1), None);
+
+        // Only what both connections of the session support
+        let second = resumed_connection(&registry, 10, Capabilities::default());
+        let shared = registry.negotiated(10, 1).unwrap();
+        assert!(!shared.capabilities.contains(Capabilities::KEY_UPDATE));
+
+        drop(second);
+        assert_eq!(registry.negotiated(10, 1), first.negotiated());
+        drop(first);
+        assert_eq!(registry.negotiated(10, 1), None);
     }
 }
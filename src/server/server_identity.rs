@@ -0,0 +1,73 @@
+use crate::util::crypto::identity_util::{fingerprint, sign, IDENTITY_SIGNATURE_LEN};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ed25519_dalek::SigningKey;
+use rand::RngCore;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::{env, fs};
+
+const DEFAULT_IDENTITY_KEY_FILE: &str = "server_identity.key";
+
+/// Long-term Ed25519 key the server signs its handshakes with, so clients can tell it
+/// apart from anyone who merely got hold of the user table. Clients pin its
+/// `fingerprint`.
+pub struct ServerIdentity {
+    key: SigningKey,
+}
+
+/// `SERVER_IDENTITY_KEY_FILE`, or `server_identity.key` in the working directory
+pub fn identity_key_path_from_env() -> String {
+    env::var("SERVER_IDENTITY_KEY_FILE").unwrap_or_else(|_| DEFAULT_IDENTITY_KEY_FILE.to_string())
+}
+
+impl ServerIdentity {
+    /// Reads a key written by `generate`, the file holds the base64 of its 32 byte seed
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let seed = STANDARD
+            .decode(fs::read_to_string(path)?.trim())
+            .ok()
+            .and_then(|v| <[u8; 32]>::try_from(v).ok())
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "not a server identity key")
+            })?;
+        Ok(Self::from_seed(seed))
+    }
+
+    /// Creates a new key at `path`, readable by the owner only. An existing file is never
+    /// overwritten, replacing the key breaks every client that pinned it.
+    pub fn generate(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut seed = [0u8; 32];
+        rand::rng().fill_bytes(&mut seed);
+
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(path)?;
+        writeln!(file, "{}", STANDARD.encode(seed))?;
+
+        Ok(Self::from_seed(seed))
+    }
+
+    /// A key that isn't kept anywhere, `load` and `generate` are for real servers
+    pub(crate) fn from_seed(seed: [u8; 32]) -> Self {
+        Self {
+            key: SigningKey::from_bytes(&seed),
+        }
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        self.key.verifying_key().to_bytes()
+    }
+
+    pub fn fingerprint(&self) -> String {
+        fingerprint(&self.public_key())
+    }
+
+    /// Signs `message` for the purpose `label`, one of the `identity_util::SIGN_*` labels
+    pub fn sign(&self, label: &[u8], message: &[u8]) -> [u8; IDENTITY_SIGNATURE_LEN] {
+        sign(&self.key, label, message)
+    }
+}
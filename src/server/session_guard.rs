@@ -0,0 +1,199 @@
+use crate::server::access_keyring::AccessKeyring;
+use crate::server::db::devices_db::DevicesDb;
+use crate::server::db::tokens_db::{TokenRow, TokensDb};
+use crate::server::db::totp_db::TotpDb;
+use crate::server::db::users_db::UsersDb;
+use crate::server::session_registry::SessionRegistry;
+use crate::structures::error_code::ErrorCode;
+use crate::structures::protolink_stype::{DeviceRegistrationStruct, SessionTokenStruct};
+use crate::util::crypto::token_util::{hash_session_token, ACCESS_TOKEN_PREFIX};
+use chrono::{DateTime, Duration, Utc};
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::result::Error as DieselError;
+use diesel::{Connection, MysqlConnection};
+use std::net::SocketAddr;
+
+/// Lifetime of the token a login ends with
+pub const SESSION_TTL: Duration = Duration::hours(2);
+/// `devices.label` is VARCHAR(255), `devices.platform` VARCHAR(64)
+const MAX_DEVICE_LABEL_LEN: usize = 255;
+const MAX_DEVICE_PLATFORM_LEN: usize = 64;
+/// Bytes, room for any common public key encoding
+const MIN_DEVICE_KEY_LEN: usize = 16;
+const MAX_DEVICE_KEY_LEN: usize = 512;
+
+/// Tokens issued by `issue_session`
+pub struct IssuedSession {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+    pub device_id: Option<u64>,
+    pub access_token: String,
+    pub access_expires_at: DateTime<Utc>,
+}
+
+impl From<IssuedSession> for SessionTokenStruct {
+    fn from(issued: IssuedSession) -> Self {
+        SessionTokenStruct {
+            value: issued.token,
+            expires_at: issued.expires_at.timestamp(),
+            device_id: issued.device_id,
+            access_token: issued.access_token,
+            access_expires_at: issued.access_expires_at.timestamp(),
+        }
+    }
+}
+
+/// The session an authenticated request was made with
+#[derive(Clone, Copy, Debug)]
+pub struct AuthorizedSession {
+    /// `tokens` row of the session
+    pub id: u64,
+    pub user_id: u64,
+    pub device_id: Option<u64>,
+}
+
+/// Resolves the token carried by an authenticated request. Access tokens are checked
+/// against `keyring` and the revocations in `registry`, session tokens are looked up like
+/// `resume` does.
+///
+/// Returns None for unknown, forged, revoked or expired tokens.
+pub fn authorize(
+    conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+    keyring: &AccessKeyring,
+    registry: &SessionRegistry,
+    token: &str,
+) -> Option<AuthorizedSession> {
+    if token.starts_with(ACCESS_TOKEN_PREFIX) {
+        let claims = keyring.verify(token, Utc::now())?;
+        if registry.is_access_revoked(&claims) {
+            return None;
+        }
+        return Some(AuthorizedSession {
+            id: claims.session_id,
+            user_id: claims.user_id,
+            device_id: claims.device_id,
+        });
+    }
+    authorize_session(conn, token)
+}
+
+/// Like `authorize`, but only for session tokens. For requests that end sessions or
+/// change credentials, those are always checked against the database.
+pub fn authorize_session(
+    conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+    token: &str,
+) -> Option<AuthorizedSession> {
+    resume(conn, token).map(|row| AuthorizedSession {
+        id: row.id,
+        user_id: row.user_id,
+        device_id: row.device_id,
+    })
+}
+
+/// Resolves a session token and marks it and its device as used.
+///
+/// Returns None for unknown or expired tokens, and for access tokens.
+pub fn resume(
+    conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+    token: &str,
+) -> Option<TokenRow> {
+    resume_by_hash(conn, &hash_session_token(token)?)
+}
+
+/// Like `resume`, for the encrypted handshake, where clients only send the token's hash
+pub fn resume_by_hash(
+    conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+    token_hash: &[u8],
+) -> Option<TokenRow> {
+    let now = Utc::now().naive_utc();
+    let row = TokensDb::find_valid_token_by_hash(conn, token_hash, now).ok()?;
+    let _ = TokensDb::touch_token(conn, row.id, now);
+    if let Some(device_id) = row.device_id {
+        let _ = DevicesDb::touch_device(conn, device_id, now);
+    }
+    Some(row)
+}
+
+/// Checks a device a client wants to log in with, before any work is spent on the login
+pub fn check_device(device: &DeviceRegistrationStruct) -> Result<(), ErrorCode> {
+    let label = device.label.trim();
+    if label.is_empty()
+        || label.chars().count() > MAX_DEVICE_LABEL_LEN
+        || label.chars().any(char::is_control)
+    {
+        return Err(ErrorCode::InvalidDevice);
+    }
+    if device.platform.len() > MAX_DEVICE_PLATFORM_LEN
+        || !device.platform.bytes().all(|b| b.is_ascii_graphic())
+    {
+        return Err(ErrorCode::InvalidDevice);
+    }
+    if !(MIN_DEVICE_KEY_LEN..=MAX_DEVICE_KEY_LEN).contains(&device.public_key.len()) {
+        return Err(ErrorCode::InvalidDevice);
+    }
+    Ok(())
+}
+
+/// Creates the tokens a successful login ends with, registering `device` and binding them
+/// to it if the client sent one. Logging in cancels a pending account deletion. The rows
+/// are written in one transaction, a failed login leaves no device or token behind.
+#[allow(clippy::too_many_arguments)]
+pub fn issue_session(
+    conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+    keyring: &AccessKeyring,
+    user_id: u64,
+    session_secret: Vec<u8>,
+    client_label: String,
+    device: Option<&DeviceRegistrationStruct>,
+    peer: SocketAddr,
+    now: DateTime<Utc>,
+) -> Result<IssuedSession, DieselError> {
+    let expires_at = now + SESSION_TTL;
+    let (device_id, session_id, token) = conn.transaction(|conn| {
+        UsersDb::cancel_deletion(conn, user_id)?;
+        let device_id = match device {
+            Some(device) => Some(DevicesDb::register_device(
+                conn,
+                user_id,
+                device.label.trim(),
+                &device.platform,
+                &device.public_key,
+                now.naive_utc(),
+            )?),
+            None => None,
+        };
+        let (session_id, token) = TokensDb::create_token(
+            conn,
+            user_id,
+            expires_at.naive_utc(),
+            session_secret,
+            client_label,
+            peer.to_string(),
+            device_id,
+        )?;
+        Ok::<_, DieselError>((device_id, session_id, token))
+    })?;
+    let (access_token, access_expires_at) =
+        keyring.issue(user_id, session_id, device_id, expires_at, now);
+    Ok(IssuedSession {
+        token,
+        expires_at,
+        device_id,
+        access_token,
+        access_expires_at,
+    })
+}
+
+/// Whether a login of `user_id` needs a TOTP code: None if not, Some(true) if the user
+/// has to enroll first because the account requires 2FA
+pub fn second_factor(
+    conn: &mut PooledConnection<ConnectionManager<MysqlConnection>>,
+    user_id: u64,
+    require_2fa: bool,
+) -> Option<bool> {
+    match TotpDb::find_secret(conn, user_id) {
+        Ok(secret) if secret.confirmed => Some(false),
+        _ if require_2fa => Some(true),
+        _ => None,
+    }
+}
@@ -0,0 +1,159 @@
+use crate::structures::protocol_hello::Negotiated;
+use crate::util::crypto::token_util::AccessClaims;
+use chrono::Utc;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Who an encrypted connection was established for, see `SessionRegistry::is_revoked`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SessionOwner {
+    pub user_id: u64,
+    /// Token the connection resumed, None for a password handshake
+    pub token_id: Option<u64>,
+    /// Device of that token, if it is bound to one
+    pub device_id: Option<u64>,
+    /// `SessionRegistry::current_epoch` when the handshake started
+    pub epoch: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Revocation {
+    epoch: u64,
+    keep_token: Option<u64>,
+    /// Unix milliseconds, access tokens issued up to then are revoked too
+    at: i64,
+}
+
+/// Lets request handlers end encrypted connections they don't own. Connections keep their
+/// traffic key until they drop, so after a credential change `ServerEncriptedCodec` checks
+/// here on every frame and fails the ones established before it.
+///
+/// Access tokens are checked without a database lookup, `session_guard::authorize` asks
+/// `is_access_revoked` for the same reason.
+#[derive(Default)]
+pub struct SessionRegistry {
+    epoch: AtomicU64,
+    revocations: Mutex<HashMap<u64, Revocation>>,
+    /// Device ids are never reused, so these stay revoked for good
+    revoked_devices: Mutex<HashSet<u64>>,
+    /// Ended sessions, their access tokens and resumed connections. Token ids are never
+    /// reused either.
+    revoked_sessions: Mutex<HashSet<u64>>,
+    next_connection: AtomicU64,
+    /// Established encrypted connections and what their handshake negotiated
+    connections: Mutex<HashMap<u64, (SessionOwner, Negotiated)>>,
+}
+
+impl SessionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn current_epoch(&self) -> u64 {
+        self.epoch.load(Ordering::SeqCst)
+    }
+
+    /// Forces every connection of `user_id` established so far to re-handshake and
+    /// revokes every access token issued so far, except those of `keep_token`
+    pub fn revoke_user(&self, user_id: u64, keep_token: Option<u64>) {
+        let epoch = self.epoch.fetch_add(1, Ordering::SeqCst) + 1;
+        let at = Utc::now().timestamp_millis();
+        self.revocations.lock().unwrap().insert(
+            user_id,
+            Revocation {
+                epoch,
+                keep_token,
+                at,
+            },
+        );
+    }
+
+    /// Revokes the access tokens of an ended session and ends the connections resumed
+    /// with it
+    pub fn revoke_session(&self, session_id: u64) {
+        self.revoked_sessions.lock().unwrap().insert(session_id);
+    }
+
+    /// Ends every connection resumed with a token of `device_id`
+    pub fn revoke_device(&self, device_id: u64) {
+        self.revoked_devices.lock().unwrap().insert(device_id);
+    }
+
+    pub fn is_revoked(&self, owner: &SessionOwner) -> bool {
+        if owner
+            .device_id
+            .is_some_and(|device| self.revoked_devices.lock().unwrap().contains(&device))
+            || owner
+                .token_id
+                .is_some_and(|token| self.revoked_sessions.lock().unwrap().contains(&token))
+        {
+            return true;
+        }
+        let revocations = self.revocations.lock().unwrap();
+        let Some(revocation) = revocations.get(&owner.user_id) else {
+            return false;
+        };
+        revocation.epoch > owner.epoch
+            && (revocation.keep_token.is_none() || revocation.keep_token != owner.token_id)
+    }
+
+    /// Records an established connection, returns the id to pass to `close_connection`
+    /// once it drops
+    pub fn open_connection(&self, owner: SessionOwner, negotiated: Negotiated) -> u64 {
+        let id = self.next_connection.fetch_add(1, Ordering::SeqCst);
+        self.connections
+            .lock()
+            .unwrap()
+            .insert(id, (owner, negotiated));
+        id
+    }
+
+    pub fn close_connection(&self, id: u64) {
+        self.connections.lock().unwrap().remove(&id);
+    }
+
+    /// Protocol version and capabilities a request authorised for `session_id` of
+    /// `user_id` can rely on. `serve_route` only gets the peer address, which the
+    /// handshake can't see, so handlers look connections up by the session of the request
+    /// instead. With several connections open it is the lowest version and the
+    /// capabilities all of them share. Connections from a password handshake aren't tied
+    /// to a session and count for every session of their user.
+    ///
+    /// None if no encrypted connection of the session is open.
+    pub fn negotiated(&self, session_id: u64, user_id: u64) -> Option<Negotiated> {
+        self.connections
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|(owner, _)| match owner.token_id {
+                Some(token_id) => token_id == session_id,
+                None => owner.user_id == user_id,
+            })
+            .map(|(_, negotiated)| *negotiated)
+            .reduce(|a, b| Negotiated {
+                version: a.version.min(b.version),
+                capabilities: a.capabilities.intersect(b.capabilities),
+            })
+    }
+
+    /// Whether an access token was revoked with its session, its device or its user
+    pub fn is_access_revoked(&self, claims: &AccessClaims) -> bool {
+        if claims
+            .device_id
+            .is_some_and(|device| self.revoked_devices.lock().unwrap().contains(&device))
+            || self
+                .revoked_sessions
+                .lock()
+                .unwrap()
+                .contains(&claims.session_id)
+        {
+            return true;
+        }
+        let revocations = self.revocations.lock().unwrap();
+        let Some(revocation) = revocations.get(&claims.user_id) else {
+            return false;
+        };
+        revocation.at >= claims.issued_at && revocation.keep_token != Some(claims.session_id)
+    }
+}
@@ -1,13 +1,34 @@
-use crate::server::handlers::register_handler::AuthHandler;
+use crate::server::access_keyring::AccessKeyring;
+use crate::server::db::users_db::UsersDb;
+use crate::server::decoy_credentials::DecoyCredentials;
+use crate::server::deletion_policy::DeletionPolicy;
+use crate::server::event_hub::EventHub;
+use crate::server::expiry_sweeper::{spawn_expiry_sweeper, sweep_interval_from_env};
+use crate::server::handlers::admin_handler::AdminHandler;
+use crate::server::handlers::auth_handler::AuthHandler;
+use crate::server::handlers::register_handler::RegisterHandler;
+use crate::server::handlers::session_handler::SessionHandler;
+use crate::server::handlers::two_factor_handler::{TotpConfig, TwoFactorHandler};
 use crate::server::handlers::chat_handler::ChatHandler;
+use crate::server::handlers::event_handler::EventHandler;
+use crate::server::handlers::invite_handler::InviteHandler;
+use crate::server::handlers::profile_handler::ProfileHandler;
+use crate::server::invite_policy::InvitePolicy;
+use crate::server::login_throttle::{persistence_from_env, LoginThrottle, ThrottleConfig};
+use crate::server::pending_logins::PendingLogins;
+use crate::server::registration_policy::RegistrationPolicy;
+use crate::server::session_registry::SessionRegistry;
+use crate::server::server_auth_codec::ServerAuthCodec;
 use crate::server::server_encrypted_codec::ServerEncriptedCodec;
+use crate::server::server_identity::{identity_key_path_from_env, ServerIdentity};
 use crate::structures::protolink_stype::ProtoLinkSType;
+use crate::util::clock::SystemClock;
+use crate::util::crypto::codec_util::{CipherSuite, RekeyLimits};
 use diesel::r2d2::{ConnectionManager, Pool};
 use diesel::{r2d2, Connection, MysqlConnection};
 use dotenvy::dotenv;
 use std::env;
 use std::sync::Arc;
-use tfserver::codec::length_delimited::LengthDelimitedCodec;
 use tfserver::server::server_router::TcpServerRouter;
 use tfserver::server::tcp_server::TcpServer;
 use tfserver::tokio;
@@ -20,17 +41,67 @@ mod structures;
 
 async fn init_auth_server(
     pool: Arc<Mutex<Pool<ConnectionManager<MysqlConnection>>>>,
-) -> TcpServer<LengthDelimitedCodec> {
-    let enc_codec = LengthDelimitedCodec::new();
+    throttle: Arc<LoginThrottle>,
+    registry: Arc<SessionRegistry>,
+    deletion: DeletionPolicy,
+    keyring: Arc<AccessKeyring>,
+    identity: Arc<ServerIdentity>,
+    decoy: Arc<DecoyCredentials>,
+) -> TcpServer<ServerAuthCodec> {
+    let enc_codec = ServerAuthCodec::new(identity);
+    let policy = RegistrationPolicy::from_env();
+    let pending = Arc::new(PendingLogins::new());
 
-    let auth_handler = Arc::new(Mutex::new(AuthHandler::new(pool.clone())));
-    let mut router: TcpServerRouter<LengthDelimitedCodec> =
+    let register_handler = Arc::new(Mutex::new(RegisterHandler::new(
+        pool.clone(),
+        policy.clone(),
+        InvitePolicy::from_env(),
+    )));
+    let auth_handler = Arc::new(Mutex::new(AuthHandler::new(
+        pool.clone(),
+        throttle.clone(),
+        policy,
+        registry,
+        pending.clone(),
+        deletion,
+        keyring.clone(),
+        decoy,
+    )));
+    let two_factor_handler = Arc::new(Mutex::new(TwoFactorHandler::new(
+        pool.clone(),
+        pending,
+        throttle,
+        Arc::new(SystemClock),
+        TotpConfig::from_env(),
+        keyring,
+    )));
+    let mut router: TcpServerRouter<ServerAuthCodec> =
         TcpServerRouter::new(Box::new(ProtoLinkSType::AuthResponse));
     router.add_route(
-        auth_handler,
+        register_handler,
         "REGISTER_HANDLER".to_string(),
         vec![
             Box::new(ProtoLinkSType::RegisterRequest),
+            Box::new(ProtoLinkSType::KdfParamsRequest),
+        ],
+    );
+    router.add_route(
+        auth_handler,
+        "AUTH_HANDLER".to_string(),
+        vec![
+            Box::new(ProtoLinkSType::AuthRequest),
+            Box::new(ProtoLinkSType::AuthProof),
+            Box::new(ProtoLinkSType::ChangePassword),
+            Box::new(ProtoLinkSType::DeleteAccount),
+        ],
+    );
+    router.add_route(
+        two_factor_handler,
+        "TWO_FACTOR_HANDLER".to_string(),
+        vec![
+            Box::new(ProtoLinkSType::TotpEnroll),
+            Box::new(ProtoLinkSType::TotpConfirm),
+            Box::new(ProtoLinkSType::TotpVerify),
         ],
     );
     router.commit_routes();
@@ -41,8 +112,22 @@ async fn init_auth_server(
 async fn init_server(
     pool: Arc<Mutex<Pool<ConnectionManager<MysqlConnection>>>>,
     codec_pool: Pool<ConnectionManager<MysqlConnection>>,
-) {
-    let enc_codec = ServerEncriptedCodec::new(codec_pool);
+    throttle: Arc<LoginThrottle>,
+    registry: Arc<SessionRegistry>,
+    keyring: Arc<AccessKeyring>,
+    identity: Arc<ServerIdentity>,
+    decoy: Arc<DecoyCredentials>,
+) -> TcpServer<ServerEncriptedCodec> {
+    let events = Arc::new(EventHub::new());
+    let enc_codec = ServerEncriptedCodec::new(
+        codec_pool,
+        throttle.clone(),
+        registry.clone(),
+        identity,
+        decoy,
+        RekeyLimits::from_env(),
+        CipherSuite::preference_from_env(),
+    );
     let mut router: TcpServerRouter<ServerEncriptedCodec> =
         TcpServerRouter::new(Box::new(ProtoLinkSType::CreateChat));
 
@@ -52,14 +137,112 @@ async fn init_server(
         "CHAT_HANDLER".to_string(),
         vec![Box::new(ProtoLinkSType::CreateChat)],
     );
+
+    let session_handler = Arc::new(Mutex::new(SessionHandler::new(
+        pool.clone(),
+        registry.clone(),
+        keyring.clone(),
+    )));
+    router.add_route(
+        session_handler,
+        "SESSION_HANDLER".to_string(),
+        vec![
+            Box::new(ProtoLinkSType::ListSessions),
+            Box::new(ProtoLinkSType::Logout),
+            Box::new(ProtoLinkSType::RevokeSession),
+            Box::new(ProtoLinkSType::LogoutAllDevices),
+            Box::new(ProtoLinkSType::ListDevices),
+            Box::new(ProtoLinkSType::RevokeDevice),
+            Box::new(ProtoLinkSType::RefreshAccess),
+        ],
+    );
+
+    let admin_handler = Arc::new(Mutex::new(AdminHandler::new(
+        pool.clone(),
+        throttle,
+        registry.clone(),
+        keyring.clone(),
+    )));
+    router.add_route(
+        admin_handler,
+        "ADMIN_HANDLER".to_string(),
+        vec![Box::new(ProtoLinkSType::ListLockouts)],
+    );
+
+    let profile_handler = Arc::new(Mutex::new(ProfileHandler::new(
+        pool.clone(),
+        RegistrationPolicy::from_env(),
+        events.clone(),
+        registry.clone(),
+        keyring.clone(),
+    )));
+    router.add_route(
+        profile_handler,
+        "PROFILE_HANDLER".to_string(),
+        vec![
+            Box::new(ProtoLinkSType::GetUserProfile),
+            Box::new(ProtoLinkSType::GetUsersBatch),
+            Box::new(ProtoLinkSType::UpdateProfile),
+            Box::new(ProtoLinkSType::SearchUsers),
+        ],
+    );
+
+    let event_handler = Arc::new(Mutex::new(EventHandler::new(
+        pool.clone(),
+        events,
+        registry.clone(),
+        keyring.clone(),
+    )));
+    router.add_route(
+        event_handler,
+        "EVENT_HANDLER".to_string(),
+        vec![Box::new(ProtoLinkSType::PollEvents)],
+    );
+
+    let invite_handler = Arc::new(Mutex::new(InviteHandler::new(
+        pool.clone(),
+        InvitePolicy::from_env(),
+        registry,
+        keyring,
+    )));
+    router.add_route(
+        invite_handler,
+        "INVITE_HANDLER".to_string(),
+        vec![
+            Box::new(ProtoLinkSType::CreateInvite),
+            Box::new(ProtoLinkSType::ListInvites),
+            Box::new(ProtoLinkSType::RevokeInvite),
+        ],
+    );
     router.commit_routes();
     let router = Arc::new(router);
-    TcpServer::new("0.0.0.0:8090".to_string(), router, None, enc_codec, None).await;
+    TcpServer::new("0.0.0.0:8090".to_string(), router, None, enc_codec, None).await
+}
+
+/// `server keygen [path]` writes a new identity key and prints its fingerprint for
+/// clients to pin
+fn keygen(path: Option<String>) {
+    let path = path.unwrap_or_else(identity_key_path_from_env);
+    match ServerIdentity::generate(&path) {
+        Ok(identity) => println!("Wrote {}, fingerprint {}", path, identity.fingerprint()),
+        Err(err) => eprintln!("Could not create {}: {}", path, err),
+    }
 }
 
 #[tokio::main]
 pub async fn main() {
     dotenv().ok();
+    let mut args = env::args().skip(1);
+    if args.next().as_deref() == Some("keygen") {
+        keygen(args.next());
+        return;
+    }
+
+    let identity_path = identity_key_path_from_env();
+    let identity = Arc::new(ServerIdentity::load(&identity_path).unwrap_or_else(|err| {
+        panic!("Server identity key {} unusable ({}), run `server keygen`", identity_path, err)
+    }));
+    println!("Server identity {}", identity.fingerprint());
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     let manager = ConnectionManager::<MysqlConnection>::new(database_url.clone());
     let auth_pool = Arc::new(Mutex::new(
@@ -73,6 +256,15 @@ pub async fn main() {
         .build(manager)
         .expect("Failed to create pool.");
 
+    {
+        let mut conn = enc_pool.get().expect("DB connection failed");
+        match UsersDb::migrate_legacy_credentials(&mut conn) {
+            Ok(0) => {}
+            Ok(n) => println!("Converted {} legacy accounts to SRP verifiers", n),
+            Err(err) => eprintln!("Legacy credential migration failed: {}", err),
+        }
+    }
+
     let manager = ConnectionManager::<MysqlConnection>::new(database_url.clone());
     let server_pool = Arc::new(Mutex::new(
         r2d2::Pool::builder()
@@ -80,7 +272,46 @@ pub async fn main() {
             .expect("Failed to create pool."),
     ));
 
-    let mut auth_server = init_auth_server(auth_pool).await;
-    let mut server = init_server(server_pool.clone(), enc_pool).await;
-    auth_server.start().await.await;
+    let throttle = Arc::new(LoginThrottle::new(
+        ThrottleConfig::from_env(),
+        persistence_from_env().then(|| enc_pool.clone()),
+    ));
+
+    let deletion = DeletionPolicy::from_env();
+    spawn_expiry_sweeper(
+        enc_pool.clone(),
+        throttle.clone(),
+        deletion.clone(),
+        sweep_interval_from_env(),
+    );
+
+    let registry = Arc::new(SessionRegistry::new());
+    let keyring = Arc::new(AccessKeyring::from_env());
+    let decoy = Arc::new(DecoyCredentials::generate());
+
+    let mut auth_server = init_auth_server(
+        auth_pool,
+        throttle.clone(),
+        registry.clone(),
+        deletion,
+        keyring.clone(),
+        identity.clone(),
+        decoy.clone(),
+    )
+    .await;
+    let mut server = init_server(
+        server_pool.clone(),
+        enc_pool,
+        throttle,
+        registry,
+        keyring,
+        identity,
+        decoy,
+    )
+    .await;
+
+    // The auth listener and the encrypted one serve side by side
+    let auth_handle = auth_server.start().await;
+    let server_handle = server.start().await;
+    let _ = tokio::join!(auth_handle, server_handle);
 }
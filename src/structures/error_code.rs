@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Machine readable reason carried by every failed response, None on success.
+///
+/// Serialized by variant index, so new codes go at the end and existing ones are
+/// never reordered or removed.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    Internal,
+    Database,
+    NotFound,
+    AlreadyExists,
+    Unauthorized,
+    Forbidden,
+    UserNotFound,
+    InvalidCredentials,
+    ChallengeNotFound,
+    LockedOut,
+    TokenCreationFailed,
+    SessionNotFound,
+    LoginTaken,
+    LoginTooShort,
+    LoginTooLong,
+    LoginInvalidChars,
+    LoginMustStartWithLetter,
+    LoginReserved,
+    NameEmpty,
+    NameTooLong,
+    NameInvalidChars,
+    InvalidSalt,
+    InvalidVerifier,
+    KdfParamsRejected,
+    InvalidCredentialUpdate,
+    InvalidTotpCode,
+    PendingLoginNotFound,
+    TwoFactorUnavailable,
+    TwoFactorAlreadyEnabled,
+    BioTooLong,
+    BioInvalidChars,
+    InvalidAvatarRef,
+    BatchTooLarge,
+    QueryTooShort,
+    QueryTooLong,
+    InvalidDevice,
+    DeviceNotFound,
+    InviteRequired,
+    InvalidInvite,
+    InviteNotFound,
+    TooManyChallenges,
+}
+
+impl ErrorCode {
+    /// English fallback text, clients are expected to localise by code
+    pub fn description(&self) -> &'static str {
+        match self {
+            ErrorCode::Internal => "internal error",
+            ErrorCode::Database => "internal database error",
+            ErrorCode::NotFound => "not found",
+            ErrorCode::AlreadyExists => "already exists",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::Forbidden => "forbidden",
+            ErrorCode::UserNotFound => "user not found",
+            ErrorCode::InvalidCredentials => "incorrect",
+            ErrorCode::ChallengeNotFound => "challenge not found",
+            ErrorCode::LockedOut => "locked",
+            ErrorCode::TokenCreationFailed => "token creation failed",
+            ErrorCode::SessionNotFound => "session not found",
+            ErrorCode::LoginTaken => "login taken",
+            ErrorCode::LoginTooShort => "login too short",
+            ErrorCode::LoginTooLong => "login too long",
+            ErrorCode::LoginInvalidChars => "login contains invalid characters",
+            ErrorCode::LoginMustStartWithLetter => "login must start with a letter",
+            ErrorCode::LoginReserved => "login is reserved",
+            ErrorCode::NameEmpty => "name must not be empty",
+            ErrorCode::NameTooLong => "name too long",
+            ErrorCode::NameInvalidChars => "name contains invalid characters",
+            ErrorCode::InvalidSalt => "SRP salt has an invalid size",
+            ErrorCode::InvalidVerifier => "SRP verifier has an invalid size",
+            ErrorCode::KdfParamsRejected => "KDF parameters rejected",
+            ErrorCode::InvalidCredentialUpdate => "credential update rejected",
+            ErrorCode::InvalidTotpCode => "invalid code",
+            ErrorCode::PendingLoginNotFound => "login expired, start again",
+            ErrorCode::TwoFactorUnavailable => "two-factor authentication is not available",
+            ErrorCode::TwoFactorAlreadyEnabled => "two-factor authentication is already enabled",
+            ErrorCode::BioTooLong => "bio too long",
+            ErrorCode::BioInvalidChars => "bio contains invalid characters",
+            ErrorCode::InvalidAvatarRef => "invalid avatar reference",
+            ErrorCode::BatchTooLarge => "too many items in one request",
+            ErrorCode::QueryTooShort => "search query too short",
+            ErrorCode::QueryTooLong => "search query too long",
+            ErrorCode::InvalidDevice => "invalid device",
+            ErrorCode::DeviceNotFound => "device not found",
+            ErrorCode::InviteRequired => "registration requires an invite",
+            ErrorCode::InvalidInvite => "invite is invalid, expired or used up",
+            ErrorCode::InviteNotFound => "invite not found",
+            ErrorCode::TooManyChallenges => "too many logins in progress, try again later",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
@@ -1 +1,3 @@
 pub mod protolink_stype;
+pub mod error_code;
+pub mod protocol_hello;
@@ -0,0 +1,166 @@
+/// Starts every client hello and server reply, so anything else is refused outright
+pub const HELLO_MAGIC: [u8; 4] = *b"PLNK";
+
+/// Protocol versions this build speaks, a peer has to share at least one
+pub const PROTOCOL_VERSION_MIN: u16 = 1;
+pub const PROTOCOL_VERSION_MAX: u16 = 1;
+
+/// Magic, lowest and highest version, capability bits
+pub const CLIENT_HELLO_LEN: usize = 4 + 2 + 2 + 4;
+
+const REPLY_ACCEPTED: u8 = 0;
+const REPLY_UNSUPPORTED_VERSION: u8 = 1;
+
+/// Optional features either side may support. A connection uses those both announced,
+/// unknown bits are ignored so new ones can be added without a version bump.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    /// Encrypted listener: the peer follows `KeyUpdate` records, see `codec_util::RekeyLimits`
+    pub const KEY_UPDATE: Capabilities = Capabilities(1 << 0);
+
+    /// Everything this build supports
+    pub const SUPPORTED: Capabilities = Capabilities(Self::KEY_UPDATE.0);
+
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub fn from_bits(bits: u32) -> Self {
+        Capabilities(bits)
+    }
+
+    pub fn contains(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersect(self, other: Capabilities) -> Self {
+        Capabilities(self.0 & other.0)
+    }
+}
+
+/// What a handshake settled on, kept by the codec, see `ServerEncriptedCodec::negotiated`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Negotiated {
+    pub version: u16,
+    pub capabilities: Capabilities,
+}
+
+/// Opening frame of both listeners, each codec appends its own handshake data after it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientHello {
+    pub min_version: u16,
+    pub max_version: u16,
+    pub capabilities: Capabilities,
+}
+
+impl ClientHello {
+    /// The versions and capabilities of this build
+    pub fn ours() -> Self {
+        Self {
+            min_version: PROTOCOL_VERSION_MIN,
+            max_version: PROTOCOL_VERSION_MAX,
+            capabilities: Capabilities::SUPPORTED,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(CLIENT_HELLO_LEN);
+        out.extend_from_slice(&HELLO_MAGIC);
+        out.extend_from_slice(&self.min_version.to_be_bytes());
+        out.extend_from_slice(&self.max_version.to_be_bytes());
+        out.extend_from_slice(&self.capabilities.bits().to_be_bytes());
+        out
+    }
+
+    /// Splits a frame into the hello and what the codec appended, None without the magic
+    pub fn parse(frame: &[u8]) -> Option<(Self, &[u8])> {
+        if frame.len() < CLIENT_HELLO_LEN || frame[..4] != HELLO_MAGIC {
+            return None;
+        }
+        let hello = Self {
+            min_version: u16::from_be_bytes([frame[4], frame[5]]),
+            max_version: u16::from_be_bytes([frame[6], frame[7]]),
+            capabilities: Capabilities::from_bits(u32::from_be_bytes(
+                frame[8..12].try_into().unwrap(),
+            )),
+        };
+        Some((hello, &frame[CLIENT_HELLO_LEN..]))
+    }
+
+    /// Server side, the highest version both speak and the capabilities both support
+    pub fn negotiate(&self) -> HelloReply {
+        let version = self.max_version.min(PROTOCOL_VERSION_MAX);
+        if version < self.min_version.max(PROTOCOL_VERSION_MIN) {
+            return HelloReply::UnsupportedVersion {
+                min_version: PROTOCOL_VERSION_MIN,
+                max_version: PROTOCOL_VERSION_MAX,
+            };
+        }
+        HelloReply::Accepted(Negotiated {
+            version,
+            capabilities: self.capabilities.intersect(Capabilities::SUPPORTED),
+        })
+    }
+}
+
+/// The server's answer to a `ClientHello`, sent before anything else
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HelloReply {
+    Accepted(Negotiated),
+    /// None of the client's versions is supported, carries the server's range so the
+    /// client can tell the user which side to upgrade
+    UnsupportedVersion {
+        min_version: u16,
+        max_version: u16,
+    },
+}
+
+impl HelloReply {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = HELLO_MAGIC.to_vec();
+        match self {
+            HelloReply::Accepted(negotiated) => {
+                out.push(REPLY_ACCEPTED);
+                out.extend_from_slice(&negotiated.version.to_be_bytes());
+                out.extend_from_slice(&negotiated.capabilities.bits().to_be_bytes());
+            }
+            HelloReply::UnsupportedVersion {
+                min_version,
+                max_version,
+            } => {
+                out.push(REPLY_UNSUPPORTED_VERSION);
+                out.extend_from_slice(&min_version.to_be_bytes());
+                out.extend_from_slice(&max_version.to_be_bytes());
+            }
+        }
+        out
+    }
+
+    /// Client side, also checks the server picked something the client offered
+    pub fn parse(frame: &[u8], hello: &ClientHello) -> Option<Self> {
+        if frame.len() < 5 || frame[..4] != HELLO_MAGIC {
+            return None;
+        }
+        match (frame[4], &frame[5..]) {
+            (REPLY_ACCEPTED, [v0, v1, caps @ ..]) if caps.len() == 4 => {
+                let negotiated = Negotiated {
+                    version: u16::from_be_bytes([*v0, *v1]),
+                    capabilities: Capabilities::from_bits(u32::from_be_bytes(
+                        caps.try_into().unwrap(),
+                    )),
+                };
+                let offered = hello.min_version..=hello.max_version;
+                (offered.contains(&negotiated.version)
+                    && hello.capabilities.contains(negotiated.capabilities))
+                .then_some(HelloReply::Accepted(negotiated))
+            }
+            (REPLY_UNSUPPORTED_VERSION, [a0, a1, b0, b1]) => Some(HelloReply::UnsupportedVersion {
+                min_version: u16::from_be_bytes([*a0, *a1]),
+                max_version: u16::from_be_bytes([*b0, *b1]),
+            }),
+            _ => None,
+        }
+    }
+}
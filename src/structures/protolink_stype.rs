@@ -1,4 +1,6 @@
 use num_enum::TryFromPrimitive;
+use crate::structures::error_code::ErrorCode;
+use crate::util::crypto::kdf_util::KdfParams;
 use serde::{Deserialize, Serialize};
 use std::any::{Any, TypeId};
 use std::hash::{DefaultHasher, Hash, Hasher};
@@ -12,7 +14,45 @@ pub enum ProtoLinkSType {
     AuthResponse,
     CreateChat,
     ChatHandlerResponse,
-    AuthChallenge
+    AuthChallenge,
+    AuthProof,
+    KdfParamsRequest,
+    KdfParamsResponse,
+    ListSessions,
+    ListSessionsResponse,
+    Logout,
+    RevokeSession,
+    LogoutAllDevices,
+    SessionHandlerResponse,
+    ListLockouts,
+    ListLockoutsResponse,
+    ChangePassword,
+    TotpEnroll,
+    TotpEnrollResponse,
+    TotpConfirm,
+    TotpVerify,
+    TwoFactorResponse,
+    DeleteAccount,
+    GetUserProfile,
+    UserProfileResponse,
+    GetUsersBatch,
+    UsersBatchResponse,
+    UpdateProfile,
+    PollEvents,
+    PollEventsResponse,
+    SearchUsers,
+    SearchUsersResponse,
+    ListDevices,
+    ListDevicesResponse,
+    RevokeDevice,
+    CreateInvite,
+    CreateInviteResponse,
+    ListInvites,
+    ListInvitesResponse,
+    RevokeInvite,
+    InviteHandlerResponse,
+    RefreshAccess,
+    RefreshAccessResponse,
 }
 
 impl ProtoLinkSType {
@@ -35,6 +75,44 @@ impl StructureType for ProtoLinkSType {
             Self::CreateChat => TypeId::of::<CreateChatRequestStruct>(),
             Self::ChatHandlerResponse => TypeId::of::<ChatHandlerResponseStruct>(),
             Self::AuthChallenge => TypeId::of::<AuthChallenge>(),
+            Self::AuthProof => TypeId::of::<AuthProof>(),
+            Self::KdfParamsRequest => TypeId::of::<KdfParamsRequestStruct>(),
+            Self::KdfParamsResponse => TypeId::of::<KdfParamsResponse>(),
+            Self::ListSessions => TypeId::of::<ListSessionsRequestStruct>(),
+            Self::ListSessionsResponse => TypeId::of::<ListSessionsResponse>(),
+            Self::Logout => TypeId::of::<LogoutRequestStruct>(),
+            Self::RevokeSession => TypeId::of::<RevokeSessionRequestStruct>(),
+            Self::LogoutAllDevices => TypeId::of::<LogoutAllDevicesRequestStruct>(),
+            Self::SessionHandlerResponse => TypeId::of::<SessionHandlerResponseStruct>(),
+            Self::ListLockouts => TypeId::of::<ListLockoutsRequestStruct>(),
+            Self::ListLockoutsResponse => TypeId::of::<ListLockoutsResponse>(),
+            Self::ChangePassword => TypeId::of::<ChangePasswordRequestStruct>(),
+            Self::TotpEnroll => TypeId::of::<TotpEnrollRequestStruct>(),
+            Self::TotpEnrollResponse => TypeId::of::<TotpEnrollResponse>(),
+            Self::TotpConfirm => TypeId::of::<TotpConfirmRequestStruct>(),
+            Self::TotpVerify => TypeId::of::<TotpVerifyRequestStruct>(),
+            Self::TwoFactorResponse => TypeId::of::<TwoFactorResponseStruct>(),
+            Self::DeleteAccount => TypeId::of::<DeleteAccountRequestStruct>(),
+            Self::GetUserProfile => TypeId::of::<GetUserProfileRequestStruct>(),
+            Self::UserProfileResponse => TypeId::of::<UserProfileResponse>(),
+            Self::GetUsersBatch => TypeId::of::<GetUsersBatchRequestStruct>(),
+            Self::UsersBatchResponse => TypeId::of::<UsersBatchResponse>(),
+            Self::UpdateProfile => TypeId::of::<UpdateProfileRequestStruct>(),
+            Self::PollEvents => TypeId::of::<PollEventsRequestStruct>(),
+            Self::PollEventsResponse => TypeId::of::<PollEventsResponse>(),
+            Self::SearchUsers => TypeId::of::<SearchUsersRequestStruct>(),
+            Self::SearchUsersResponse => TypeId::of::<SearchUsersResponse>(),
+            Self::ListDevices => TypeId::of::<ListDevicesRequestStruct>(),
+            Self::ListDevicesResponse => TypeId::of::<ListDevicesResponse>(),
+            Self::RevokeDevice => TypeId::of::<RevokeDeviceRequestStruct>(),
+            Self::CreateInvite => TypeId::of::<CreateInviteRequestStruct>(),
+            Self::CreateInviteResponse => TypeId::of::<CreateInviteResponse>(),
+            Self::ListInvites => TypeId::of::<ListInvitesRequestStruct>(),
+            Self::ListInvitesResponse => TypeId::of::<ListInvitesResponse>(),
+            Self::RevokeInvite => TypeId::of::<RevokeInviteRequestStruct>(),
+            Self::InviteHandlerResponse => TypeId::of::<InviteHandlerResponseStruct>(),
+            Self::RefreshAccess => TypeId::of::<RefreshAccessRequestStruct>(),
+            Self::RefreshAccessResponse => TypeId::of::<RefreshAccessResponse>(),
         }
     }
 
@@ -72,50 +150,1252 @@ impl StructureType for ProtoLinkSType {
 #[derive(Serialize, Deserialize)]
 pub struct ChatHandlerResponseStruct {
     s_type: ProtoLinkSType,
-    success: bool,
-    message: String,
+    pub success: bool,
+    pub message: String,
+    pub error: Option<ErrorCode>,
+}
+
+impl ChatHandlerResponseStruct {
+    pub fn ok() -> Self {
+        Self {
+            s_type: ProtoLinkSType::ChatHandlerResponse,
+            success: true,
+            message: String::new(),
+            error: None,
+        }
+    }
+
+    pub fn error(code: ErrorCode) -> Self {
+        Self {
+            s_type: ProtoLinkSType::ChatHandlerResponse,
+            success: false,
+            message: code.description().to_string(),
+            error: Some(code),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateChatRequestStruct {
+    s_type: ProtoLinkSType,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RegisterRequestStruct {
+    s_type: ProtoLinkSType,
+    pub name: String,
+    pub login: String,
+    /// SRP-6a salt and verifier, the password key itself never leaves the client
+    pub srp_salt: Vec<u8>,
+    pub srp_verifier: Vec<u8>,
+    /// How `srp_verifier`'s password key was derived
+    pub kdf_params: KdfParams,
+    /// Required when the server only accepts invited users, ignored otherwise
+    pub invite_code: Option<String>,
+}
+
+/// Asks for the KDF parameters of `login` before deriving the password key
+#[derive(Serialize, Deserialize)]
+pub struct KdfParamsRequestStruct {
+    s_type: ProtoLinkSType,
+    pub login: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct KdfParamsResponse {
+    s_type: ProtoLinkSType,
+    pub login: String,
+    pub params: KdfParams,
+}
+
+/// Replacement credential, `mac` is `srp_util::credential_update_mac` keyed with the
+/// SRP session key of the exchange it travels with
+#[derive(Serialize, Deserialize)]
+pub struct CredentialUpdateStruct {
+    pub kdf_params: KdfParams,
+    pub srp_salt: Vec<u8>,
+    pub srp_verifier: Vec<u8>,
+    pub mac: Vec<u8>,
+}
+/// Server reply to `AuthRequestStruct`: SRP salt and server public ephemeral `B`
+#[derive(Serialize, Deserialize)]
+pub struct AuthChallenge {
+    s_type: ProtoLinkSType,
+    /// Echoed back in `AuthProof` so concurrent logins don't answer each other's challenge
+    pub challenge_id: u64,
+    pub login: String,
+    pub salt: Vec<u8>,
+    pub server_public: Vec<u8>,
+    /// Set when no challenge could be issued, the other fields are empty then
+    pub error: Option<ErrorCode>,
+    /// Unix seconds until which the client's address is refused challenges, 0 unless it
+    /// is locked out
+    pub locked_until: i64,
+}
+
+/// Client proof `M1` answering an `AuthChallenge`
+#[derive(Serialize, Deserialize)]
+pub struct AuthProof {
+    s_type: ProtoLinkSType,
+    pub challenge_id: u64,
+    pub login: String,
+    pub client_proof: Vec<u8>,
+    /// Free-form name of the client, shown back in `ListSessions`
+    pub client_label: String,
+    /// Set by clients logging in with `KdfParams::LegacyHkdf` to move to Argon2id
+    pub credential_update: Option<CredentialUpdateStruct>,
+    /// Binds the session to this device, registering it on its first login
+    pub device: Option<DeviceRegistrationStruct>,
+}
+
+/// A client install as it introduces itself at login. `public_key` is generated on the
+/// device and identifies it across logins.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeviceRegistrationStruct {
+    pub label: String,
+    /// Free-form, e.g. `linux` or `android`
+    pub platform: String,
+    pub public_key: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AuthRequestStruct {
+    s_type: ProtoLinkSType,
+    pub login: String,
+    /// Client public ephemeral `A`
+    pub client_public: Vec<u8>,
+}
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuthResponse {
+    pub success: bool,
+    pub s_type: ProtoLinkSType,
+    pub message: String,
+    /// Server proof `M2`, empty unless answering an `AuthProof`
+    pub server_proof: Vec<u8>,
+    /// Set when a login completed
+    pub session: Option<SessionTokenStruct>,
+    /// Unix seconds when the account will be purged, only set answering `DeleteAccount`
+    pub expires_at: i64,
+    /// Unix seconds until which further attempts are refused, 0 unless the login or the
+    /// client's address is locked out
+    pub locked_until: i64,
+    pub error: Option<ErrorCode>,
+    /// Set instead of a token when the proof was right but a TOTP code is still needed
+    pub pending_login: Option<PendingLoginStruct>,
+}
+
+/// Tokens a completed login ends with. Both are opaque to clients and either can be sent
+/// as `token` by authenticated requests.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SessionTokenStruct {
+    /// `plk_` followed by base64url of 32 random bytes. Resumes encrypted connections and
+    /// gets new access tokens with `RefreshAccess`.
+    pub value: String,
+    /// Unix seconds
+    pub expires_at: i64,
+    /// Device the token is bound to, None unless the login registered one
+    pub device_id: Option<u64>,
+    /// Short-lived `pla_` token the server checks without a database lookup, meant for
+    /// everyday requests
+    pub access_token: String,
+    /// Unix seconds
+    pub access_expires_at: i64,
+}
+
+/// Login waiting for its second factor, finished with `TotpVerifyRequestStruct`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PendingLoginStruct {
+    pub id: u64,
+    /// The account requires 2FA but has no authenticator yet, enroll with `TotpEnroll`
+    /// before verifying
+    pub enrollment_required: bool,
+    /// Unix seconds
+    pub expires_at: i64,
+}
+
+impl AuthResponse {
+    pub fn ok(message: String) -> Self {
+        Self {
+            success: true,
+            s_type: ProtoLinkSType::AuthResponse,
+            message,
+            server_proof: vec![],
+            session: None,
+            expires_at: 0,
+            locked_until: 0,
+            error: None,
+            pending_login: None,
+        }
+    }
+
+    pub fn error(code: ErrorCode) -> Self {
+        Self::error_with(code, code.description())
+    }
+
+    /// Like `error` with a more specific English `detail` in `message`
+    pub fn error_with(code: ErrorCode, detail: &str) -> Self {
+        Self {
+            success: false,
+            s_type: ProtoLinkSType::AuthResponse,
+            message: detail.to_string(),
+            server_proof: vec![],
+            session: None,
+            expires_at: 0,
+            locked_until: 0,
+            error: Some(code),
+            pending_login: None,
+        }
+    }
+
+    /// Too many failed attempts, the client should wait until `locked_until`
+    pub fn locked(locked_until: i64) -> Self {
+        let mut resp = Self::error(ErrorCode::LockedOut);
+        resp.locked_until = locked_until;
+        resp
+    }
+}
+
+/// Replaces the credential of the user `token` belongs to. `client_proof` answers an
+/// `AuthChallenge` for the old password and `credential_update.mac` is keyed with that
+/// exchange's session key. Answered with an `AuthResponse` carrying the server proof.
+/// `token` has to be the session token, access tokens are refused for this request.
+#[derive(Serialize, Deserialize)]
+pub struct ChangePasswordRequestStruct {
+    s_type: ProtoLinkSType,
+    pub token: String,
+    pub challenge_id: u64,
+    pub client_proof: Vec<u8>,
+    pub credential_update: CredentialUpdateStruct,
+}
+
+impl ChangePasswordRequestStruct {
+    pub fn new(
+        token: String,
+        challenge_id: u64,
+        client_proof: Vec<u8>,
+        credential_update: CredentialUpdateStruct,
+    ) -> Self {
+        Self {
+            s_type: ProtoLinkSType::ChangePassword,
+            token,
+            challenge_id,
+            client_proof,
+            credential_update,
+        }
+    }
+}
+
+/// Schedules the account of `token` for deletion. `client_proof` answers an
+/// `AuthChallenge` for the password. Answered with an `AuthResponse` carrying the server
+/// proof, its `expires_at` is when the account will be purged.
+/// `token` has to be the session token, access tokens are refused for this request.
+#[derive(Serialize, Deserialize)]
+pub struct DeleteAccountRequestStruct {
+    s_type: ProtoLinkSType,
+    pub token: String,
+    pub challenge_id: u64,
+    pub client_proof: Vec<u8>,
+}
+
+impl DeleteAccountRequestStruct {
+    pub fn new(token: String, challenge_id: u64, client_proof: Vec<u8>) -> Self {
+        Self {
+            s_type: ProtoLinkSType::DeleteAccount,
+            token,
+            challenge_id,
+            client_proof,
+        }
+    }
+}
+
+/// Starts TOTP enrollment. Authenticated by a session `token` together with
+/// `client_proof` answering an `AuthChallenge` for the password, or by `pending_login` for
+/// a login that is waiting on enrollment, in which case the other fields are empty.
+/// `token` has to be the session token, access tokens are refused for this request.
+#[derive(Serialize, Deserialize)]
+pub struct TotpEnrollRequestStruct {
+    s_type: ProtoLinkSType,
+    pub token: String,
+    pub challenge_id: u64,
+    pub client_proof: Vec<u8>,
+    pub pending_login: u64,
+}
+
+/// Shown to the user once: the base32 `secret`, the same as an `otpauth://` URI for QR
+/// codes, and single use backup codes
+#[derive(Serialize, Deserialize)]
+pub struct TotpEnrollResponse {
+    s_type: ProtoLinkSType,
+    pub success: bool,
+    pub message: String,
+    pub error: Option<ErrorCode>,
+    /// Unix seconds until which further attempts are refused, 0 unless a wrong password
+    /// proof locked the login or the client's address out
+    pub locked_until: i64,
+    pub secret: String,
+    pub otpauth_uri: String,
+    pub backup_codes: Vec<String>,
+}
+
+/// Finishes an enrollment started with a session token by proving the authenticator works
+/// `token` has to be the session token, access tokens are refused for this request.
+#[derive(Serialize, Deserialize)]
+pub struct TotpConfirmRequestStruct {
+    s_type: ProtoLinkSType,
+    pub token: String,
+    pub code: String,
+}
+
+/// Second step of a login that got a `PendingLoginStruct`. `code` is a TOTP code or a
+/// backup code, answered with an `AuthResponse` carrying the token.
+#[derive(Serialize, Deserialize)]
+pub struct TotpVerifyRequestStruct {
+    s_type: ProtoLinkSType,
+    pub pending_login: u64,
+    pub code: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TwoFactorResponseStruct {
+    s_type: ProtoLinkSType,
+    pub success: bool,
+    pub message: String,
+    pub error: Option<ErrorCode>,
+}
+
+impl TotpEnrollRequestStruct {
+    pub fn new(token: String, challenge_id: u64, client_proof: Vec<u8>) -> Self {
+        Self {
+            s_type: ProtoLinkSType::TotpEnroll,
+            token,
+            challenge_id,
+            client_proof,
+            pending_login: 0,
+        }
+    }
+
+    pub fn pending(pending_login: u64) -> Self {
+        Self {
+            s_type: ProtoLinkSType::TotpEnroll,
+            token: String::new(),
+            challenge_id: 0,
+            client_proof: vec![],
+            pending_login,
+        }
+    }
+}
+
+impl TotpEnrollResponse {
+    pub fn ok(secret: String, otpauth_uri: String, backup_codes: Vec<String>) -> Self {
+        Self {
+            s_type: ProtoLinkSType::TotpEnrollResponse,
+            success: true,
+            message: String::new(),
+            error: None,
+            locked_until: 0,
+            secret,
+            otpauth_uri,
+            backup_codes,
+        }
+    }
+
+    pub fn error(code: ErrorCode) -> Self {
+        Self {
+            s_type: ProtoLinkSType::TotpEnrollResponse,
+            success: false,
+            message: code.description().to_string(),
+            error: Some(code),
+            locked_until: 0,
+            secret: String::new(),
+            otpauth_uri: String::new(),
+            backup_codes: vec![],
+        }
+    }
+
+    pub fn locked(locked_until: i64) -> Self {
+        let mut resp = Self::error(ErrorCode::LockedOut);
+        resp.locked_until = locked_until;
+        resp
+    }
+}
+
+impl TotpConfirmRequestStruct {
+    pub fn new(token: String, code: String) -> Self {
+        Self {
+            s_type: ProtoLinkSType::TotpConfirm,
+            token,
+            code,
+        }
+    }
+}
+
+impl TotpVerifyRequestStruct {
+    pub fn new(pending_login: u64, code: String) -> Self {
+        Self {
+            s_type: ProtoLinkSType::TotpVerify,
+            pending_login,
+            code,
+        }
+    }
+}
+
+impl TwoFactorResponseStruct {
+    pub fn ok() -> Self {
+        Self {
+            s_type: ProtoLinkSType::TwoFactorResponse,
+            success: true,
+            message: String::new(),
+            error: None,
+        }
+    }
+
+    pub fn error(code: ErrorCode) -> Self {
+        Self {
+            s_type: ProtoLinkSType::TwoFactorResponse,
+            success: false,
+            message: code.description().to_string(),
+            error: Some(code),
+        }
+    }
+}
+
+impl AuthChallenge{
+    pub fn new(challenge_id: u64, login: String, salt: Vec<u8>, server_public: Vec<u8>) -> Self {
+        Self {
+            s_type: ProtoLinkSType::AuthChallenge,
+            challenge_id,
+            login,
+            salt,
+            server_public,
+            error: None,
+            locked_until: 0,
+        }
+    }
+
+    pub fn error(code: ErrorCode) -> Self {
+        let mut challenge = Self::new(0, String::new(), vec![], vec![]);
+        challenge.error = Some(code);
+        challenge
+    }
+
+    pub fn locked(locked_until: i64) -> Self {
+        let mut challenge = Self::error(ErrorCode::LockedOut);
+        challenge.locked_until = locked_until;
+        challenge
+    }
+}
+
+impl AuthProof {
+    pub fn new(challenge_id: u64, login: String, client_proof: Vec<u8>) -> Self {
+        Self {
+            s_type: ProtoLinkSType::AuthProof,
+            challenge_id,
+            login,
+            client_proof,
+            client_label: String::new(),
+            credential_update: None,
+            device: None,
+        }
+    }
+}
+
+impl KdfParamsRequestStruct {
+    pub fn new(login: String) -> Self {
+        Self {
+            s_type: ProtoLinkSType::KdfParamsRequest,
+            login,
+        }
+    }
+}
+
+impl KdfParamsResponse {
+    pub fn new(login: String, params: KdfParams) -> Self {
+        Self {
+            s_type: ProtoLinkSType::KdfParamsResponse,
+            login,
+            params,
+        }
+    }
+}
+
+impl AuthRequestStruct {
+    pub fn new(login: String, client_public: Vec<u8>) -> Self {
+        Self {
+            s_type: ProtoLinkSType::AuthRequest,
+            login,
+            client_public,
+        }
+    }
+}
+
+/// One row of `tokens` as shown to its owner, times are unix seconds
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SessionInfoStruct {
+    pub id: u64,
+    pub client_label: String,
+    pub peer_addr: String,
+    pub created_at: i64,
+    pub last_used_at: i64,
+    pub expires_at: i64,
+    /// Whether this is the session the request was made with
+    pub current: bool,
+    pub device_id: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ListSessionsRequestStruct {
+    s_type: ProtoLinkSType,
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ListSessionsResponse {
+    s_type: ProtoLinkSType,
+    pub success: bool,
+    pub message: String,
+    pub error: Option<ErrorCode>,
+    pub sessions: Vec<SessionInfoStruct>,
+}
+
+/// Ends the session `token` belongs to
+#[derive(Serialize, Deserialize)]
+pub struct LogoutRequestStruct {
+    s_type: ProtoLinkSType,
+    pub token: String,
+}
+
+/// Ends another session of the same user, `session_id` comes from `ListSessions`
+/// `token` has to be the session token, access tokens are refused for this request.
+#[derive(Serialize, Deserialize)]
+pub struct RevokeSessionRequestStruct {
+    s_type: ProtoLinkSType,
+    pub token: String,
+    pub session_id: u64,
+}
+
+/// Ends every session of the user, including the one making the request
+/// `token` has to be the session token, access tokens are refused for this request.
+#[derive(Serialize, Deserialize)]
+pub struct LogoutAllDevicesRequestStruct {
+    s_type: ProtoLinkSType,
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SessionHandlerResponseStruct {
+    s_type: ProtoLinkSType,
+    pub success: bool,
+    pub message: String,
+    pub error: Option<ErrorCode>,
+}
+
+impl ListSessionsRequestStruct {
+    pub fn new(token: String) -> Self {
+        Self {
+            s_type: ProtoLinkSType::ListSessions,
+            token,
+        }
+    }
+}
+
+impl ListSessionsResponse {
+    pub fn ok(sessions: Vec<SessionInfoStruct>) -> Self {
+        Self {
+            s_type: ProtoLinkSType::ListSessionsResponse,
+            success: true,
+            message: String::new(),
+            error: None,
+            sessions,
+        }
+    }
+
+    pub fn error(code: ErrorCode) -> Self {
+        Self {
+            s_type: ProtoLinkSType::ListSessionsResponse,
+            success: false,
+            message: code.description().to_string(),
+            error: Some(code),
+            sessions: vec![],
+        }
+    }
+}
+
+impl LogoutRequestStruct {
+    pub fn new(token: String) -> Self {
+        Self {
+            s_type: ProtoLinkSType::Logout,
+            token,
+        }
+    }
+}
+
+impl RevokeSessionRequestStruct {
+    pub fn new(token: String, session_id: u64) -> Self {
+        Self {
+            s_type: ProtoLinkSType::RevokeSession,
+            token,
+            session_id,
+        }
+    }
+}
+
+impl LogoutAllDevicesRequestStruct {
+    pub fn new(token: String) -> Self {
+        Self {
+            s_type: ProtoLinkSType::LogoutAllDevices,
+            token,
+        }
+    }
+}
+
+impl SessionHandlerResponseStruct {
+    pub fn ok() -> Self {
+        Self {
+            s_type: ProtoLinkSType::SessionHandlerResponse,
+            success: true,
+            message: String::new(),
+            error: None,
+        }
+    }
+
+    pub fn error(code: ErrorCode) -> Self {
+        Self {
+            s_type: ProtoLinkSType::SessionHandlerResponse,
+            success: false,
+            message: code.description().to_string(),
+            error: Some(code),
+        }
+    }
+}
+
+/// One row of `devices` as shown to its owner, times are unix seconds
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeviceInfoStruct {
+    pub id: u64,
+    pub label: String,
+    pub platform: String,
+    pub public_key: Vec<u8>,
+    pub created_at: i64,
+    pub last_seen_at: i64,
+    /// Whether the request was made from this device
+    pub current: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ListDevicesRequestStruct {
+    s_type: ProtoLinkSType,
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ListDevicesResponse {
+    s_type: ProtoLinkSType,
+    pub success: bool,
+    pub message: String,
+    pub error: Option<ErrorCode>,
+    pub devices: Vec<DeviceInfoStruct>,
+}
+
+/// Forgets a device of the same user and ends its sessions and connections, `device_id`
+/// comes from `ListDevices`. Answered with a `SessionHandlerResponseStruct`.
+/// `token` has to be the session token, access tokens are refused for this request.
+#[derive(Serialize, Deserialize)]
+pub struct RevokeDeviceRequestStruct {
+    s_type: ProtoLinkSType,
+    pub token: String,
+    pub device_id: u64,
+}
+
+impl ListDevicesRequestStruct {
+    pub fn new(token: String) -> Self {
+        Self {
+            s_type: ProtoLinkSType::ListDevices,
+            token,
+        }
+    }
+}
+
+impl ListDevicesResponse {
+    pub fn ok(devices: Vec<DeviceInfoStruct>) -> Self {
+        Self {
+            s_type: ProtoLinkSType::ListDevicesResponse,
+            success: true,
+            message: String::new(),
+            error: None,
+            devices,
+        }
+    }
+
+    pub fn error(code: ErrorCode) -> Self {
+        Self {
+            s_type: ProtoLinkSType::ListDevicesResponse,
+            success: false,
+            message: code.description().to_string(),
+            error: Some(code),
+            devices: vec![],
+        }
+    }
+}
+
+impl RevokeDeviceRequestStruct {
+    pub fn new(token: String, device_id: u64) -> Self {
+        Self {
+            s_type: ProtoLinkSType::RevokeDevice,
+            token,
+            device_id,
+        }
+    }
+}
+
+/// Trades the session token in `token` for a new access token. Access tokens aren't
+/// accepted here.
+#[derive(Serialize, Deserialize)]
+pub struct RefreshAccessRequestStruct {
+    s_type: ProtoLinkSType,
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RefreshAccessResponse {
+    s_type: ProtoLinkSType,
+    pub success: bool,
+    pub message: String,
+    pub error: Option<ErrorCode>,
+    pub access_token: String,
+    /// Unix seconds, never later than the session's own expiry
+    pub expires_at: i64,
+}
+
+impl RefreshAccessRequestStruct {
+    pub fn new(token: String) -> Self {
+        Self {
+            s_type: ProtoLinkSType::RefreshAccess,
+            token,
+        }
+    }
+}
+
+impl RefreshAccessResponse {
+    pub fn ok(access_token: String, expires_at: i64) -> Self {
+        Self {
+            s_type: ProtoLinkSType::RefreshAccessResponse,
+            success: true,
+            message: String::new(),
+            error: None,
+            access_token,
+            expires_at,
+        }
+    }
+
+    pub fn error(code: ErrorCode) -> Self {
+        Self {
+            s_type: ProtoLinkSType::RefreshAccessResponse,
+            success: false,
+            message: code.description().to_string(),
+            error: Some(code),
+            access_token: String::new(),
+            expires_at: 0,
+        }
+    }
+}
+
+/// An invite as shown to its creator, times are unix seconds. The code itself is only
+/// in the `CreateInviteResponse`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InviteInfoStruct {
+    pub id: u64,
+    pub created_by: u64,
+    pub max_uses: u32,
+    pub uses: u32,
+    pub expires_at: i64,
+    pub created_at: i64,
+    pub revoked: bool,
+}
+
+/// `max_uses` 0 means a single use, `valid_for_secs` 0 the server's default lifetime.
+/// Both are capped by the server.
+#[derive(Serialize, Deserialize)]
+pub struct CreateInviteRequestStruct {
+    s_type: ProtoLinkSType,
+    pub token: String,
+    pub max_uses: u32,
+    pub valid_for_secs: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateInviteResponse {
+    s_type: ProtoLinkSType,
+    pub success: bool,
+    pub message: String,
+    pub error: Option<ErrorCode>,
+    /// What the invitee enters when registering, not retrievable later
+    pub code: String,
+    pub invite: Option<InviteInfoStruct>,
+}
+
+/// The user's own invites, every invite for admins
+#[derive(Serialize, Deserialize)]
+pub struct ListInvitesRequestStruct {
+    s_type: ProtoLinkSType,
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ListInvitesResponse {
+    s_type: ProtoLinkSType,
+    pub success: bool,
+    pub message: String,
+    pub error: Option<ErrorCode>,
+    pub invites: Vec<InviteInfoStruct>,
+}
+
+/// Stops an invite from being used again, accounts already made with it stay.
+/// Admins may revoke anyone's invites.
+#[derive(Serialize, Deserialize)]
+pub struct RevokeInviteRequestStruct {
+    s_type: ProtoLinkSType,
+    pub token: String,
+    pub invite_id: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct InviteHandlerResponseStruct {
+    s_type: ProtoLinkSType,
+    pub success: bool,
+    pub message: String,
+    pub error: Option<ErrorCode>,
+}
+
+impl CreateInviteRequestStruct {
+    pub fn new(token: String, max_uses: u32, valid_for_secs: u64) -> Self {
+        Self {
+            s_type: ProtoLinkSType::CreateInvite,
+            token,
+            max_uses,
+            valid_for_secs,
+        }
+    }
+}
+
+impl CreateInviteResponse {
+    pub fn ok(code: String, invite: InviteInfoStruct) -> Self {
+        Self {
+            s_type: ProtoLinkSType::CreateInviteResponse,
+            success: true,
+            message: String::new(),
+            error: None,
+            code,
+            invite: Some(invite),
+        }
+    }
+
+    pub fn error(code: ErrorCode) -> Self {
+        Self {
+            s_type: ProtoLinkSType::CreateInviteResponse,
+            success: false,
+            message: code.description().to_string(),
+            error: Some(code),
+            code: String::new(),
+            invite: None,
+        }
+    }
+}
+
+impl ListInvitesRequestStruct {
+    pub fn new(token: String) -> Self {
+        Self {
+            s_type: ProtoLinkSType::ListInvites,
+            token,
+        }
+    }
+}
+
+impl ListInvitesResponse {
+    pub fn ok(invites: Vec<InviteInfoStruct>) -> Self {
+        Self {
+            s_type: ProtoLinkSType::ListInvitesResponse,
+            success: true,
+            message: String::new(),
+            error: None,
+            invites,
+        }
+    }
+
+    pub fn error(code: ErrorCode) -> Self {
+        Self {
+            s_type: ProtoLinkSType::ListInvitesResponse,
+            success: false,
+            message: code.description().to_string(),
+            error: Some(code),
+            invites: vec![],
+        }
+    }
+}
+
+impl RevokeInviteRequestStruct {
+    pub fn new(token: String, invite_id: u64) -> Self {
+        Self {
+            s_type: ProtoLinkSType::RevokeInvite,
+            token,
+            invite_id,
+        }
+    }
+}
+
+impl InviteHandlerResponseStruct {
+    pub fn ok() -> Self {
+        Self {
+            s_type: ProtoLinkSType::InviteHandlerResponse,
+            success: true,
+            message: String::new(),
+            error: None,
+        }
+    }
+
+    pub fn error(code: ErrorCode) -> Self {
+        Self {
+            s_type: ProtoLinkSType::InviteHandlerResponse,
+            success: false,
+            message: code.description().to_string(),
+            error: Some(code),
+        }
+    }
+}
+
+/// An address currently locked out, or a login on the encrypted listener. Times are unix
+/// seconds.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LockoutInfoStruct {
+    /// `addr:<ip>` or `handshake:<login>`
+    pub key: String,
+    pub failures: u32,
+    pub locked_until: i64,
+}
+
+/// Admin only
+#[derive(Serialize, Deserialize)]
+pub struct ListLockoutsRequestStruct {
+    s_type: ProtoLinkSType,
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ListLockoutsResponse {
+    s_type: ProtoLinkSType,
+    pub success: bool,
+    pub message: String,
+    pub error: Option<ErrorCode>,
+    pub lockouts: Vec<LockoutInfoStruct>,
+}
+
+impl ListLockoutsRequestStruct {
+    pub fn new(token: String) -> Self {
+        Self {
+            s_type: ProtoLinkSType::ListLockouts,
+            token,
+        }
+    }
+}
+
+impl ListLockoutsResponse {
+    pub fn ok(lockouts: Vec<LockoutInfoStruct>) -> Self {
+        Self {
+            s_type: ProtoLinkSType::ListLockoutsResponse,
+            success: true,
+            message: String::new(),
+            error: None,
+            lockouts,
+        }
+    }
+
+    pub fn error(code: ErrorCode) -> Self {
+        Self {
+            s_type: ProtoLinkSType::ListLockoutsResponse,
+            success: false,
+            message: code.description().to_string(),
+            error: Some(code),
+            lockouts: vec![],
+        }
+    }
+}
+
+/// Public part of a user. Deleted accounts keep their id with `deleted` set and every
+/// other field empty. Times are unix seconds.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct UserProfileStruct {
+    pub user_id: u64,
+    pub login: String,
+    /// The chosen display name, the registration name if none was set
+    pub display_name: String,
+    pub bio: String,
+    /// Reference to the avatar blob, None without an avatar
+    pub avatar_ref: Option<String>,
+    pub created_at: i64,
+    pub deleted: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetUserProfileRequestStruct {
+    s_type: ProtoLinkSType,
+    pub token: String,
+    pub user_id: u64,
+}
+
+/// Answers `GetUserProfile` and `UpdateProfile`, the latter with the updated profile
+#[derive(Serialize, Deserialize)]
+pub struct UserProfileResponse {
+    s_type: ProtoLinkSType,
+    pub success: bool,
+    pub message: String,
+    pub error: Option<ErrorCode>,
+    pub profile: Option<UserProfileStruct>,
+}
+
+/// Looks up several users at once, ids that don't exist are left out of the answer
+#[derive(Serialize, Deserialize)]
+pub struct GetUsersBatchRequestStruct {
+    s_type: ProtoLinkSType,
+    pub token: String,
+    pub user_ids: Vec<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UsersBatchResponse {
+    s_type: ProtoLinkSType,
+    pub success: bool,
+    pub message: String,
+    pub error: Option<ErrorCode>,
+    pub profiles: Vec<UserProfileStruct>,
+}
+
+/// Changes the profile of the user `token` belongs to. None leaves a field as it is, an
+/// empty string clears it.
+#[derive(Serialize, Deserialize)]
+pub struct UpdateProfileRequestStruct {
+    s_type: ProtoLinkSType,
+    pub token: String,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub avatar_ref: Option<String>,
+    /// Whether the user can be found with `SearchUsers`
+    pub discoverable: Option<bool>,
+}
+
+/// Something that happened to another user the client should know about
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum ServerEventStruct {
+    /// Sent to the user and to everyone sharing a chat with them
+    ProfileUpdated(UserProfileStruct),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EventStruct {
+    /// Increasing, pass the last one seen as `PollEventsRequestStruct::after`
+    pub seq: u64,
+    pub event: ServerEventStruct,
+}
+
+/// Fetches queued events newer than `after`, 0 for all. Events are only delivered this
+/// way, clients poll for them.
+#[derive(Serialize, Deserialize)]
+pub struct PollEventsRequestStruct {
+    s_type: ProtoLinkSType,
+    pub token: String,
+    pub after: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PollEventsResponse {
+    s_type: ProtoLinkSType,
+    pub success: bool,
+    pub message: String,
+    pub error: Option<ErrorCode>,
+    pub events: Vec<EventStruct>,
+    /// Pass as `after` next time, also when `events` is empty
+    pub cursor: u64,
+    /// Events since `after` were lost, to a server restart or a full inbox. Whatever the
+    /// client shows from them should be fetched again.
+    pub missed: bool,
+}
+
+impl GetUserProfileRequestStruct {
+    pub fn new(token: String, user_id: u64) -> Self {
+        Self {
+            s_type: ProtoLinkSType::GetUserProfile,
+            token,
+            user_id,
+        }
+    }
+}
+
+impl UserProfileResponse {
+    pub fn ok(profile: Option<UserProfileStruct>) -> Self {
+        Self {
+            s_type: ProtoLinkSType::UserProfileResponse,
+            success: true,
+            message: String::new(),
+            error: None,
+            profile,
+        }
+    }
+
+    pub fn error(code: ErrorCode) -> Self {
+        Self {
+            s_type: ProtoLinkSType::UserProfileResponse,
+            success: false,
+            message: code.description().to_string(),
+            error: Some(code),
+            profile: None,
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct CreateChatRequestStruct {
-    s_type: ProtoLinkSType,
+impl GetUsersBatchRequestStruct {
+    pub fn new(token: String, user_ids: Vec<u64>) -> Self {
+        Self {
+            s_type: ProtoLinkSType::GetUsersBatch,
+            token,
+            user_ids,
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct RegisterRequestStruct {
-    s_type: ProtoLinkSType,
-    pub name: String,
-    pub login: String,
-    pub password_hash_sha256_hkdf: Vec<u8>,
+impl UsersBatchResponse {
+    pub fn ok(profiles: Vec<UserProfileStruct>) -> Self {
+        Self {
+            s_type: ProtoLinkSType::UsersBatchResponse,
+            success: true,
+            message: String::new(),
+            error: None,
+            profiles,
+        }
+    }
+
+    pub fn error(code: ErrorCode) -> Self {
+        Self {
+            s_type: ProtoLinkSType::UsersBatchResponse,
+            success: false,
+            message: code.description().to_string(),
+            error: Some(code),
+            profiles: vec![],
+        }
+    }
 }
-#[derive(Serialize, Deserialize)]
-pub struct AuthChallenge {
-    s_type: ProtoLinkSType,
-    pub nonce: [u8; 12],
-    pub challenge: Vec<u8>,
-    pub login: String,
+
+impl UpdateProfileRequestStruct {
+    pub fn new(token: String) -> Self {
+        Self {
+            s_type: ProtoLinkSType::UpdateProfile,
+            token,
+            display_name: None,
+            bio: None,
+            avatar_ref: None,
+            discoverable: None,
+        }
+    }
 }
 
+impl PollEventsRequestStruct {
+    pub fn new(token: String, after: u64) -> Self {
+        Self {
+            s_type: ProtoLinkSType::PollEvents,
+            token,
+            after,
+        }
+    }
+}
+
+impl PollEventsResponse {
+    pub fn ok(events: Vec<EventStruct>, cursor: u64, missed: bool) -> Self {
+        Self {
+            s_type: ProtoLinkSType::PollEventsResponse,
+            success: true,
+            message: String::new(),
+            error: None,
+            events,
+            cursor,
+            missed,
+        }
+    }
 
+    pub fn error(code: ErrorCode) -> Self {
+        Self {
+            s_type: ProtoLinkSType::PollEventsResponse,
+            success: false,
+            message: code.description().to_string(),
+            error: Some(code),
+            events: vec![],
+            cursor: 0,
+            missed: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum SearchMode {
+    /// Login or display name starts with the query
+    Prefix,
+    /// Login or display name contains the query
+    Substring,
+}
+
+/// Searches users who didn't opt out of the directory. Pages are ordered by login, pass
+/// `next_cursor` of the previous page as `cursor` to continue.
 #[derive(Serialize, Deserialize)]
-pub struct AuthRequestStruct {
+pub struct SearchUsersRequestStruct {
     s_type: ProtoLinkSType,
-    pub login: String,
+    pub token: String,
+    pub query: String,
+    pub mode: SearchMode,
+    pub cursor: Option<String>,
+    /// Results per page, the server caps it
+    pub limit: u32,
 }
-#[derive(Serialize, Deserialize, Debug)]
-pub struct AuthResponse {
+
+#[derive(Serialize, Deserialize)]
+pub struct SearchUsersResponse {
+    s_type: ProtoLinkSType,
     pub success: bool,
-    pub s_type: ProtoLinkSType,
     pub message: String,
+    pub error: Option<ErrorCode>,
+    pub profiles: Vec<UserProfileStruct>,
+    /// None on the last page
+    pub next_cursor: Option<String>,
 }
 
-impl AuthChallenge{
-    pub fn new(challenge: Vec<u8>, nonce: [u8;12], login: String) -> Self {
+impl SearchUsersRequestStruct {
+    pub fn new(token: String, query: String, mode: SearchMode) -> Self {
         Self {
-            s_type: ProtoLinkSType::AuthChallenge,
-            nonce,
-            challenge,
-            login
+            s_type: ProtoLinkSType::SearchUsers,
+            token,
+            query,
+            mode,
+            cursor: None,
+            limit: 0,
+        }
+    }
+}
+
+impl SearchUsersResponse {
+    pub fn ok(profiles: Vec<UserProfileStruct>, next_cursor: Option<String>) -> Self {
+        Self {
+            s_type: ProtoLinkSType::SearchUsersResponse,
+            success: true,
+            message: String::new(),
+            error: None,
+            profiles,
+            next_cursor,
+        }
+    }
+
+    pub fn error(code: ErrorCode) -> Self {
+        Self {
+            s_type: ProtoLinkSType::SearchUsersResponse,
+            success: false,
+            message: code.description().to_string(),
+            error: Some(code),
+            profiles: vec![],
+            next_cursor: None,
         }
     }
 }
@@ -150,13 +1430,34 @@ impl StrongType for AuthChallenge {
     }
 }
 
+impl StrongType for AuthProof {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for KdfParamsRequestStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for KdfParamsResponse {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
 impl RegisterRequestStruct {
     pub fn new() -> Self {
         Self {
             s_type: ProtoLinkSType::RegisterRequest,
             name: "".to_string(),
             login: "".to_string(),
-            password_hash_sha256_hkdf: vec![],
+            srp_salt: vec![],
+            srp_verifier: vec![],
+            kdf_params: KdfParams::LegacyHkdf,
+            invite_code: None,
         }
     }
 }
@@ -166,3 +1467,213 @@ impl StrongType for AuthRequestStruct {
         &self.s_type
     }
 }
+
+impl StrongType for ListSessionsRequestStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for ListSessionsResponse {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for LogoutRequestStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for RevokeSessionRequestStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for LogoutAllDevicesRequestStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for SessionHandlerResponseStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for ListLockoutsRequestStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for ListLockoutsResponse {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for ChangePasswordRequestStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for TotpEnrollRequestStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for TotpEnrollResponse {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for TotpConfirmRequestStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for TotpVerifyRequestStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for TwoFactorResponseStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for DeleteAccountRequestStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for GetUserProfileRequestStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for UserProfileResponse {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for GetUsersBatchRequestStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for UsersBatchResponse {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for UpdateProfileRequestStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for PollEventsRequestStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for PollEventsResponse {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for SearchUsersRequestStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for SearchUsersResponse {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for ListDevicesRequestStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for ListDevicesResponse {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for RevokeDeviceRequestStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for CreateInviteRequestStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for CreateInviteResponse {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for ListInvitesRequestStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for ListInvitesResponse {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for RevokeInviteRequestStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for InviteHandlerResponseStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for RefreshAccessRequestStruct {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
+
+impl StrongType for RefreshAccessResponse {
+    fn get_s_type(&self) -> &dyn StructureType {
+        &self.s_type
+    }
+}
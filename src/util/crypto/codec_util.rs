@@ -1,48 +1,699 @@
-use aes_gcm::{Aes256Gcm, Nonce};
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use chacha20poly1305::ChaCha20Poly1305;
 use hkdf::Hkdf;
-use sha2::Sha256;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::time::{Duration, Instant};
+use std::{env, io};
 use tfserver::sha2::digest::consts::U12;
+use x25519_dalek::{PublicKey, StaticSecret};
 
 pub const NONCE_CLIENT_TO_SERVER: [u8; 4] = [0, 0, 0, 1];
 pub const NONCE_SERVER_TO_CLIENT: [u8; 4] = [0, 0, 0, 2];
+
+/// First byte after the client's hello, the rest is the login or the session token's
+/// hash, see `token_util::hash_session_token`. The token itself never goes out in the
+/// clear, the finished MACs prove the client holds the session secret issued with it.
+pub const HANDSHAKE_SRP: u8 = 1;
+pub const HANDSHAKE_RESUME: u8 = 2;
+
+/// X25519 public key each side appends to its traffic nonce
+pub const EPHEMERAL_KEY_LEN: usize = 32;
+
+/// Traffic nonce and ephemeral key, the part of the nonce messages before the cipher suites
+pub const NONCE_MESSAGE_LEN: usize = 12 + EPHEMERAL_KEY_LEN;
+
+/// HKDF labels of the encrypted codec's key schedule, one per key
+const LABEL_CLIENT_TRAFFIC: &[u8] = b"c2s traffic";
+const LABEL_SERVER_TRAFFIC: &[u8] = b"s2c traffic";
+const LABEL_CLIENT_FINISHED: &[u8] = b"client finished";
+const LABEL_SERVER_FINISHED: &[u8] = b"server finished";
+
+/// First byte of every decrypted frame of an established connection
+const RECORD_DATA: u8 = 0;
+/// The sender moved to the next key of its direction, see `TrafficKey::ratchet`
+const RECORD_KEY_UPDATE: u8 = 1;
+const LABEL_KEY_UPDATE: &[u8] = b"traffic update";
+
+/// Random bytes each side contributes to the auth listener handshake
+pub const AUTH_NONCE_LEN: usize = 32;
+const LABEL_AUTH_CLIENT_TRAFFIC: &[u8] = b"auth c2s traffic";
+const LABEL_AUTH_SERVER_TRAFFIC: &[u8] = b"auth s2c traffic";
+
+/// Running hash of the handshake frames both sides saw, in order. Each frame is length
+/// prefixed so frame boundaries are part of the hash.
+#[derive(Clone, Default)]
+pub struct Transcript(Sha256);
+
+impl Transcript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, frame: &[u8]) {
+        self.0.update((frame.len() as u64).to_be_bytes());
+        self.0.update(frame);
+    }
+
+    /// Hash of the frames added so far, more can still be added after
+    pub fn hash(&self) -> [u8; 32] {
+        self.0.clone().finalize().into()
+    }
+}
+
+/// AEAD an established connection encrypts with. The client offers the suites it supports
+/// after its nonce message and the server answers with the one it picked, both are in the
+/// signed transcript so the choice can't be downgraded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CipherSuite {
+    Aes256Gcm,
+    /// For clients without AES instructions, where it is much faster than AES-GCM
+    ChaCha20Poly1305,
+}
+
+impl CipherSuite {
+    pub const ALL: [CipherSuite; 2] = [CipherSuite::Aes256Gcm, CipherSuite::ChaCha20Poly1305];
+
+    pub fn id(self) -> u8 {
+        match self {
+            CipherSuite::Aes256Gcm => 1,
+            CipherSuite::ChaCha20Poly1305 => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|suite| suite.id() == id)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CipherSuite::Aes256Gcm => "aes-256-gcm",
+            CipherSuite::ChaCha20Poly1305 => "chacha20-poly1305",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|suite| suite.name() == name)
+    }
+
+    /// Server preference from `CIPHER_SUITES`, comma separated names most preferred first.
+    /// Unknown names are skipped, `ALL` is used when none is left.
+    pub fn preference_from_env() -> Vec<CipherSuite> {
+        let Ok(value) = env::var("CIPHER_SUITES") else {
+            return Self::ALL.to_vec();
+        };
+        let mut preference = Vec::new();
+        for name in value.split(',').map(|v| v.trim().to_lowercase()) {
+            match Self::from_name(&name) {
+                Some(suite) if !preference.contains(&suite) => preference.push(suite),
+                Some(_) => {}
+                None => eprintln!("CIPHER_SUITES: unknown cipher suite {:?} ignored", name),
+            }
+        }
+        if preference.is_empty() {
+            eprintln!("CIPHER_SUITES has no known cipher suite, using the defaults");
+            return Self::ALL.to_vec();
+        }
+        preference
+    }
+
+    /// The first suite of `preference` the client offered
+    pub fn select(preference: &[CipherSuite], offered: &[u8]) -> Option<CipherSuite> {
+        preference
+            .iter()
+            .copied()
+            .find(|suite| offered.contains(&suite.id()))
+    }
+}
+
+/// A key of one of the `CipherSuite`s. Both take 96 bit nonces from `make_nonce`.
+#[derive(Clone)]
+enum TrafficCipher {
+    /// Boxed, its expanded key schedule is far larger than ChaCha20's key
+    Aes256Gcm(Box<Aes256Gcm>),
+    ChaCha20Poly1305(ChaCha20Poly1305),
+}
+
+impl TrafficCipher {
+    fn new(suite: CipherSuite, key: &[u8; 32]) -> Self {
+        match suite {
+            CipherSuite::Aes256Gcm => {
+                TrafficCipher::Aes256Gcm(Box::new(Aes256Gcm::new_from_slice(key).unwrap()))
+            }
+            CipherSuite::ChaCha20Poly1305 => {
+                TrafficCipher::ChaCha20Poly1305(ChaCha20Poly1305::new_from_slice(key).unwrap())
+            }
+        }
+    }
+
+    fn encrypt(&self, nonce: &Nonce<U12>, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            TrafficCipher::Aes256Gcm(cipher) => cipher.encrypt(nonce, plaintext),
+            TrafficCipher::ChaCha20Poly1305(cipher) => cipher.encrypt(nonce, plaintext),
+        }
+        .map_err(|_| broken_pipe())
+    }
+
+    fn decrypt(&self, nonce: &Nonce<U12>, ciphertext: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            TrafficCipher::Aes256Gcm(cipher) => cipher.decrypt(nonce, ciphertext),
+            TrafficCipher::ChaCha20Poly1305(cipher) => cipher.decrypt(nonce, ciphertext),
+        }
+        .map_err(|_| broken_pipe())
+    }
+}
+
+/// When a sender moves its direction to the next key, whichever limit is hit first. The
+/// frame and byte limits keep a single key far below the AEAD's usage bounds, the age
+/// limits how much traffic a leaked key exposes. With a peer that didn't announce
+/// `Capabilities::KEY_UPDATE` the connection is closed at the limit instead.
+#[derive(Clone, Copy, Debug)]
+pub struct RekeyLimits {
+    pub max_frames: u64,
+    pub max_bytes: u64,
+    pub max_age: Duration,
+}
+
+impl Default for RekeyLimits {
+    fn default() -> Self {
+        Self {
+            max_frames: 1 << 24,
+            max_bytes: 1 << 34,
+            max_age: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+fn env_u64(name: &str) -> Option<u64> {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|v| *v > 0)
+}
+
+impl RekeyLimits {
+    /// Reads `REKEY_MAX_FRAMES`, `REKEY_MAX_BYTES` and `REKEY_MAX_AGE_SECS`, keeping the
+    /// defaults for unset or invalid values
+    pub fn from_env() -> Self {
+        let mut limits = Self::default();
+        if let Some(v) = env_u64("REKEY_MAX_FRAMES") {
+            limits.max_frames = v;
+        }
+        if let Some(v) = env_u64("REKEY_MAX_BYTES") {
+            limits.max_bytes = v;
+        }
+        if let Some(v) = env_u64("REKEY_MAX_AGE_SECS") {
+            limits.max_age = Duration::from_secs(v);
+        }
+        limits
+    }
+}
+
+fn broken_pipe() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe")
+}
+
+/// Key of one direction of an established connection and how much it has been used
+#[derive(Clone)]
+pub struct TrafficKey {
+    suite: CipherSuite,
+    secret: [u8; 32],
+    cipher: TrafficCipher,
+    dir: [u8; 4],
+    ctr: u64,
+    bytes: u64,
+    since: Instant,
+}
+
+impl TrafficKey {
+    fn new(suite: CipherSuite, secret: [u8; 32], dir: [u8; 4]) -> Self {
+        Self {
+            suite,
+            cipher: TrafficCipher::new(suite, &secret),
+            secret,
+            dir,
+            ctr: 0,
+            bytes: 0,
+            since: Instant::now(),
+        }
+    }
+
+    /// Replaces the key with one derived from it, like a TLS 1.3 KeyUpdate. Old keys
+    /// can't be recovered from new ones.
+    fn ratchet(&mut self) {
+        let hk = Hkdf::<Sha256>::from_prk(&self.secret).unwrap();
+        let mut next = [0u8; 32];
+        hk.expand(LABEL_KEY_UPDATE, &mut next).unwrap();
+        *self = Self::new(self.suite, next, self.dir);
+    }
+
+    fn is_due(&self, limits: &RekeyLimits) -> bool {
+        self.ctr >= limits.max_frames
+            || self.bytes >= limits.max_bytes
+            || self.since.elapsed() >= limits.max_age
+    }
+
+    fn seal(&mut self, record_type: u8, payload: &[u8]) -> io::Result<Vec<u8>> {
+        let mut record = Vec::with_capacity(1 + payload.len());
+        record.push(record_type);
+        record.extend_from_slice(payload);
+
+        let nonce = make_nonce(self.ctr, self.dir);
+        self.ctr += 1;
+        self.bytes += record.len() as u64;
+        self.cipher.encrypt(&nonce, &record)
+    }
+
+    fn open(&mut self, data: &[u8]) -> io::Result<(u8, Vec<u8>)> {
+        let nonce = make_nonce(self.ctr, self.dir);
+        self.ctr += 1;
+        let mut record = self.cipher.decrypt(&nonce, data)?;
+        self.bytes += record.len() as u64;
+        if record.is_empty() {
+            return Err(broken_pipe());
+        }
+        let record_type = record.remove(0);
+        Ok((record_type, record))
+    }
+}
+
+/// Both directions of an established connection
+#[derive(Clone)]
+pub struct TrafficState {
+    send: TrafficKey,
+    recv: TrafficKey,
+    limits: RekeyLimits,
+    /// Whether the peer follows `KeyUpdate` records
+    key_update: bool,
+}
+
+impl TrafficState {
+    /// Encrypts an application frame. Returns the frames to send, a `KeyUpdate` under the
+    /// old key comes first when the send key is due.
+    pub fn seal(&mut self, item: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+        let mut frames = Vec::with_capacity(2);
+        if self.send.is_due(&self.limits) {
+            if !self.key_update {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "traffic key exhausted",
+                ));
+            }
+            frames.push(self.send.seal(RECORD_KEY_UPDATE, &[])?);
+            self.send.ratchet();
+        }
+        frames.push(self.send.seal(RECORD_DATA, item)?);
+        Ok(frames)
+    }
+
+    /// Decrypts a frame, None when it only moved the peer to its next key
+    pub fn open(&mut self, data: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        match self.recv.open(data)? {
+            (RECORD_DATA, payload) => Ok(Some(payload)),
+            (RECORD_KEY_UPDATE, _) if self.key_update => {
+                self.recv.ratchet();
+                Ok(None)
+            }
+            _ => Err(broken_pipe()),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub enum CryptoState {
     Uninitialized,
-    Established {
-        cipher: Aes256Gcm,
-        send_ctr: u64,
-        recv_ctr: u64,
-    },
+    /// Each direction has its own key, see `HandshakeKeys`
+    Established(Box<TrafficState>),
+}
+
+impl CryptoState {
+    pub fn established(
+        suite: CipherSuite,
+        send_key: [u8; 32],
+        send_dir: [u8; 4],
+        recv_key: [u8; 32],
+        recv_dir: [u8; 4],
+        limits: RekeyLimits,
+        key_update: bool,
+    ) -> Self {
+        CryptoState::Established(Box::new(TrafficState {
+            send: TrafficKey::new(suite, send_key, send_dir),
+            recv: TrafficKey::new(suite, recv_key, recv_dir),
+            limits,
+            key_update,
+        }))
+    }
+}
+
+/// X25519 key pair for a single handshake, dropped once the traffic key is derived
+pub fn generate_ephemeral_key() -> (StaticSecret, [u8; EPHEMERAL_KEY_LEN]) {
+    let mut secret = [0u8; 32];
+    rand::rng().fill_bytes(&mut secret);
+    let secret = StaticSecret::from(secret);
+    let public = PublicKey::from(&secret).to_bytes();
+    (secret, public)
+}
+
+/// None for a malformed or low order peer key, either would make the result predictable
+pub fn ephemeral_shared_secret(secret: &StaticSecret, peer_public: &[u8]) -> Option<[u8; 32]> {
+    let peer_public = <[u8; EPHEMERAL_KEY_LEN]>::try_from(peer_public).ok()?;
+    let shared = secret.diffie_hellman(&PublicKey::from(peer_public));
+    shared.was_contributory().then(|| shared.to_bytes())
+}
+
+/// Keys of one encrypted connection. Traffic keys differ per direction, the finished keys
+/// only MAC the transcript to confirm both sides derived the same keys.
+pub struct HandshakeKeys {
+    pub client_traffic: [u8; 32],
+    pub server_traffic: [u8; 32],
+    pub client_finished: [u8; 32],
+    pub server_finished: [u8; 32],
 }
 
+impl HandshakeKeys {
+    /// Extracts from `session_key` with `ephemeral_shared` as salt, then expands every key
+    /// with its own label and `transcript_hash`, so handshakes that differ in any message
+    /// end up with unrelated keys.
+    ///
+    /// `session_key` is the SRP shared key `K` or a resumed session's secret, never the
+    /// stored verifier. `ephemeral_shared` comes from the handshake's X25519 exchange, so
+    /// a recorded connection stays unreadable to anyone who later learns `session_key`.
+    pub fn derive(
+        session_key: &[u8],
+        ephemeral_shared: &[u8; 32],
+        transcript_hash: &[u8; 32],
+    ) -> Self {
+        let hk = Hkdf::<Sha256>::new(Some(ephemeral_shared), session_key);
+        let expand = |label: &[u8]| {
+            let mut key = [0u8; 32];
+            hk.expand_multi_info(&[label, transcript_hash.as_slice()], &mut key)
+                .unwrap();
+            key
+        };
+        Self {
+            client_traffic: expand(LABEL_CLIENT_TRAFFIC),
+            server_traffic: expand(LABEL_SERVER_TRAFFIC),
+            client_finished: expand(LABEL_CLIENT_FINISHED),
+            server_finished: expand(LABEL_SERVER_FINISHED),
+        }
+    }
+}
 
-pub fn derive_traffic_key(
-    password_hash: &[u8],
-    client_nonce: &[u8; 12],
-    server_nonce: &[u8; 12],
-) -> [u8; 32] {
-    let hk = Hkdf::<Sha256>::new(None, password_hash);
+/// Key confirmation a side sends over the transcript so far
+pub fn finished_mac(finished_key: &[u8; 32], transcript_hash: &[u8; 32]) -> [u8; 32] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(finished_key).unwrap();
+    mac.update(transcript_hash);
+    mac.finalize().into_bytes().into()
+}
 
-    let mut info = Vec::with_capacity(7 + 12 + 12);
-    info.extend_from_slice(b"traffic");
-    info.extend_from_slice(client_nonce);
-    info.extend_from_slice(server_nonce);
+/// Constant time check of the peer's `finished_mac`
+pub fn verify_finished(finished_key: &[u8; 32], transcript_hash: &[u8; 32], tag: &[u8]) -> bool {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(finished_key).unwrap();
+    mac.update(transcript_hash);
+    mac.verify_slice(tag).is_ok()
+}
 
-    let mut key = [0u8; 32];
-    hk.expand(&info, &mut key).unwrap();
-    key
+/// Secret both sides derive from the login's SRP key and keep with the session token,
+/// later used instead of the password to key resumed connections.
+pub fn derive_session_secret(session_key: &[u8]) -> [u8; 32] {
+    let hk = Hkdf::<Sha256>::new(None, session_key);
+    let mut secret = [0u8; 32];
+    hk.expand(b"session-resume", &mut secret).unwrap();
+    secret
 }
 
-pub fn derive_handshake_key(password_hash: &[u8]) -> [u8; 32] {
-    let hk = Hkdf::<Sha256>::new(None, password_hash);
-    let mut key = [0u8; 32];
-    hk.expand(b"handshake-key", &mut key).unwrap();
-    key
+/// Traffic keys of an auth listener connection, the client's first. Only the client and
+/// the server whose signature covers `binding` know the ephemeral `shared` secret. The
+/// auth listener always encrypts with `CipherSuite::Aes256Gcm`, it only carries a few
+/// small frames per connection.
+pub fn derive_auth_traffic_keys(shared: &[u8; 32], binding: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let hk = Hkdf::<Sha256>::new(Some(binding), shared);
+    let expand = |label: &[u8]| {
+        let mut key = [0u8; 32];
+        hk.expand(label, &mut key).unwrap();
+        key
+    };
+    (
+        expand(LABEL_AUTH_CLIENT_TRAFFIC),
+        expand(LABEL_AUTH_SERVER_TRAFFIC),
+    )
 }
 
 pub fn make_nonce(counter: u64, dir: [u8; 4]) -> Nonce<U12> {
     let mut nonce = [0u8; 12];
     nonce[..4].copy_from_slice(&dir);
     nonce[4..].copy_from_slice(&counter.to_be_bytes());
-    Nonce::from_slice(&nonce).clone()
-}
\ No newline at end of file
+    *Nonce::from_slice(&nonce)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Both sides of an encrypted handshake, run in memory. Returns the keys each side
+    /// derived and the transcript hash they ended with.
+    fn handshake(session_key: &[u8], frames: &[&[u8]]) -> (HandshakeKeys, HandshakeKeys, [u8; 32]) {
+        let (client_secret, client_public) = generate_ephemeral_key();
+        let (server_secret, server_public) = generate_ephemeral_key();
+
+        let mut transcript = Transcript::new();
+        for frame in frames {
+            transcript.add(frame);
+        }
+        transcript.add(&client_public);
+        transcript.add(&server_public);
+        let hash = transcript.hash();
+
+        let client_shared = ephemeral_shared_secret(&client_secret, &server_public).unwrap();
+        let server_shared = ephemeral_shared_secret(&server_secret, &client_public).unwrap();
+        (
+            HandshakeKeys::derive(session_key, &client_shared, &hash),
+            HandshakeKeys::derive(session_key, &server_shared, &hash),
+            hash,
+        )
+    }
+
+    #[test]
+    fn client_and_server_derive_identical_keys() {
+        let (client, server, _) = handshake(b"srp session key", &[b"hello", b"reply"]);
+        assert_eq!(client.client_traffic, server.client_traffic);
+        assert_eq!(client.server_traffic, server.server_traffic);
+        assert_eq!(client.client_finished, server.client_finished);
+        assert_eq!(client.server_finished, server.server_finished);
+    }
+
+    #[test]
+    fn every_key_has_its_own_direction_and_purpose() {
+        let (keys, _, _) = handshake(b"srp session key", &[b"hello"]);
+        let all = [
+            keys.client_traffic,
+            keys.server_traffic,
+            keys.client_finished,
+            keys.server_finished,
+        ];
+        for (i, a) in all.iter().enumerate() {
+            for b in &all[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
+
+        // A client's frame can't be reflected back to it as if the server sent it
+        let mut client = CryptoState::established(
+            CipherSuite::Aes256Gcm,
+            keys.client_traffic,
+            NONCE_CLIENT_TO_SERVER,
+            keys.server_traffic,
+            NONCE_SERVER_TO_CLIENT,
+            RekeyLimits::default(),
+            true,
+        );
+        let CryptoState::Established(client) = &mut client else {
+            unreachable!();
+        };
+        let frames = client.seal(b"request").unwrap();
+        assert!(client.open(&frames[0]).is_err());
+    }
+
+    #[test]
+    fn finished_mac_fails_on_a_tampered_transcript() {
+        let frames: [&[u8]; 2] = [b"hello", b"reply"];
+        let mut transcript = Transcript::new();
+        for frame in frames {
+            transcript.add(frame);
+        }
+        let key = [3u8; 32];
+        let tag = finished_mac(&key, &transcript.hash());
+        assert!(verify_finished(&key, &transcript.hash(), &tag));
+
+        let mut tampered = Transcript::new();
+        tampered.add(b"hello");
+        tampered.add(b"replz");
+        assert!(!verify_finished(&key, &tampered.hash(), &tag));
+
+        // Moving a byte across a frame boundary changes the hash too
+        let mut shifted = Transcript::new();
+        shifted.add(b"hellor");
+        shifted.add(b"eply");
+        assert_ne!(shifted.hash(), transcript.hash());
+    }
+
+    #[test]
+    fn a_different_transcript_derives_unrelated_keys() {
+        let shared = [9u8; 32];
+        let mut transcript = Transcript::new();
+        transcript.add(b"hello");
+        let mut tampered = Transcript::new();
+        tampered.add(b"hellp");
+
+        let keys = HandshakeKeys::derive(b"key", &shared, &transcript.hash());
+        let other = HandshakeKeys::derive(b"key", &shared, &tampered.hash());
+        assert_ne!(keys.client_traffic, other.client_traffic);
+        assert_ne!(keys.server_finished, other.server_finished);
+    }
+
+    /// Client and server state of one connection
+    fn connection(suite: CipherSuite, limits: RekeyLimits) -> (TrafficState, TrafficState) {
+        let client = TrafficState {
+            send: TrafficKey::new(suite, [1u8; 32], NONCE_CLIENT_TO_SERVER),
+            recv: TrafficKey::new(suite, [2u8; 32], NONCE_SERVER_TO_CLIENT),
+            limits,
+            key_update: true,
+        };
+        let server = TrafficState {
+            send: TrafficKey::new(suite, [2u8; 32], NONCE_SERVER_TO_CLIENT),
+            recv: TrafficKey::new(suite, [1u8; 32], NONCE_CLIENT_TO_SERVER),
+            limits,
+            key_update: true,
+        };
+        (client, server)
+    }
+
+    fn frame_limit(max_frames: u64) -> RekeyLimits {
+        RekeyLimits {
+            max_frames,
+            ..RekeyLimits::default()
+        }
+    }
+
+    #[test]
+    fn key_update_fires_at_the_frame_limit() {
+        let (mut client, mut server) = connection(CipherSuite::Aes256Gcm, frame_limit(3));
+        for i in 0..3u8 {
+            let frames = client.seal(&[i]).unwrap();
+            assert_eq!(frames.len(), 1);
+            assert_eq!(server.open(&frames[0]).unwrap(), Some(vec![i]));
+        }
+
+        let frames = client.seal(b"after").unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(server.open(&frames[0]).unwrap(), None);
+        assert_eq!(server.open(&frames[1]).unwrap(), Some(b"after".to_vec()));
+        assert_eq!(client.send.ctr, 1);
+    }
+
+    #[test]
+    fn key_update_fires_at_the_byte_limit() {
+        let limits = RekeyLimits {
+            max_bytes: 64,
+            ..RekeyLimits::default()
+        };
+        let (mut client, mut server) = connection(CipherSuite::Aes256Gcm, limits);
+        let payload = [7u8; 64];
+        let frames = client.seal(&payload).unwrap();
+        assert_eq!(frames.len(), 1);
+        server.open(&frames[0]).unwrap();
+
+        let frames = client.seal(&payload).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(server.open(&frames[0]).unwrap(), None);
+        assert_eq!(server.open(&frames[1]).unwrap(), Some(payload.to_vec()));
+    }
+
+    #[test]
+    fn old_key_frames_are_refused_after_a_ratchet() {
+        let (mut client, mut server) = connection(CipherSuite::Aes256Gcm, frame_limit(1));
+        server.open(&client.seal(b"first").unwrap()[0]).unwrap();
+        for frame in client.seal(b"second").unwrap() {
+            server.open(&frame).unwrap();
+        }
+
+        // Same direction and the counter the server expects next, only the key is old
+        let mut old = TrafficKey::new(CipherSuite::Aes256Gcm, [1u8; 32], NONCE_CLIENT_TO_SERVER);
+        old.ctr = server.recv.ctr;
+        let stale = old.seal(RECORD_DATA, b"stale").unwrap();
+        assert!(server.open(&stale).is_err());
+    }
+
+    #[test]
+    fn limit_without_key_update_closes_the_connection() {
+        let (mut client, _) = connection(CipherSuite::Aes256Gcm, frame_limit(1));
+        client.key_update = false;
+        client.seal(b"first").unwrap();
+        let err = client.seal(b"second").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+    }
+
+    #[test]
+    fn key_update_is_refused_from_a_peer_that_did_not_announce_it() {
+        let (mut client, mut server) = connection(CipherSuite::Aes256Gcm, frame_limit(1));
+        server.key_update = false;
+        server.open(&client.seal(b"first").unwrap()[0]).unwrap();
+        let frames = client.seal(b"second").unwrap();
+        assert!(server.open(&frames[0]).is_err());
+    }
+
+    #[test]
+    fn suite_ids_round_trip_and_unknown_ids_are_rejected() {
+        for suite in CipherSuite::ALL {
+            assert_eq!(CipherSuite::from_id(suite.id()), Some(suite));
+        }
+        for id in [0u8, 3, 255] {
+            assert_eq!(CipherSuite::from_id(id), None);
+        }
+    }
+
+    #[test]
+    fn server_preference_decides_among_offered_suites() {
+        let aes_first = [CipherSuite::Aes256Gcm, CipherSuite::ChaCha20Poly1305];
+        let chacha_first = [CipherSuite::ChaCha20Poly1305, CipherSuite::Aes256Gcm];
+        let both = [1u8, 2];
+
+        assert_eq!(
+            CipherSuite::select(&aes_first, &both),
+            Some(CipherSuite::Aes256Gcm)
+        );
+        assert_eq!(
+            CipherSuite::select(&chacha_first, &both),
+            Some(CipherSuite::ChaCha20Poly1305)
+        );
+        // A ChaCha-only client still gets through to a server preferring AES
+        assert_eq!(
+            CipherSuite::select(&aes_first, &[CipherSuite::ChaCha20Poly1305.id()]),
+            Some(CipherSuite::ChaCha20Poly1305)
+        );
+    }
+
+    #[test]
+    fn no_common_suite_fails_the_selection() {
+        let aes_only = [CipherSuite::Aes256Gcm];
+        assert_eq!(CipherSuite::select(&aes_only, &[2]), None);
+        assert_eq!(CipherSuite::select(&aes_only, &[9, 200]), None);
+        assert_eq!(CipherSuite::select(&CipherSuite::ALL, &[]), None);
+    }
+
+    #[test]
+    fn every_suite_carries_traffic_and_suites_do_not_mix() {
+        for suite in CipherSuite::ALL {
+            let (mut client, mut server) = connection(suite, RekeyLimits::default());
+            let frames = client.seal(b"hello").unwrap();
+            assert_eq!(server.open(&frames[0]).unwrap(), Some(b"hello".to_vec()));
+        }
+
+        let (mut client, _) = connection(CipherSuite::Aes256Gcm, RekeyLimits::default());
+        let (_, mut server) = connection(CipherSuite::ChaCha20Poly1305, RekeyLimits::default());
+        let frames = client.seal(b"hello").unwrap();
+        assert!(server.open(&frames[0]).is_err());
+    }
+}
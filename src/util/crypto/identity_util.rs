@@ -0,0 +1,47 @@
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+pub const IDENTITY_KEY_LEN: usize = 32;
+pub const IDENTITY_SIGNATURE_LEN: usize = 64;
+
+/// What the server signs with its identity key, distinct so a signature made for one
+/// can't be replayed as another
+pub const SIGN_ENCRYPTED_HANDSHAKE: &[u8] = b"protolink encrypted handshake";
+pub const SIGN_AUTH_HANDSHAKE: &[u8] = b"protolink auth handshake";
+
+/// `SHA256:` and the unpadded base64 of the key's hash, what operators compare and pin
+pub fn fingerprint(public_key: &[u8]) -> String {
+    format!(
+        "SHA256:{}",
+        STANDARD_NO_PAD.encode(Sha256::digest(public_key))
+    )
+}
+
+fn labeled(label: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(label.len() + 1 + message.len());
+    out.extend_from_slice(label);
+    out.push(0);
+    out.extend_from_slice(message);
+    out
+}
+
+pub fn sign(key: &SigningKey, label: &[u8], message: &[u8]) -> [u8; IDENTITY_SIGNATURE_LEN] {
+    key.sign(&labeled(label, message)).to_bytes()
+}
+
+/// False for malformed keys or signatures as well as wrong ones
+pub fn verify(public_key: &[u8], label: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let Ok(public_key) = <[u8; IDENTITY_KEY_LEN]>::try_from(public_key) else {
+        return false;
+    };
+    let Ok(key) = VerifyingKey::from_bytes(&public_key) else {
+        return false;
+    };
+    let Ok(signature) = Signature::from_slice(signature) else {
+        return false;
+    };
+    key.verify_strict(&labeled(label, message), &signature)
+        .is_ok()
+}
@@ -0,0 +1,19 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+/// 128 bits, guessing a live code is hopeless so redeeming isn't throttled
+const INVITE_CODE_BYTES: usize = 16;
+
+/// Shown to its creator once, only `hash_invite_code` of it is stored
+pub fn generate_invite_code() -> String {
+    let mut code = [0u8; INVITE_CODE_BYTES];
+    rand::rng().fill_bytes(&mut code);
+    URL_SAFE_NO_PAD.encode(code)
+}
+
+/// What `invites.code_hash` holds. Surrounding whitespace from copy and paste is ignored.
+pub fn hash_invite_code(code: &str) -> Vec<u8> {
+    Sha256::digest(code.trim().as_bytes()).to_vec()
+}
@@ -0,0 +1,147 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use hkdf::Hkdf;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+pub const KDF_SALT_LEN: usize = 16;
+
+/// Lower bounds the server accepts at registration, the OWASP Argon2id baseline
+pub const MIN_M_COST: u32 = 19 * 1024;
+pub const MIN_T_COST: u32 = 2;
+/// Upper bounds so a client can't make everyone else's login unusably slow
+pub const MAX_M_COST: u32 = 256 * 1024;
+pub const MAX_T_COST: u32 = 10;
+pub const MAX_P_COST: u32 = 8;
+
+/// How the client turns a password into the key it feeds into SRP.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum KdfParams {
+    /// Unsalted HKDF used before Argon2id, kept so existing accounts can still log in
+    /// and upgrade their credential on the next successful login.
+    LegacyHkdf,
+    Argon2id {
+        salt: Vec<u8>,
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+    },
+}
+
+impl KdfParams {
+    /// Default Argon2id parameters with a fresh random salt
+    pub fn generate() -> Self {
+        let mut salt = vec![0u8; KDF_SALT_LEN];
+        rand::rng().fill_bytes(&mut salt);
+        Self::with_salt(salt)
+    }
+
+    /// Default Argon2id parameters around the given salt
+    pub fn with_salt(salt: Vec<u8>) -> Self {
+        KdfParams::Argon2id {
+            salt,
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        matches!(self, KdfParams::LegacyHkdf)
+    }
+
+    /// Whether the server should accept these parameters for a new credential
+    pub fn is_acceptable(&self) -> bool {
+        match self {
+            KdfParams::LegacyHkdf => false,
+            KdfParams::Argon2id {
+                salt,
+                m_cost,
+                t_cost,
+                p_cost,
+            } => {
+                salt.len() >= KDF_SALT_LEN
+                    && (MIN_M_COST..=MAX_M_COST).contains(m_cost)
+                    && (MIN_T_COST..=MAX_T_COST).contains(t_cost)
+                    && (1..=MAX_P_COST).contains(p_cost)
+            }
+        }
+    }
+
+    /// Encodes the parameters for the `users.kdf_params` column,
+    /// `argon2id$m=19456,t=2,p=1$<salt>`. Legacy accounts are stored as NULL.
+    pub fn to_db_string(&self) -> Option<String> {
+        match self {
+            KdfParams::LegacyHkdf => None,
+            KdfParams::Argon2id {
+                salt,
+                m_cost,
+                t_cost,
+                p_cost,
+            } => Some(format!(
+                "argon2id$m={},t={},p={}${}",
+                m_cost,
+                t_cost,
+                p_cost,
+                STANDARD_NO_PAD.encode(salt)
+            )),
+        }
+    }
+
+    pub fn from_db_string(value: Option<&str>) -> Option<Self> {
+        let Some(value) = value else {
+            return Some(KdfParams::LegacyHkdf);
+        };
+
+        let mut parts = value.split('$');
+        if parts.next()? != "argon2id" {
+            return None;
+        }
+        let (mut m_cost, mut t_cost, mut p_cost) = (None, None, None);
+        for param in parts.next()?.split(',') {
+            let (name, val) = param.split_once('=')?;
+            let val = val.parse::<u32>().ok()?;
+            match name {
+                "m" => m_cost = Some(val),
+                "t" => t_cost = Some(val),
+                "p" => p_cost = Some(val),
+                _ => return None,
+            }
+        }
+        let salt = STANDARD_NO_PAD.decode(parts.next()?).ok()?;
+
+        Some(KdfParams::Argon2id {
+            salt,
+            m_cost: m_cost?,
+            t_cost: t_cost?,
+            p_cost: p_cost?,
+        })
+    }
+}
+
+/// Derives the 32 byte password key used as the SRP password.
+///
+/// Returns None if the parameters are rejected by Argon2.
+pub fn derive_password_key(password: &str, params: &KdfParams) -> Option<[u8; 32]> {
+    let mut key = [0u8; 32];
+    match params {
+        KdfParams::LegacyHkdf => {
+            let hk = Hkdf::<Sha256>::new(None, password.as_bytes());
+            hk.expand(b"aes-256-key", &mut key).unwrap();
+        }
+        KdfParams::Argon2id {
+            salt,
+            m_cost,
+            t_cost,
+            p_cost,
+        } => {
+            let params = Params::new(*m_cost, *t_cost, *p_cost, Some(key.len())).ok()?;
+            Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                .hash_password_into(password.as_bytes(), salt, &mut key)
+                .ok()?;
+        }
+    }
+    Some(key)
+}
@@ -1,2 +1,7 @@
-pub mod challenge_util;
 pub mod codec_util;
+pub mod identity_util;
+pub mod invite_util;
+pub mod kdf_util;
+pub mod srp_util;
+pub mod token_util;
+pub mod totp_util;
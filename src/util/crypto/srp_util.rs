@@ -0,0 +1,114 @@
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use srp::client::{SrpClient, SrpClientVerifier};
+use srp::groups::G_2048;
+use srp::server::{SrpServer, SrpServerVerifier};
+
+pub const SRP_SALT_LEN: usize = 32;
+pub const SRP_SECRET_LEN: usize = 64;
+
+/// Fresh random salt for a new SRP verifier
+pub fn generate_salt() -> Vec<u8> {
+    let mut salt = vec![0u8; SRP_SALT_LEN];
+    rand::rng().fill_bytes(&mut salt);
+    salt
+}
+
+/// Fresh random ephemeral secret (`a` on the client, `b` on the server)
+pub fn generate_ephemeral_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; SRP_SECRET_LEN];
+    rand::rng().fill_bytes(&mut secret);
+    secret
+}
+
+/// Computes the SRP-6a verifier `v = g^x` the server stores instead of the password key.
+pub fn compute_verifier(login: &str, password_key: &[u8], salt: &[u8]) -> Vec<u8> {
+    SrpClient::<Sha256>::new(&G_2048).compute_verifier(login.as_bytes(), password_key, salt)
+}
+
+/// Client public ephemeral `A`
+pub fn compute_client_public(client_secret: &[u8]) -> Vec<u8> {
+    SrpClient::<Sha256>::new(&G_2048).compute_public_ephemeral(client_secret)
+}
+
+/// Server public ephemeral `B`
+pub fn compute_server_public(server_secret: &[u8], verifier: &[u8]) -> Vec<u8> {
+    SrpServer::<Sha256>::new(&G_2048).compute_public_ephemeral(server_secret, verifier)
+}
+
+/// Client side of the exchange. The returned session holds the client proof `M1`,
+/// checks the server proof `M2` and exposes the shared key.
+///
+/// Returns None if the server sent a malformed `B`.
+pub fn process_server_reply(
+    login: &str,
+    password_key: &[u8],
+    salt: &[u8],
+    client_secret: &[u8],
+    server_public: &[u8],
+) -> Option<SrpClientVerifier<Sha256>> {
+    SrpClient::<Sha256>::new(&G_2048)
+        .process_reply(
+            client_secret,
+            login.as_bytes(),
+            password_key,
+            salt,
+            server_public,
+        )
+        .ok()
+}
+
+/// Server side of the exchange. The returned session checks the client proof `M1`,
+/// holds the server proof `M2` and exposes the shared key.
+///
+/// Returns None if the client sent a malformed `A`.
+pub fn process_client_reply(
+    server_secret: &[u8],
+    verifier: &[u8],
+    client_public: &[u8],
+) -> Option<SrpServerVerifier<Sha256>> {
+    SrpServer::<Sha256>::new(&G_2048)
+        .process_reply(server_secret, verifier, client_public)
+        .ok()
+}
+
+fn credential_update_hmac(
+    session_key: &[u8],
+    kdf_params: &str,
+    salt: &[u8],
+    verifier: &[u8],
+) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(session_key).unwrap();
+    for field in [kdf_params.as_bytes(), salt, verifier] {
+        mac.update(&(field.len() as u64).to_be_bytes());
+        mac.update(field);
+    }
+    mac
+}
+
+/// MAC binding a replacement credential to the SRP session that authorised it,
+/// so it can't be swapped in transit on the plaintext listener.
+pub fn credential_update_mac(
+    session_key: &[u8],
+    kdf_params: &str,
+    salt: &[u8],
+    verifier: &[u8],
+) -> Vec<u8> {
+    credential_update_hmac(session_key, kdf_params, salt, verifier)
+        .finalize()
+        .into_bytes()
+        .to_vec()
+}
+
+pub fn verify_credential_update_mac(
+    session_key: &[u8],
+    kdf_params: &str,
+    salt: &[u8],
+    verifier: &[u8],
+    tag: &[u8],
+) -> bool {
+    credential_update_hmac(session_key, kdf_params, salt, verifier)
+        .verify_slice(tag)
+        .is_ok()
+}
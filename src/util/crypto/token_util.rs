@@ -0,0 +1,113 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+/// Marks ProtoLink session tokens, so leaked ones are easy to grep for
+pub const SESSION_TOKEN_PREFIX: &str = "plk_";
+const SESSION_TOKEN_BYTES: usize = 32;
+
+/// `SESSION_TOKEN_PREFIX` followed by base64url of 256 random bits, and the hash that is
+/// stored instead of it
+pub fn generate_session_token() -> (String, Vec<u8>) {
+    let mut token = [0u8; SESSION_TOKEN_BYTES];
+    rand::rng().fill_bytes(&mut token);
+    (
+        format!("{}{}", SESSION_TOKEN_PREFIX, URL_SAFE_NO_PAD.encode(token)),
+        Sha256::digest(token).to_vec(),
+    )
+}
+
+/// What `tokens.token_hash` holds. The token is random enough that a plain hash is fine.
+/// None for anything not shaped like a session token, that needs no lookup.
+pub fn hash_session_token(token: &str) -> Option<Vec<u8>> {
+    let encoded = token.strip_prefix(SESSION_TOKEN_PREFIX)?;
+    let raw = URL_SAFE_NO_PAD.decode(encoded).ok()?;
+    if raw.len() != SESSION_TOKEN_BYTES {
+        return None;
+    }
+    Some(Sha256::digest(&raw).to_vec())
+}
+
+/// Marks the short-lived access tokens signed by `AccessKeyring`
+pub const ACCESS_TOKEN_PREFIX: &str = "pla_";
+const ACCESS_TOKEN_VERSION: u8 = 2;
+/// Version, user id, session id, device id, issue time and expiry
+const ACCESS_CLAIMS_LEN: usize = 1 + 8 * 5;
+
+/// What an access token vouches for until `expires_at` (unix seconds). `session_id` is
+/// the `tokens` row it was issued from, `issued_at` (unix milliseconds) is compared with
+/// revocations in `SessionRegistry`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AccessClaims {
+    pub user_id: u64,
+    pub session_id: u64,
+    pub device_id: Option<u64>,
+    pub issued_at: i64,
+    pub expires_at: i64,
+}
+
+impl AccessClaims {
+    fn to_bytes(self) -> [u8; ACCESS_CLAIMS_LEN] {
+        let mut out = [0u8; ACCESS_CLAIMS_LEN];
+        out[0] = ACCESS_TOKEN_VERSION;
+        out[1..9].copy_from_slice(&self.user_id.to_be_bytes());
+        out[9..17].copy_from_slice(&self.session_id.to_be_bytes());
+        // Device ids start at 1, 0 stands for none
+        out[17..25].copy_from_slice(&self.device_id.unwrap_or(0).to_be_bytes());
+        out[25..33].copy_from_slice(&self.issued_at.to_be_bytes());
+        out[33..41].copy_from_slice(&self.expires_at.to_be_bytes());
+        out
+    }
+
+    fn from_bytes(raw: &[u8]) -> Option<Self> {
+        if raw.len() != ACCESS_CLAIMS_LEN || raw[0] != ACCESS_TOKEN_VERSION {
+            return None;
+        }
+        let word = |at: usize| <[u8; 8]>::try_from(&raw[at..at + 8]).unwrap();
+        let device_id = u64::from_be_bytes(word(17));
+        Some(Self {
+            user_id: u64::from_be_bytes(word(1)),
+            session_id: u64::from_be_bytes(word(9)),
+            device_id: (device_id != 0).then_some(device_id),
+            issued_at: i64::from_be_bytes(word(25)),
+            expires_at: i64::from_be_bytes(word(33)),
+        })
+    }
+}
+
+fn access_mac(key: &[u8], signed: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(signed.as_bytes());
+    mac
+}
+
+/// `pla_<key id>.<claims>.<mac>`, claims and MAC in base64url. The MAC covers everything
+/// before it, so the key id can't be swapped either.
+pub fn sign_access_token(key_id: &str, key: &[u8], claims: AccessClaims) -> String {
+    let signed = format!(
+        "{}{}.{}",
+        ACCESS_TOKEN_PREFIX,
+        key_id,
+        URL_SAFE_NO_PAD.encode(claims.to_bytes())
+    );
+    let tag = access_mac(key, &signed).finalize().into_bytes();
+    format!("{}.{}", signed, URL_SAFE_NO_PAD.encode(tag))
+}
+
+/// Id of the key an access token says it was signed with, None if it isn't shaped like one
+pub fn access_token_key_id(token: &str) -> Option<&str> {
+    let (key_id, _) = token.strip_prefix(ACCESS_TOKEN_PREFIX)?.split_once('.')?;
+    Some(key_id)
+}
+
+/// Checks the MAC of an access token against `key` and returns its claims. Expiry is up
+/// to the caller.
+pub fn verify_access_token(token: &str, key: &[u8]) -> Option<AccessClaims> {
+    let (signed, tag) = token.rsplit_once('.')?;
+    let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+    access_mac(key, signed).verify_slice(&tag).ok()?;
+    let (_, claims) = signed.rsplit_once('.')?;
+    AccessClaims::from_bytes(&URL_SAFE_NO_PAD.decode(claims).ok()?)
+}
@@ -0,0 +1,337 @@
+use crate::util::clock::Clock;
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::{Rng, RngCore};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+/// RFC 6238 defaults, what every authenticator app understands
+pub const TOTP_STEP_SECS: u64 = 30;
+pub const TOTP_DIGITS: u32 = 6;
+/// 160 bits, the size RFC 4226 recommends for HMAC-SHA1
+pub const TOTP_SECRET_LEN: usize = 20;
+/// Steps of clock drift tolerated either way
+pub const TOTP_SKEW_STEPS: u64 = 1;
+
+pub const BACKUP_CODE_COUNT: usize = 10;
+const BACKUP_CODE_LEN: usize = 10;
+/// No 0/O or 1/I/L so codes survive being read aloud or written down
+const BACKUP_CODE_ALPHABET: &[u8] = b"23456789ABCDEFGHJKMNPQRSTUVWXYZ";
+
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+pub fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; TOTP_SECRET_LEN];
+    rand::rng().fill_bytes(&mut secret);
+    secret
+}
+
+/// Unpadded RFC 4648 base32, the encoding authenticator apps expect for secrets
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(5) * 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// `otpauth://` URI for QR codes, see the Key Uri Format used by authenticator apps
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &[u8]) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        uri_encode(issuer),
+        uri_encode(account),
+        base32_encode(secret),
+        uri_encode(issuer),
+        TOTP_DIGITS,
+        TOTP_STEP_SECS
+    )
+}
+
+/// Time step containing `unix_secs`
+pub fn time_step(unix_secs: u64) -> u64 {
+    unix_secs / TOTP_STEP_SECS
+}
+
+/// HOTP value (RFC 4226) for `step`
+pub fn code_at_step(secret: &[u8], step: u64) -> u32 {
+    let mut mac =
+        <Hmac<Sha1> as Mac>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    binary % 10u32.pow(TOTP_DIGITS)
+}
+
+/// Checks `code` against the steps around `unix_secs`. Returns the matching step so the
+/// caller can refuse it a second time, None if the code is wrong.
+pub fn verify_code(secret: &[u8], code: &str, unix_secs: u64) -> Option<u64> {
+    let code = code.trim();
+    if code.len() != TOTP_DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let current = time_step(unix_secs);
+    let first = current.saturating_sub(TOTP_SKEW_STEPS);
+    (first..=current + TOTP_SKEW_STEPS).find(|step| {
+        let expected = format!(
+            "{:0width$}",
+            code_at_step(secret, *step),
+            width = TOTP_DIGITS as usize
+        );
+        bool::from(expected.as_bytes().ct_eq(code.as_bytes()))
+    })
+}
+
+/// Where accepted time steps and used backup codes are recorded, the database on the
+/// server. Both checks have to be atomic, they are what stops replays.
+pub trait TotpStore {
+    type Error;
+
+    /// Records a code for `step` as accepted, false if one for this or a later step was
+    /// accepted before
+    fn accept_step(&mut self, user_id: u64, step: u64) -> Result<bool, Self::Error>;
+
+    /// Marks the backup code with `hash` as used, false if there is none or it was used
+    fn use_backup_code(
+        &mut self,
+        user_id: u64,
+        hash: &[u8],
+        now: DateTime<Utc>,
+    ) -> Result<bool, Self::Error>;
+}
+
+/// Checks a TOTP code at `clock`'s time, or a backup code once the secret is `confirmed`.
+/// Each time step and each backup code is accepted once.
+pub fn check_code<S: TotpStore>(
+    store: &mut S,
+    clock: &dyn Clock,
+    user_id: u64,
+    secret: &[u8],
+    confirmed: bool,
+    code: &str,
+) -> Result<bool, S::Error> {
+    let now = clock.now();
+    if let Some(step) = verify_code(secret, code, now.timestamp().max(0) as u64) {
+        return store.accept_step(user_id, step);
+    }
+    if confirmed {
+        return store.use_backup_code(user_id, &hash_backup_code(code), now);
+    }
+    Ok(false)
+}
+
+/// Single use codes for when the authenticator is lost, shown to the user once
+pub fn generate_backup_codes() -> Vec<String> {
+    let mut rng = rand::rng();
+    (0..BACKUP_CODE_COUNT)
+        .map(|_| {
+            (0..BACKUP_CODE_LEN)
+                .map(|_| {
+                    BACKUP_CODE_ALPHABET[rng.random_range(0..BACKUP_CODE_ALPHABET.len())] as char
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// What is stored for a backup code. Codes are random enough that a plain hash is fine.
+/// Case and separators are ignored so "abcd-efgh-ij" matches "ABCDEFGHIJ".
+pub fn hash_backup_code(code: &str) -> Vec<u8> {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    Sha256::digest(normalized.as_bytes()).to_vec()
+}
+
+/// Encrypts a TOTP secret for `totp_secrets`, bound to `user_id` so rows can't be swapped.
+/// Returns the ciphertext and the nonce.
+pub fn encrypt_secret(key: &[u8; 32], user_id: u64, secret: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let cipher = Aes256Gcm::new_from_slice(key).unwrap();
+    let mut nonce = [0u8; 12];
+    rand::rng().fill_bytes(&mut nonce);
+    let aad = user_id.to_be_bytes();
+    let ciphertext = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: secret,
+                aad: &aad,
+            },
+        )
+        .expect("AES-GCM encryption failed");
+    (ciphertext, nonce.to_vec())
+}
+
+pub fn decrypt_secret(
+    key: &[u8; 32],
+    user_id: u64,
+    ciphertext: &[u8],
+    nonce: &[u8],
+) -> Option<Vec<u8>> {
+    if nonce.len() != 12 {
+        return None;
+    }
+    let cipher = Aes256Gcm::new_from_slice(key).unwrap();
+    let aad = user_id.to_be_bytes();
+    cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: &aad,
+            },
+        )
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::clock::ManualClock;
+    use chrono::{Duration, TimeZone};
+    use std::collections::{HashMap, HashSet};
+
+    /// The SHA-1 seed of RFC 6238 appendix B
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+    const USER: u64 = 7;
+
+    #[derive(Default)]
+    struct MemoryStore {
+        last_used_step: HashMap<u64, u64>,
+        backup_codes: HashSet<Vec<u8>>,
+    }
+
+    impl TotpStore for MemoryStore {
+        type Error = ();
+
+        fn accept_step(&mut self, user_id: u64, step: u64) -> Result<bool, ()> {
+            if self.last_used_step.get(&user_id).is_some_and(|last| *last >= step) {
+                return Ok(false);
+            }
+            self.last_used_step.insert(user_id, step);
+            Ok(true)
+        }
+
+        fn use_backup_code(
+            &mut self,
+            _user_id: u64,
+            hash: &[u8],
+            _now: DateTime<Utc>,
+        ) -> Result<bool, ()> {
+            Ok(self.backup_codes.remove(hash))
+        }
+    }
+
+    fn clock_at(unix_secs: i64) -> ManualClock {
+        ManualClock::new(Utc.timestamp_opt(unix_secs, 0).unwrap())
+    }
+
+    fn code_at(unix_secs: u64) -> String {
+        format!("{:06}", code_at_step(RFC_SECRET, time_step(unix_secs)))
+    }
+
+    #[test]
+    fn rfc_6238_sha1_vectors() {
+        // The RFC lists 8 digit codes, these are their last 6 digits
+        let vectors = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ];
+        for (unix_secs, expected) in vectors {
+            assert_eq!(code_at(unix_secs), expected, "at {}", unix_secs);
+            assert_eq!(
+                verify_code(RFC_SECRET, expected, unix_secs),
+                Some(time_step(unix_secs))
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_one_step_of_skew_either_way() {
+        let code = code_at(1234567890);
+        let clock = clock_at(1234567890);
+
+        clock.advance(Duration::seconds(TOTP_STEP_SECS as i64));
+        let mut store = MemoryStore::default();
+        assert_eq!(check_code(&mut store, &clock, USER, RFC_SECRET, true, &code), Ok(true));
+
+        clock.set(Utc.timestamp_opt(1234567890 - TOTP_STEP_SECS as i64, 0).unwrap());
+        let mut store = MemoryStore::default();
+        assert_eq!(check_code(&mut store, &clock, USER, RFC_SECRET, true, &code), Ok(true));
+
+        clock.set(Utc.timestamp_opt(1234567890 + 2 * TOTP_STEP_SECS as i64, 0).unwrap());
+        let mut store = MemoryStore::default();
+        assert_eq!(check_code(&mut store, &clock, USER, RFC_SECRET, true, &code), Ok(false));
+    }
+
+    #[test]
+    fn refuses_a_replayed_step() {
+        let clock = clock_at(1111111111);
+        let mut store = MemoryStore::default();
+        let code = code_at(1111111111);
+
+        assert_eq!(check_code(&mut store, &clock, USER, RFC_SECRET, true, &code), Ok(true));
+        assert_eq!(check_code(&mut store, &clock, USER, RFC_SECRET, true, &code), Ok(false));
+
+        // A code of an earlier step is a replay too, even inside the skew window
+        clock.advance(Duration::seconds(TOTP_STEP_SECS as i64));
+        assert_eq!(check_code(&mut store, &clock, USER, RFC_SECRET, true, &code), Ok(false));
+        let next = code_at(1111111111 + TOTP_STEP_SECS);
+        assert_eq!(check_code(&mut store, &clock, USER, RFC_SECRET, true, &next), Ok(true));
+    }
+
+    #[test]
+    fn backup_codes_work_once() {
+        let clock = clock_at(2000000000);
+        let mut store = MemoryStore::default();
+        let code = "ABCD-EFGH-JK";
+        store.backup_codes.insert(hash_backup_code(code));
+
+        // Not before the authenticator is confirmed
+        assert_eq!(check_code(&mut store, &clock, USER, RFC_SECRET, false, code), Ok(false));
+
+        assert_eq!(
+            check_code(&mut store, &clock, USER, RFC_SECRET, true, "abcdefghjk"),
+            Ok(true)
+        );
+        assert_eq!(check_code(&mut store, &clock, USER, RFC_SECRET, true, code), Ok(false));
+    }
+}